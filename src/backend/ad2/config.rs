@@ -0,0 +1,178 @@
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::backend::SweepRange;
+use crate::error::TracerError;
+use crate::Result;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct AD2Config {
+    pub diode_current_limit_ma: f64,
+    pub current_shunt_ohms: f64,
+    pub bias_limiter_ohms: f64,
+    pub sampling_time: f64,
+    pub capture_offset_stabilization_time: f64,
+    pub bias_level_sampling_time: f64,
+    pub sampling_frequency: f64,
+    pub cycles_to_sample: u32,
+    pub cycles_to_skip: u32,
+    pub max_v: f64,
+    pub min_v: f64,
+}
+
+impl Default for AD2Config {
+    fn default() -> Self {
+        AD2Config {
+            diode_current_limit_ma: 40.0,
+            current_shunt_ohms: 101.0,
+            bias_limiter_ohms: 100_000.0,
+            sampling_time: 0.5,
+            capture_offset_stabilization_time: 0.5,
+            bias_level_sampling_time: 0.3,
+            sampling_frequency: 500_000.0,
+            cycles_to_sample: 5,
+            cycles_to_skip: 1,
+            max_v: 2.2,
+            min_v: -2.2,
+        }
+    }
+}
+
+fn check_range<T: PartialOrd + Display>(name: &str, value: T, min: T, max: T) -> Result<()> {
+    if value < min || value > max {
+        Err(TracerError::invalid_config(&format!(
+            "{} must be within [{}, {}], got {}",
+            name, min, max, value
+        ))
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+impl AD2Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let config: AD2Config =
+            toml::from_str(&std::fs::read_to_string(path).map_err(|e| TracerError::io(path, e))?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_toml()?).map_err(|e| TracerError::io(path, e))?;
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Peak voltage of the sweep, enough to reach the current limit with the DUT shorted
+    pub fn sweep_max_v(&self, polarity: f64) -> f64 {
+        (self.diode_current_limit_ma / 1000.0 * self.current_shunt_ohms + 0.5 * polarity)
+            .min(self.max_v)
+            .max(self.min_v)
+    }
+
+    /// Lowest and highest voltages of a two-terminal sweep over `range`
+    pub fn sweep_limits(&self, range: SweepRange) -> (f64, f64) {
+        let reach = self.diode_current_limit_ma / 1000.0 * self.current_shunt_ohms + 0.5;
+        let lo = if range.covers_negative() {
+            (-reach).max(self.min_v).min(0.0)
+        } else {
+            0.0
+        };
+        let hi = if range.covers_positive() {
+            self.sweep_max_v(1.0)
+        } else {
+            0.0
+        };
+        (lo, hi)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_range(
+            "diode_current_limit_ma",
+            self.diode_current_limit_ma,
+            1.0,
+            100.0,
+        )?;
+        check_range("current_shunt_ohms", self.current_shunt_ohms, 1.0, 10_000.0)?;
+        check_range(
+            "bias_limiter_ohms",
+            self.bias_limiter_ohms,
+            1_000.0,
+            10_000_000.0,
+        )?;
+        check_range("sampling_time", self.sampling_time, 0.01, 10.0)?;
+        check_range(
+            "capture_offset_stabilization_time",
+            self.capture_offset_stabilization_time,
+            0.0,
+            5.0,
+        )?;
+        check_range(
+            "bias_level_sampling_time",
+            self.bias_level_sampling_time,
+            0.01,
+            10.0,
+        )?;
+        check_range(
+            "sampling_frequency",
+            self.sampling_frequency,
+            1_000.0,
+            100_000_000.0,
+        )?;
+        check_range("cycles_to_sample", self.cycles_to_sample, 1, 100)?;
+        check_range("cycles_to_skip", self.cycles_to_skip, 0, 100)?;
+        check_range("max_v", self.max_v, -5.0, 5.0)?;
+        check_range("min_v", self.min_v, -5.0, 5.0)?;
+        if self.min_v >= self.max_v {
+            return Err(TracerError::invalid_config(&format!(
+                "min_v must be below max_v, got [{}, {}]",
+                self.min_v, self.max_v
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backend::AD2Config;
+
+    #[test]
+    pub fn default_config_is_valid() {
+        assert!(AD2Config::default().validate().is_ok());
+    }
+
+    #[test]
+    pub fn partial_toml_falls_back_to_defaults() {
+        let config: AD2Config = toml::from_str("current_shunt_ohms = 47.0").unwrap();
+
+        assert_eq!(
+            config,
+            AD2Config {
+                current_shunt_ohms: 47.0,
+                ..AD2Config::default()
+            }
+        );
+    }
+
+    #[test]
+    pub fn out_of_range_values_are_rejected() {
+        let config = AD2Config {
+            max_v: 7.0,
+            ..AD2Config::default()
+        };
+
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "max_v must be within [-5, 5], got 7"
+        );
+    }
+}
@@ -0,0 +1,491 @@
+use digilent_waveforms::*;
+use itertools::Itertools;
+use noisy_float::prelude::*;
+use time::Duration;
+
+pub use self::config::AD2Config;
+pub use self::selection::{AD2ConfigInfo, AD2DeviceInfo, DeviceSelection, DeviceSelector};
+
+use crate::backend::Backend;
+use crate::backend::BiasedTrace;
+use crate::backend::RawTrace;
+use crate::backend::SweepRange;
+use crate::backend::{Calibration, CalibrationProfiles, CALIBRATION_SWEEP};
+use crate::backend::{Progress, TraceControl};
+use crate::dut::BiasDrive;
+use crate::error::TracerError;
+
+mod config;
+mod selection;
+
+/// Share of lost and corrupted samples beyond which a trace is rejected
+const MAX_LOST_FRACTION: f64 = 0.1;
+
+fn device_infos(devices: &DeviceInfoList) -> Vec<AD2DeviceInfo> {
+    devices
+        .devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| AD2DeviceInfo {
+            index,
+            name: device.name.clone(),
+            serial: device.serial.clone(),
+            in_use: device.in_use,
+            configs: device
+                .configs
+                .iter()
+                .map(|config| AD2ConfigInfo {
+                    analog_in_buffer_size: config.analog_in_buf_size,
+                    analog_out_buffer_size: config.analog_out_buf_size,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+pub fn list_devices() -> crate::Result<Vec<AD2DeviceInfo>> {
+    Ok(device_infos(&devices()?))
+}
+
+pub struct AD2 {
+    device: Device,
+    config: AD2Config,
+    serial: String,
+    calibration: Option<Calibration>,
+}
+
+impl AD2 {
+    pub fn new(selection: &DeviceSelection, config: AD2Config) -> crate::Result<Self> {
+        config.validate()?;
+        // Open the device from the enumeration it was picked from, as indices may change
+        // between enumerations when devices are plugged in or out
+        let devices = devices()?;
+        let (device_ix, config_ix) = selection.resolve(&device_infos(&devices))?;
+        let (serial, device_config) = devices
+            .devices
+            .get(device_ix)
+            .and_then(|device| Some((device.serial.clone(), device.configs.get(config_ix)?)))
+            .ok_or_else(|| TracerError::NoDevice {
+                message: format!("No device #{} config #{} found", device_ix, config_ix),
+            })?;
+
+        let calibration = match CalibrationProfiles::load()?.get(&serial) {
+            None => {
+                info!("No calibration for {}, using raw readings", serial);
+                None
+            }
+            Some(calibration)
+                if (calibration.nominal_shunt_ohms - config.current_shunt_ohms).abs() > 1e-9 =>
+            {
+                warn!(
+                    "Calibration for {} was done with a {}Ω shunt, not {}Ω, ignoring it",
+                    serial, calibration.nominal_shunt_ohms, config.current_shunt_ohms
+                );
+                None
+            }
+            Some(calibration) => Some(calibration.clone()),
+        };
+
+        Ok(AD2 {
+            device: device_config.open()?,
+            config,
+            serial,
+            calibration,
+        })
+    }
+
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    pub fn config(&self) -> &AD2Config {
+        &self.config
+    }
+
+    /// Drops the calibration, for capturing calibration fixtures
+    pub fn uncalibrated(self) -> Self {
+        AD2 {
+            calibration: None,
+            ..self
+        }
+    }
+
+    /// Corrects a trace whose input channels were set up as for a sweep over `range`, unless
+    /// that isn't the sweep the calibration was done over
+    fn calibrate(&self, trace: RawTrace, range: SweepRange) -> RawTrace {
+        match &self.calibration {
+            Some(calibration) if range == CALIBRATION_SWEEP => calibration.apply(&trace),
+            Some(_) => {
+                debug!(
+                    "The calibration holds for {} sweeps, using raw readings over a {} one",
+                    CALIBRATION_SWEEP, range
+                );
+                trace
+            }
+            None => trace,
+        }
+    }
+
+    fn enable_power(&self) -> crate::Result<()> {
+        let ps = self.device.analog_io();
+        let v_pos = ps.channel(0);
+        v_pos.node(1).set_value(5.0)?;
+        v_pos.node(0).set_value(1.0)?;
+
+        let v_neg = ps.channel(1);
+        v_neg.node(1).set_value(-5.0)?;
+        v_neg.node(0).set_value(1.0)?;
+
+        ps.set_enabled(true)?;
+
+        Ok(())
+    }
+
+    fn disable_power(&self) -> crate::Result<()> {
+        let ps = self.device.analog_io();
+        let v_pos = ps.channel(0);
+        v_pos.node(1).set_value(0.0)?;
+        v_pos.node(0).set_value(0.0)?;
+
+        let v_neg = ps.channel(1);
+        v_neg.node(1).set_value(0.0)?;
+        v_neg.node(0).set_value(0.0)?;
+
+        ps.set_enabled(false)?;
+
+        Ok(())
+    }
+
+    /// Runs `capture` with the power supplies on, stopping the outputs and
+    /// turning the power off however it ends
+    fn powered<T, F: FnOnce() -> crate::Result<T>>(&self, capture: F) -> crate::Result<T> {
+        let res = self.enable_power().and_then(|_| capture());
+        if res.is_err() {
+            for ix in 0..2 {
+                if let Err(err) = self.device.analog_out(ix).stop() {
+                    warn!("Can't stop analog output {}: {}", ix, err);
+                }
+            }
+        }
+        let disabled = self.disable_power();
+        let value = res?;
+        disabled?;
+        Ok(value)
+    }
+
+    /// `on_samples` gets the number of samples recorded so far after every
+    /// fetch, an error from it aborts the recording
+    fn record_raw(
+        input: &AnalogIn<'_>,
+        in1: &AnalogInChannel<'_>,
+        dst1: &mut Vec<f64>,
+        in2: &AnalogInChannel<'_>,
+        dst2: &mut Vec<f64>,
+        on_samples: &mut dyn FnMut(usize) -> crate::Result<()>,
+    ) -> crate::Result<()> {
+        let mut total_lost = 0;
+        let mut total_corrupted = 0;
+
+        loop {
+            on_samples(dst1.len())?;
+
+            let status = input.get_status()?;
+            if status == AnalogAcquisitionStatus::Config
+                || status == AnalogAcquisitionStatus::Prefill
+                || status == AnalogAcquisitionStatus::Armed
+            {
+                std::thread::yield_now();
+                continue;
+            }
+            if status == AnalogAcquisitionStatus::Done {
+                break;
+            }
+
+            let left = input.get_samples_left()?;
+            if left < 0 {
+                break;
+            }
+            let (available, lost, corrupted) = input.get_record_status()?;
+            total_lost += lost;
+            total_corrupted += corrupted;
+
+            if lost > 0 {
+                dst1.extend(itertools::repeat_n(std::f64::NAN, lost as usize));
+                dst2.extend(itertools::repeat_n(std::f64::NAN, lost as usize));
+            }
+            if available > 0 {
+                in1.fetch_samples(dst1, available)?;
+                in2.fetch_samples(dst2, available)?;
+            }
+        }
+
+        if total_lost > 0 || total_corrupted > 0 {
+            let total = dst1.len();
+            if (total_lost + total_corrupted) as f64 > MAX_LOST_FRACTION * total as f64 {
+                return Err(TracerError::SamplesLost {
+                    lost: total_lost as usize,
+                    corrupted: total_corrupted as usize,
+                    total,
+                }
+                .into());
+            }
+            warn!(
+                "Lost {} sample(-s), got {} corrupted sample(-s)",
+                total_lost, total_corrupted
+            );
+        }
+        Ok(())
+    }
+}
+
+impl AD2 {
+    fn capture_2(&self, range: SweepRange, control: &TraceControl) -> crate::Result<RawTrace> {
+        let hz = f64::from(self.config.cycles_to_sample + self.config.cycles_to_skip)
+            / self.config.sampling_time;
+        let (min_v, max_v) = self.config.sweep_limits(range);
+        let time_slack = 0.05;
+        let total_time = self.config.sampling_time + time_slack;
+
+        let out_vf = self.device.analog_out(0);
+        out_vf.set_idle_mode(AnalogOutIdleMode::Initial)?;
+        out_vf.set_trigger_source(TriggerSource::AnalogIn)?;
+        let out_vf_carrier = out_vf.node(0);
+
+        debug!(
+            "Setting up a triangle waveform between A and K: [{}, {}] at {} Hz",
+            min_v, max_v, hz
+        );
+        out_vf_carrier.set_function(AnalogOutFunction::Triangle {
+            frequency: hz,
+            amplitude: (max_v - min_v) / 2.0,
+            offset: (max_v + min_v) / 2.0,
+            symmetry: 50.0,
+            phase_deg: 270.0,
+        })?;
+        out_vf_carrier.set_enabled(true)?;
+        out_vf.set_duration(Duration::nanoseconds((total_time * 1.0e9) as i64))?;
+        out_vf.set_repeat_count(0)?;
+
+        out_vf.start()?;
+
+        let input = self.device.analog_input();
+        input.set_frequency(self.config.sampling_frequency)?;
+        input.set_record_mode(self.config.sampling_time)?;
+
+        // A reverse-biased DUT blocks, so the whole negative part of the sweep ends up across it,
+        // while the forward voltage stays within a volt
+        let in_v_shunt = input.channel(0);
+        let in_v = input.channel(1);
+        if range.covers_negative() {
+            in_v_shunt.set_offset((max_v + min_v) / 2.0)?;
+            in_v_shunt.set_range(10.0_f64.max(max_v - min_v))?;
+            let in_v_max = max_v.min(1.0);
+            in_v.set_offset((in_v_max + min_v) / 2.0)?;
+            in_v.set_range(in_v_max - min_v)?;
+        } else {
+            in_v_shunt.set_offset(2.0)?;
+            in_v_shunt.set_range(10.0)?;
+            in_v.set_offset(-0.5)?;
+            in_v.set_range(1.0)?;
+        }
+
+        std::thread::sleep(
+            Duration::nanoseconds((self.config.capture_offset_stabilization_time * 1.0e9) as i64)
+                .to_std()?,
+        );
+
+        input.start()?;
+
+        let expected_samples = self.config.sampling_time * self.config.sampling_frequency;
+        let mut vs = Vec::new();
+        let mut vss = Vec::new();
+        {
+            debug_time!("Tracing");
+            Self::record_raw(
+                &input,
+                &in_v,
+                &mut vs,
+                &in_v_shunt,
+                &mut vss,
+                &mut |samples| {
+                    control.report(Progress::new(None, samples as f64 / expected_samples));
+                    control.check()
+                },
+            )?;
+        }
+        out_vf.stop()?;
+
+        let start_ix = (vs.len() as f64 * self.config.cycles_to_skip as f64
+            / (self.config.cycles_to_skip + self.config.cycles_to_sample) as f64)
+            as usize;
+        let is = vss
+            .into_iter()
+            .skip(start_ix)
+            .map(|v_s| v_s / self.config.current_shunt_ohms)
+            .collect_vec();
+        Ok(self.calibrate(RawTrace::new(is, vs.split_off(start_ix)), range))
+    }
+
+    fn capture_3(
+        &self,
+        polarity: R64,
+        bias_drive: BiasDrive,
+        bias_levels: Vec<R64>,
+        control: &TraceControl,
+    ) -> crate::Result<Vec<BiasedTrace>> {
+        let bias_factor = match bias_drive {
+            BiasDrive::Voltage => 1.0,
+            BiasDrive::Current => self.config.bias_limiter_ohms,
+        };
+        let bias_levels = bias_levels
+            .into_iter()
+            .map(|l| (l, l * bias_factor))
+            .collect_vec();
+
+        let hz = f64::from(self.config.cycles_to_sample + self.config.cycles_to_skip)
+            / self.config.bias_level_sampling_time;
+        let max_v = self.config.sweep_max_v(polarity.raw());
+        let time_slack = 0.05;
+        let total_time = self.config.bias_level_sampling_time + time_slack;
+
+        let out_vf = self.device.analog_out(0);
+        out_vf.set_idle_mode(AnalogOutIdleMode::Initial)?;
+        out_vf.set_trigger_source(TriggerSource::AnalogIn)?;
+        let out_vf_carrier = out_vf.node(0);
+
+        debug!(
+            "Setting up a triangle waveform between C/D and E/S: [{}, {}] at {} Hz",
+            0.0, max_v, hz
+        );
+        out_vf_carrier.set_function(AnalogOutFunction::Triangle {
+            frequency: hz,
+            amplitude: max_v / 2.0,
+            offset: max_v / 2.0,
+            symmetry: 50.0,
+            phase_deg: 270.0,
+        })?;
+        out_vf_carrier.set_enabled(true)?;
+        out_vf.set_duration(Duration::nanoseconds((total_time * 1.0e9) as i64))?;
+        out_vf.set_repeat_count(0)?;
+
+        let out_bias = self.device.analog_out(1);
+        out_bias.set_idle_mode(AnalogOutIdleMode::Initial)?;
+        out_bias.set_trigger_source(TriggerSource::AnalogIn)?;
+        let out_bias_carrier = out_bias.node(0);
+
+        out_bias_carrier.set_function(AnalogOutFunction::Const { offset: 0.0 })?;
+        out_bias_carrier.set_enabled(true)?;
+        out_bias.set_duration(Duration::nanoseconds((total_time * 1.0e9) as i64))?;
+        out_bias.set_repeat_count(0)?;
+
+        let input = self.device.analog_input();
+        input.set_frequency(self.config.sampling_frequency)?;
+        input.set_record_mode(self.config.bias_level_sampling_time)?;
+
+        let in_v_shunt = input.channel(0);
+        in_v_shunt.set_offset(2.0)?;
+        in_v_shunt.set_range(10.0)?;
+
+        let in_v = input.channel(1);
+        in_v.set_offset(-0.5)?;
+        in_v.set_range(1.0)?;
+
+        std::thread::sleep(
+            Duration::nanoseconds((self.config.capture_offset_stabilization_time * 1.0e9) as i64)
+                .to_std()?,
+        );
+
+        let mut traces = vec![];
+        let count = bias_levels.len();
+        let expected_samples =
+            self.config.bias_level_sampling_time * self.config.sampling_frequency;
+
+        for (ix, (bias_value, bias_v)) in bias_levels.into_iter().enumerate() {
+            control.check()?;
+            debug_time!("Tracing at a single bias level");
+            debug!("Setting up bias voltage at B/G: {}", bias_v);
+
+            out_bias_carrier.set_function(AnalogOutFunction::Const {
+                offset: bias_v.raw(),
+            })?;
+
+            out_vf.start()?;
+            std::thread::sleep(Duration::nanoseconds((time_slack * 2.0 * 1.0e9) as i64).to_std()?);
+
+            input.start()?;
+
+            let mut vs = Vec::new();
+            let mut vss = Vec::new();
+            {
+                debug_time!("Recording");
+                Self::record_raw(
+                    &input,
+                    &in_v,
+                    &mut vs,
+                    &in_v_shunt,
+                    &mut vss,
+                    &mut |samples| {
+                        control.report(Progress::new(
+                            Some((ix, count)),
+                            samples as f64 / expected_samples,
+                        ));
+                        control.check()
+                    },
+                )?;
+            }
+
+            out_vf.stop()?;
+
+            let start_ix = (vs.len() as f64 * self.config.cycles_to_skip as f64
+                / (self.config.cycles_to_skip + self.config.cycles_to_sample) as f64)
+                as usize;
+            let is = vss
+                .into_iter()
+                .skip(start_ix)
+                .map(|v_s| v_s / self.config.current_shunt_ohms)
+                .collect_vec();
+            // The input channels are set up as for a positive two-terminal sweep
+            let trace = self.calibrate(
+                RawTrace::new(is, vs.split_off(start_ix)),
+                SweepRange::Positive,
+            );
+            let is = trace.iter().map(|(_, i)| i).collect_vec();
+            let vs = trace.iter().map(|(v, _)| v * polarity.raw()).collect_vec();
+            traces.push(BiasedTrace {
+                bias: bias_value,
+                trace: RawTrace::new(is, vs),
+            })
+        }
+
+        Ok(traces)
+    }
+
+    fn reset(&self) -> crate::Result<()> {
+        self.device.reset()?;
+        self.device.set_auto_configure(true)?;
+        self.device.set_enabled(true)?;
+        Ok(())
+    }
+}
+
+impl Backend for AD2 {
+    fn capture_config(&self) -> Option<AD2Config> {
+        Some(self.config.clone())
+    }
+
+    fn trace_2(&self, range: SweepRange, control: &TraceControl) -> crate::Result<RawTrace> {
+        self.reset()?;
+        self.powered(|| self.capture_2(range, control))
+    }
+
+    fn trace_3(
+        &self,
+        polarity: R64,
+        bias_drive: BiasDrive,
+        bias_levels: Vec<R64>,
+        control: &TraceControl,
+    ) -> crate::Result<Vec<BiasedTrace>> {
+        self.reset()?;
+        self.powered(|| self.capture_3(polarity, bias_drive, bias_levels, control))
+    }
+}
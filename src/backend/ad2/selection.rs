@@ -0,0 +1,281 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use crate::error::TracerError;
+use crate::util::config_path;
+use crate::Result;
+
+const SELECTION_FILE: &str = "device.toml";
+
+#[derive(Clone, Debug)]
+pub struct AD2ConfigInfo {
+    pub analog_in_buffer_size: i32,
+    pub analog_out_buffer_size: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct AD2DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub serial: String,
+    pub in_use: bool,
+    pub configs: Vec<AD2ConfigInfo>,
+}
+
+impl Display for AD2DeviceInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}: {} {}", self.index, self.name, self.serial)?;
+        if self.in_use {
+            write!(f, " (in use)")?;
+        }
+        for (ix, config) in self.configs.iter().enumerate() {
+            write!(
+                f,
+                "\n    config #{}: analog in buffer {}, analog out buffer {}",
+                ix, config.analog_in_buffer_size, config.analog_out_buffer_size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A device picked either by its position in the enumeration, written `#n`, or by its serial
+/// number, with or without the `SN:` prefix
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(into = "String", try_from = "String")]
+pub enum DeviceSelector {
+    Index(usize),
+    Serial(String),
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, device: &AD2DeviceInfo) -> bool {
+        let strip = |serial: &str| serial.trim_start_matches("SN:").to_ascii_uppercase();
+        match self {
+            DeviceSelector::Index(ix) => device.index == *ix,
+            DeviceSelector::Serial(serial) => strip(serial) == strip(&device.serial),
+        }
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            Err(
+                TracerError::invalid_argument("device serial number or index can't be empty")
+                    .into(),
+            )
+        } else if s.starts_with('#') {
+            let ix = s[1..]
+                .parse()
+                .map_err(|_| TracerError::invalid_argument(&format!("Bad device index {}", s)))?;
+            Ok(DeviceSelector::Index(ix))
+        } else {
+            Ok(DeviceSelector::Serial(s.to_string()))
+        }
+    }
+}
+
+impl TryFrom<String> for DeviceSelector {
+    type Error = failure::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<DeviceSelector> for String {
+    fn from(selector: DeviceSelector) -> Self {
+        selector.to_string()
+    }
+}
+
+impl Display for DeviceSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceSelector::Index(ix) => write!(f, "#{}", ix),
+            DeviceSelector::Serial(serial) => write!(f, "{}", serial),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct DeviceSelection {
+    /// `None` picks the first device that isn't in use
+    pub device: Option<DeviceSelector>,
+    pub config: usize,
+}
+
+impl DeviceSelection {
+    fn path() -> Result<PathBuf> {
+        config_path(SELECTION_FILE)
+    }
+
+    /// The last choice made in the device picker, or the default one
+    pub fn remembered() -> Self {
+        Self::path()
+            .and_then(|path| {
+                if path.exists() {
+                    Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+                } else {
+                    Ok(Self::default())
+                }
+            })
+            .unwrap_or_else(|e| {
+                warn!("Can't read the remembered device choice: {}", e);
+                Self::default()
+            })
+    }
+
+    pub fn remember(&self) -> Result<()> {
+        std::fs::write(Self::path()?, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Returns the device and config indices to open
+    pub fn resolve(&self, devices: &[AD2DeviceInfo]) -> Result<(usize, usize)> {
+        if devices.is_empty() {
+            return Err(TracerError::NoDevice {
+                message: "No devices found".to_string(),
+            }
+            .into());
+        }
+
+        let device = match &self.device {
+            Some(selector) => devices
+                .iter()
+                .find(|device| selector.matches(device))
+                .ok_or_else(|| TracerError::NoDevice {
+                    message: format!(
+                        "No device {} found, available devices: {}",
+                        selector,
+                        devices
+                            .iter()
+                            .map(|d| format!("#{} {}", d.index, d.serial))
+                            .join(", ")
+                    ),
+                })?,
+            None => devices
+                .iter()
+                .find(|device| !device.in_use)
+                .unwrap_or(&devices[0]),
+        };
+
+        if device.in_use {
+            return Err(TracerError::DeviceBusy {
+                name: device.name.clone(),
+                serial: device.serial.clone(),
+            }
+            .into());
+        }
+        if self.config >= device.configs.len() {
+            return Err(TracerError::NoDevice {
+                message: format!(
+                    "Device {} {} has no config #{}, it has {} config(-s)",
+                    device.name,
+                    device.serial,
+                    self.config,
+                    device.configs.len()
+                ),
+            }
+            .into());
+        }
+
+        Ok((device.index, self.config))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backend::{AD2ConfigInfo, AD2DeviceInfo, DeviceSelection, DeviceSelector};
+
+    fn devices() -> Vec<AD2DeviceInfo> {
+        let device = |index: usize, serial: &str, in_use: bool| AD2DeviceInfo {
+            index,
+            name: "Analog Discovery 2".to_string(),
+            serial: serial.to_string(),
+            in_use,
+            configs: vec![AD2ConfigInfo {
+                analog_in_buffer_size: 8192,
+                analog_out_buffer_size: 4096,
+            }],
+        };
+        vec![
+            device(0, "SN:210321A1B2C3", true),
+            device(1, "SN:210321D4E5F6", false),
+        ]
+    }
+
+    #[test]
+    pub fn selector_parses_index_or_serial() {
+        assert_eq!(
+            "#1".parse::<DeviceSelector>().unwrap(),
+            DeviceSelector::Index(1)
+        );
+        assert_eq!(
+            "210321".parse::<DeviceSelector>().unwrap(),
+            DeviceSelector::Serial("210321".to_string())
+        );
+        assert!("#one".parse::<DeviceSelector>().is_err());
+        assert_eq!(
+            "SN:210321D4E5F6".parse::<DeviceSelector>().unwrap(),
+            DeviceSelector::Serial("SN:210321D4E5F6".to_string())
+        );
+    }
+
+    #[test]
+    pub fn default_selection_skips_busy_devices() {
+        assert_eq!(
+            DeviceSelection::default().resolve(&devices()).unwrap(),
+            (1, 0)
+        );
+    }
+
+    #[test]
+    pub fn serial_matches_without_prefix() {
+        let selection = DeviceSelection {
+            device: Some(DeviceSelector::Serial("210321d4e5f6".to_string())),
+            config: 0,
+        };
+
+        assert_eq!(selection.resolve(&devices()).unwrap(), (1, 0));
+    }
+
+    #[test]
+    pub fn missing_or_busy_device_is_an_error() {
+        let select = |device: DeviceSelector, config: usize| {
+            DeviceSelection {
+                device: Some(device),
+                config,
+            }
+            .resolve(&devices())
+            .unwrap_err()
+            .to_string()
+        };
+
+        assert_eq!(
+            select(DeviceSelector::Index(0), 0),
+            "Device Analog Discovery 2 SN:210321A1B2C3 is in use by another application"
+        );
+        assert_eq!(
+            select(DeviceSelector::Serial("SN:0000".to_string()), 0),
+            "No device SN:0000 found, available devices: #0 SN:210321A1B2C3, #1 SN:210321D4E5F6"
+        );
+        assert_eq!(
+            select(DeviceSelector::Index(2), 0),
+            "No device #2 found, available devices: #0 SN:210321A1B2C3, #1 SN:210321D4E5F6"
+        );
+        assert_eq!(
+            select(DeviceSelector::Index(1), 2),
+            "Device Analog Discovery 2 SN:210321D4E5F6 has no config #2, it has 1 config(-s)"
+        );
+    }
+}
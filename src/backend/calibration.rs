@@ -0,0 +1,256 @@
+use std::collections::btree_map::BTreeMap;
+use std::path::PathBuf;
+
+use itertools::Itertools;
+use nalgebra::*;
+
+use crate::backend::{AD2Config, RawTrace, SweepRange};
+use crate::error::TracerError;
+use crate::model::curvefit::linear_regression;
+use crate::util::config_path;
+use crate::Result;
+
+const PROFILES_FILE: &str = "calibration.toml";
+
+/// Sweep the fixtures are traced over. The input channels are set up with other offsets and
+/// ranges for sweeps that go negative, whose errors the calibration doesn't tell.
+pub const CALIBRATION_SWEEP: SweepRange = SweepRange::Positive;
+
+/// Corrections derived from an open and a shorted (or known resistor) fixture.
+///
+/// The voltage channel is the reference for the current path, so the gain error of the shunt
+/// channel can't be told apart from the shunt tolerance and ends up in `shunt_ohms`. Only a
+/// known resistor measures it: a short carries no voltage to compare the current with, so it
+/// calibrates the offsets and the voltage gain and leaves `shunt_ohms` at its nominal value.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Calibration {
+    pub voltage_offset: f64,
+    /// Recorded voltage per volt across the DUT, taking the AWG amplitude as exact: the
+    /// amplitude error of the AWG, a percent or so, ends up in it
+    pub voltage_gain: f64,
+    pub current_offset: f64,
+    /// Current through an open fixture per volt, mostly the input impedance of the voltage channel
+    pub leakage_conductance: f64,
+    /// Effective shunt resistance
+    pub shunt_ohms: f64,
+    /// Shunt resistance in the acquisition parameters the calibration was done with
+    pub nominal_shunt_ohms: f64,
+}
+
+fn fit_line(points: impl Iterator<Item = (f64, f64)>) -> Option<(f64, f64)> {
+    let points = points
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .collect_vec();
+    if points.len() < 2 {
+        return None;
+    }
+    let xs = MatrixMN::<f64, U2, Dynamic>::from_rows(&[
+        RowDVector::from_iterator(points.len(), points.iter().map(|(x, _)| *x)),
+        RowDVector::from_element(points.len(), 1.0),
+    ]);
+    let ys = DVector::from_iterator(points.len(), points.iter().map(|(_, y)| *y));
+
+    let betas = linear_regression(xs, ys)?;
+    Some((betas[(0, 1)], betas[(0, 0)]))
+}
+
+impl Calibration {
+    /// `open` and `closed` are uncalibrated two-terminal traces of the fixtures, `fixture_ohms`
+    /// is the resistance of the closed one, 0 for a short, which keeps the nominal shunt
+    /// resistance
+    pub fn from_fixtures(
+        open: &RawTrace,
+        closed: &RawTrace,
+        config: &AD2Config,
+        fixture_ohms: f64,
+    ) -> Result<Self> {
+        let (current_offset, leakage_conductance) =
+            fit_line(open.iter()).ok_or_else(|| TracerError::FitFailed {
+                message: "Can't fit the open fixture trace".to_string(),
+            })?;
+
+        let leakage_free = |(v, i): (f64, f64)| (i - current_offset - leakage_conductance * v, v);
+        let (voltage_offset, apparent_ohms) = fit_line(closed.iter().map(leakage_free))
+            .ok_or_else(|| TracerError::FitFailed {
+                message: "Can't fit the closed fixture trace".to_string(),
+            })?;
+
+        // The sweep is a whole number of triangle periods from 0 to the peak, so the mean voltage
+        // over an open fixture is half of the peak. The peak is the one the AWG is asked for,
+        // there being no independent reference to check its amplitude against.
+        let vs = open
+            .iter()
+            .map(|(v, _)| v)
+            .filter(|v| v.is_finite())
+            .collect_vec();
+        let mean_v = vs.iter().sum::<f64>() / vs.len() as f64;
+        let voltage_gain = 2.0 * (mean_v - voltage_offset) / config.sweep_max_v(1.0);
+
+        let shunt_ohms = if fixture_ohms > 0.0 {
+            fixture_ohms * config.current_shunt_ohms * voltage_gain / apparent_ohms
+        } else {
+            warn!("A short can't calibrate the shunt, keeping its nominal resistance");
+            config.current_shunt_ohms
+        };
+
+        let calibration = Calibration {
+            voltage_offset,
+            voltage_gain,
+            current_offset,
+            leakage_conductance,
+            shunt_ohms,
+            nominal_shunt_ohms: config.current_shunt_ohms,
+        };
+        calibration.check()?;
+        Ok(calibration)
+    }
+
+    fn check(&self) -> Result<()> {
+        if !(0.8..1.2).contains(&self.voltage_gain) {
+            return Err(TracerError::CalibrationFailed {
+                message: format!(
+                    "Voltage gain {} is off by more than 20%, check the open fixture",
+                    self.voltage_gain
+                ),
+            }
+            .into());
+        }
+        let shunt_ratio = self.shunt_ohms / self.nominal_shunt_ohms;
+        if !(0.8..1.2).contains(&shunt_ratio) {
+            return Err(TracerError::CalibrationFailed {
+                message: format!(
+                    "Effective shunt resistance {}Ω is off by more than 20% from {}Ω, \
+                     check the closed fixture and its resistance",
+                    self.shunt_ohms, self.nominal_shunt_ohms
+                ),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Corrects an uncalibrated trace captured with the same shunt resistance and input
+    /// channel setup as `CALIBRATION_SWEEP`
+    pub fn apply(&self, trace: &RawTrace) -> RawTrace {
+        let current_gain = self.nominal_shunt_ohms / self.shunt_ohms;
+        let (vs, is): (Vec<_>, Vec<_>) = trace
+            .iter()
+            .map(|(v, i)| {
+                (
+                    (v - self.voltage_offset) / self.voltage_gain,
+                    (i - self.current_offset - self.leakage_conductance * v) * current_gain,
+                )
+            })
+            .unzip();
+        RawTrace::new(is, vs)
+    }
+}
+
+/// Calibrations keyed by device serial number
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct CalibrationProfiles(BTreeMap<String, Calibration>);
+
+impl CalibrationProfiles {
+    fn path() -> Result<PathBuf> {
+        config_path(PROFILES_FILE)
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if path.exists() {
+            Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::write(Self::path()?, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, serial: &str) -> Option<&Calibration> {
+        self.0.get(serial)
+    }
+
+    pub fn insert(&mut self, serial: String, calibration: Calibration) {
+        self.0.insert(serial, calibration);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::backend::{AD2Config, Calibration, RawTrace};
+
+    /// A trace as an AD2 with the given errors would record it
+    fn capture(config: &AD2Config, ohms: Option<f64>) -> RawTrace {
+        let max_v = config.sweep_max_v(1.0);
+        let (vs, is): (Vec<_>, Vec<_>) = (0..1000)
+            .map(|ix| {
+                let phase = ix as f64 / 1000.0;
+                let v_src = max_v * (1.0 - (2.0 * phase - 1.0).abs());
+                let (v, i) = match ohms {
+                    None => (v_src, 0.0),
+                    Some(ohms) => {
+                        let i = v_src / (ohms + 100.0);
+                        (i * ohms, i)
+                    }
+                };
+                (
+                    v * 1.02 + 0.003,
+                    i * 100.0 / config.current_shunt_ohms - 0.000_1 + v * 1e-6,
+                )
+            })
+            .unzip();
+        RawTrace::new(is, vs)
+    }
+
+    #[test]
+    pub fn calibration_removes_offsets_and_gain_errors() {
+        let config = AD2Config::default();
+
+        let calibration = Calibration::from_fixtures(
+            &capture(&config, None),
+            &capture(&config, Some(100.0)),
+            &config,
+            100.0,
+        )
+        .unwrap();
+
+        assert_relative_eq!(calibration.voltage_offset, 0.003, epsilon = 1e-9);
+        assert_relative_eq!(calibration.voltage_gain, 1.02, epsilon = 1e-3);
+        assert_relative_eq!(calibration.current_offset, -0.000_1, epsilon = 1e-8);
+        assert_relative_eq!(calibration.shunt_ohms, 100.0, max_relative = 1e-3);
+
+        let corrected = calibration.apply(&capture(&config, Some(47.0)));
+        let resistances = corrected
+            .iter()
+            .filter(|(_, i)| *i > 0.001)
+            .map(|(v, i)| v / i)
+            .collect_vec();
+        assert!(!resistances.is_empty());
+        for r in resistances {
+            assert_relative_eq!(r, 47.0, max_relative = 1e-3);
+        }
+    }
+
+    #[test]
+    pub fn short_only_calibrates_offsets_and_voltage_gain() {
+        let config = AD2Config::default();
+
+        let calibration = Calibration::from_fixtures(
+            &capture(&config, None),
+            &capture(&config, Some(0.0)),
+            &config,
+            0.0,
+        )
+        .unwrap();
+
+        assert_relative_eq!(calibration.voltage_offset, 0.003, epsilon = 1e-9);
+        assert_relative_eq!(calibration.voltage_gain, 1.02, epsilon = 1e-3);
+        assert_relative_eq!(calibration.current_offset, -0.000_1, epsilon = 1e-8);
+        assert_relative_eq!(calibration.shunt_ohms, config.current_shunt_ohms);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use failure::Fail;
+
+#[derive(Debug)]
+pub struct TraceCancelled;
+
+impl Display for TraceCancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Trace cancelled")
+    }
+}
+
+impl Fail for TraceCancelled {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// Index of the bias level being traced (from 0) and the number of bias levels
+    pub bias_level: Option<(usize, usize)>,
+    /// Overall completion, from 0 to 1
+    pub fraction: f64,
+}
+
+impl Progress {
+    pub fn new(bias_level: Option<(usize, usize)>, level_fraction: f64) -> Self {
+        let level_fraction = level_fraction.max(0.0).min(1.0);
+        let fraction = match bias_level {
+            Some((ix, count)) => (ix as f64 + level_fraction) / count as f64,
+            None => level_fraction,
+        };
+        Progress {
+            bias_level,
+            fraction,
+        }
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some((ix, count)) = self.bias_level {
+            write!(f, "bias level {}/{}, ", ix + 1, count)?;
+        }
+        write!(f, "{:.0}%", self.fraction * 100.0)
+    }
+}
+
+struct ProgressReporter {
+    callback: Box<dyn FnMut(Progress) + Send>,
+    last_reported: Option<(Option<(usize, usize)>, u32)>,
+}
+
+/// Lets the caller of a `Backend` stop a trace in progress and follow its progress.
+/// Clones share the same state, so one can be handed to a worker thread and another kept
+/// to cancel it.
+#[derive(Clone, Default)]
+pub struct TraceControl {
+    cancelled: Arc<AtomicBool>,
+    progress: Option<Arc<Mutex<ProgressReporter>>>,
+}
+
+impl TraceControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_progress<F: FnMut(Progress) + Send + 'static>(callback: F) -> Self {
+        TraceControl {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            progress: Some(Arc::new(Mutex::new(ProgressReporter {
+                callback: Box::new(callback),
+                last_reported: None,
+            }))),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `TraceCancelled` once the trace is cancelled
+    pub fn check(&self) -> crate::Result<()> {
+        if self.is_cancelled() {
+            Err(TraceCancelled.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Passes `progress` to the callback unless it rounds to the last reported one
+    pub fn report(&self, progress: Progress) {
+        if let Some(reporter) = &self.progress {
+            if let Ok(mut reporter) = reporter.lock() {
+                let key = (progress.bias_level, (progress.fraction * 100.0) as u32);
+                if reporter.last_reported != Some(key) {
+                    reporter.last_reported = Some(key);
+                    (reporter.callback)(progress);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::backend::{Progress, TraceCancelled, TraceControl};
+
+    #[test]
+    pub fn progress_spans_bias_levels() {
+        let progress = Progress::new(Some((2, 5)), 0.0);
+
+        assert_relative_eq!(progress.fraction, 0.4);
+        assert_eq!(progress.to_string(), "bias level 3/5, 40%");
+    }
+
+    #[test]
+    pub fn clones_share_cancellation_and_progress() {
+        let reported = Arc::new(Mutex::new(vec![]));
+        let control = {
+            let reported = reported.clone();
+            TraceControl::with_progress(move |p| reported.lock().unwrap().push(p.fraction))
+        };
+        let worker = control.clone();
+
+        worker.report(Progress::new(None, 0.5));
+        worker.report(Progress::new(None, 0.501));
+        assert!(worker.check().is_ok());
+        control.cancel();
+
+        assert!(worker
+            .check()
+            .unwrap_err()
+            .downcast_ref::<TraceCancelled>()
+            .is_some());
+        assert_eq!(*reported.lock().unwrap(), vec![0.5]);
+    }
+}
@@ -1,12 +1,24 @@
 mod ad2;
+mod calibration;
+mod control;
+mod replay;
+mod sim;
 
-pub use self::ad2::AD2;
+pub use self::ad2::{
+    list_devices, AD2Config, AD2ConfigInfo, AD2DeviceInfo, DeviceSelection, DeviceSelector, AD2,
+};
+pub use self::calibration::{Calibration, CalibrationProfiles, CALIBRATION_SWEEP};
+pub use self::control::{Progress, TraceCancelled, TraceControl};
+pub use self::replay::Replay;
+pub use self::sim::{FetMode, Sim, SimConfig};
 
 use crate::dut::BiasDrive;
+use crate::error::TracerError;
 use noisy_float::prelude::*;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct BiasedTrace {
     pub bias: R64,
     pub trace: RawTrace,
@@ -43,12 +55,67 @@ impl RawTrace {
     }
 }
 
+/// Polarity of the voltages a two-terminal sweep covers, relative to the A terminal
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SweepRange {
+    Positive,
+    Negative,
+    Bipolar,
+}
+
+impl SweepRange {
+    pub fn covers_positive(self) -> bool {
+        self != SweepRange::Negative
+    }
+
+    pub fn covers_negative(self) -> bool {
+        self != SweepRange::Positive
+    }
+}
+
+impl Default for SweepRange {
+    fn default() -> Self {
+        SweepRange::Positive
+    }
+}
+
+impl Display for SweepRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SweepRange::Positive => f.write_str("positive"),
+            SweepRange::Negative => f.write_str("negative"),
+            SweepRange::Bipolar => f.write_str("bipolar"),
+        }
+    }
+}
+
+impl FromStr for SweepRange {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "positive" => Ok(SweepRange::Positive),
+            "negative" => Ok(SweepRange::Negative),
+            "bipolar" => Ok(SweepRange::Bipolar),
+            _ => Err(TracerError::invalid_argument(&format!(
+                "Unknown sweep range {}, expected positive, negative or bipolar",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
 pub trait Backend: Send + Sync {
-    fn trace_2(&self) -> crate::Result<RawTrace>;
+    fn capture_config(&self) -> Option<AD2Config> {
+        None
+    }
+    fn trace_2(&self, range: SweepRange, control: &TraceControl) -> crate::Result<RawTrace>;
     fn trace_3(
         &self,
         polarity: R64,
         bias_drive: BiasDrive,
         bias_levels: Vec<R64>,
+        control: &TraceControl,
     ) -> crate::Result<Vec<BiasedTrace>>;
 }
@@ -0,0 +1,210 @@
+use std::collections::btree_map::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use noisy_float::prelude::*;
+
+use crate::backend::Backend;
+use crate::backend::BiasedTrace;
+use crate::backend::RawTrace;
+use crate::backend::SweepRange;
+use crate::backend::{Progress, TraceControl};
+use crate::dut::csv::csv_reader_from_path;
+use crate::dut::BiasDrive;
+use crate::error::TracerError;
+use crate::util::Engineering;
+
+#[derive(Deserialize)]
+struct Record {
+    i: f64,
+    v: f64,
+    bias: Option<f64>,
+}
+
+enum Capture {
+    TwoTerminal(RawTrace),
+    ThreeTerminal(BTreeMap<R64, RawTrace>),
+}
+
+pub struct Replay {
+    path: PathBuf,
+    capture: Capture,
+}
+
+impl Replay {
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let mut unbiased = (Vec::new(), Vec::new());
+        let mut biased = BTreeMap::new();
+
+        for result in csv_reader_from_path(path)? {
+            let record: Record = result?;
+            let (vs, is) = match record.bias {
+                Some(bias) => biased
+                    .entry(r64(bias))
+                    .or_insert_with(|| (Vec::new(), Vec::new())),
+                None => &mut unbiased,
+            };
+            vs.push(record.v);
+            is.push(record.i);
+        }
+
+        let capture = match (unbiased.0.is_empty(), biased.is_empty()) {
+            (false, true) => Capture::TwoTerminal(RawTrace::new(unbiased.1, unbiased.0)),
+            (true, false) => Capture::ThreeTerminal(
+                biased
+                    .into_iter()
+                    .map(|(bias, (vs, is))| (bias, RawTrace::new(is, vs)))
+                    .collect(),
+            ),
+            (true, true) => return Err(TracerError::csv_schema(path, "contains no samples").into()),
+            (false, false) => {
+                return Err(TracerError::csv_schema(
+                    path,
+                    "mixes samples with and without a bias level",
+                )
+                .into())
+            }
+        };
+
+        Ok(Replay {
+            path: path.to_path_buf(),
+            capture,
+        })
+    }
+
+    fn find_bias_level(traces: &BTreeMap<R64, RawTrace>, level: R64) -> Option<&RawTrace> {
+        let tolerance = (level.raw().abs() * 1e-6).max(1e-12);
+        traces
+            .iter()
+            .find(|(bias, _)| (**bias - level).raw().abs() <= tolerance)
+            .map(|(_, trace)| trace)
+    }
+}
+
+impl Backend for Replay {
+    /// The captured trace is replayed as is, whatever its sweep range
+    fn trace_2(&self, _range: SweepRange, control: &TraceControl) -> crate::Result<RawTrace> {
+        match &self.capture {
+            Capture::TwoTerminal(trace) => {
+                control.report(Progress::new(None, 1.0));
+                Ok(trace.clone())
+            }
+            Capture::ThreeTerminal(_) => Err(TracerError::csv_schema(
+                &self.path,
+                "is a three-terminal capture, can't replay it as a two-terminal one",
+            )
+            .into()),
+        }
+    }
+
+    fn trace_3(
+        &self,
+        _polarity: R64,
+        _bias_drive: BiasDrive,
+        bias_levels: Vec<R64>,
+        control: &TraceControl,
+    ) -> crate::Result<Vec<BiasedTrace>> {
+        let traces = match &self.capture {
+            Capture::ThreeTerminal(traces) => traces,
+            Capture::TwoTerminal(_) => {
+                return Err(TracerError::csv_schema(
+                    &self.path,
+                    "is a two-terminal capture, can't replay it as a three-terminal one",
+                )
+                .into())
+            }
+        };
+
+        let count = bias_levels.len();
+        bias_levels
+            .into_iter()
+            .enumerate()
+            .map(|(ix, bias)| {
+                control.check()?;
+                let trace = Self::find_bias_level(traces, bias).ok_or_else(|| {
+                    TracerError::csv_schema(
+                        &self.path,
+                        &format!(
+                            "has no trace at bias level {}, available levels: {}",
+                            Engineering(bias.raw()),
+                            traces
+                                .keys()
+                                .map(|b| Engineering(b.raw()).to_string())
+                                .join(", ")
+                        ),
+                    )
+                })?;
+                control.report(Progress::new(Some((ix, count)), 1.0));
+                Ok(BiasedTrace {
+                    bias,
+                    trace: trace.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+    use noisy_float::prelude::r64;
+
+    use crate::backend::{Backend, Replay, SweepRange, TraceControl};
+    use crate::dut::BiasDrive;
+
+    #[test]
+    pub fn replays_two_terminal_capture() {
+        let replay = Replay::from_csv("res/1N4148.csv").unwrap();
+
+        assert_eq!(
+            replay
+                .trace_2(SweepRange::Positive, &TraceControl::new())
+                .unwrap()
+                .len(),
+            49471
+        );
+        assert!(replay
+            .trace_3(
+                r64(1.0),
+                BiasDrive::Current,
+                vec![r64(0.000_010)],
+                &TraceControl::new()
+            )
+            .is_err());
+    }
+
+    #[test]
+    pub fn replays_requested_bias_levels() {
+        let replay = Replay::from_csv("res/2N3904.csv.gz").unwrap();
+
+        let traces = replay
+            .trace_3(
+                r64(1.0),
+                BiasDrive::Current,
+                vec![r64(0.000_050), r64(0.000_010)],
+                &TraceControl::new(),
+            )
+            .unwrap();
+        assert_eq!(
+            traces.iter().map(|t| t.bias).collect_vec(),
+            vec![r64(0.000_050), r64(0.000_010)]
+        );
+        assert_eq!(traces[0].trace.len(), 166_563);
+    }
+
+    #[test]
+    pub fn missing_bias_level_is_an_error() {
+        let replay = Replay::from_csv("res/2N3904.csv.gz").unwrap();
+
+        let err = replay
+            .trace_3(
+                r64(1.0),
+                BiasDrive::Current,
+                vec![r64(0.000_015)],
+                &TraceControl::new(),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("no trace at bias level 15.000µ"));
+    }
+}
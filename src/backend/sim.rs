@@ -0,0 +1,445 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use noisy_float::prelude::*;
+use structopt::StructOpt;
+
+use crate::backend::AD2Config;
+use crate::backend::Backend;
+use crate::backend::BiasedTrace;
+use crate::backend::RawTrace;
+use crate::backend::{Progress, SweepRange, TraceControl};
+use crate::dut::BiasDrive;
+use crate::error::TracerError;
+
+const THERMAL_VOLTAGE: f64 = 0.025_85;
+const MAX_LOST_RUN: u64 = 32;
+const SOLVER_ITERATIONS: usize = 60;
+
+/// Whether a simulated FET conducts with no gate bias
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FetMode {
+    Enhancement,
+    Depletion,
+}
+
+impl FromStr for FetMode {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "enhancement" => Ok(FetMode::Enhancement),
+            "depletion" => Ok(FetMode::Depletion),
+            _ => Err(TracerError::invalid_argument(&format!(
+                "Unknown FET mode {}, expected enhancement or depletion",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
+#[derive(StructOpt, Clone, Debug)]
+pub struct SimConfig {
+    #[structopt(long, default_value = "1e-8", help = "diode saturation current, A")]
+    pub diode_is: f64,
+    #[structopt(long, default_value = "0.05", help = "diode n⋅Vt, V")]
+    pub diode_n_vt: f64,
+    #[structopt(long, default_value = "2", help = "diode series resistance, Ω")]
+    pub diode_rs: f64,
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "zener breakdown voltage, V (0 for no breakdown)"
+    )]
+    pub zener_vz: f64,
+    #[structopt(long, default_value = "1e-14", help = "BJT saturation current, A")]
+    pub bjt_is: f64,
+    #[structopt(long, default_value = "200", help = "BJT forward current gain")]
+    pub bjt_beta_f: f64,
+    #[structopt(long, default_value = "2", help = "BJT reverse current gain")]
+    pub bjt_beta_r: f64,
+    #[structopt(long, default_value = "100", help = "BJT Early voltage, V")]
+    pub bjt_va: f64,
+    #[structopt(
+        long,
+        default_value = "enhancement",
+        help = "FET mode, enhancement or depletion"
+    )]
+    pub fet_mode: FetMode,
+    #[structopt(
+        long,
+        default_value = "1.5",
+        help = "FET threshold voltage magnitude, V"
+    )]
+    pub fet_vth: f64,
+    #[structopt(
+        long,
+        default_value = "0.02",
+        help = "FET transconductance parameter, A/V²"
+    )]
+    pub fet_kp: f64,
+    #[structopt(
+        long,
+        default_value = "0.02",
+        help = "FET channel-length modulation, 1/V"
+    )]
+    pub fet_lambda: f64,
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "RMS noise on both input channels, V"
+    )]
+    pub noise: f64,
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "probability of losing a run of samples at any given sample"
+    )]
+    pub lost_samples: f64,
+    #[structopt(long, default_value = "10000", help = "samples per sweep")]
+    pub samples: usize,
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "seed for the noise and lost samples"
+    )]
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig::from_iter(&["sim"])
+    }
+}
+
+pub struct Sim {
+    config: SimConfig,
+    ad2: AD2Config,
+}
+
+impl Sim {
+    pub fn new(config: SimConfig, ad2: AD2Config) -> Self {
+        Sim { config, ad2 }
+    }
+
+    fn sweep(&self, min_v: f64, max_v: f64) -> impl Iterator<Item = f64> {
+        let samples = self.config.samples;
+        let cycles = f64::from(self.ad2.cycles_to_sample);
+        (0..samples).map(move |ix| {
+            let phase = (ix as f64 / samples as f64 * cycles).fract();
+            let triangle = if phase < 0.5 {
+                phase * 2.0
+            } else {
+                2.0 - phase * 2.0
+            };
+            min_v + triangle * (max_v - min_v)
+        })
+    }
+
+    /// Shockley diode, with an exponential breakdown of the same steepness if it's a zener
+    fn diode_current(&self, v_junction: f64) -> f64 {
+        let c = &self.config;
+        let forward = c.diode_is * ((v_junction / c.diode_n_vt).exp() - 1.0);
+        if c.zener_vz > 0.0 {
+            forward - c.diode_is * (-(v_junction + c.zener_vz) / c.diode_n_vt).exp()
+        } else {
+            forward
+        }
+    }
+
+    fn bjt_collector_current(&self, ib: f64, vce: f64) -> f64 {
+        let c = &self.config;
+        let base_current = |vbe: f64| {
+            c.bjt_is / c.bjt_beta_f * ((vbe / THERMAL_VOLTAGE).exp() - 1.0)
+                + c.bjt_is / c.bjt_beta_r * (((vbe - vce) / THERMAL_VOLTAGE).exp() - 1.0)
+        };
+        let vbe = bisect(-1.0, 1.2, |vbe| base_current(vbe) - ib);
+        let vbc = vbe - vce;
+
+        c.bjt_is
+            * ((vbe / THERMAL_VOLTAGE).exp() - (vbc / THERMAL_VOLTAGE).exp())
+            * (1.0 + vce / c.bjt_va)
+            - c.bjt_is / c.bjt_beta_r * ((vbc / THERMAL_VOLTAGE).exp() - 1.0)
+    }
+
+    fn fet_drain_current(&self, vgs: f64, vth: f64, vds: f64) -> f64 {
+        let c = &self.config;
+        let v_ov = vgs - vth;
+        if v_ov <= 0.0 {
+            0.0
+        } else if vds < v_ov {
+            c.fet_kp * (v_ov * vds - vds * vds / 2.0) * (1.0 + c.fet_lambda * vds)
+        } else {
+            c.fet_kp / 2.0 * v_ov * v_ov * (1.0 + c.fet_lambda * vds)
+        }
+    }
+
+    fn acquire(&self, rng: &mut Rng, points: impl Iterator<Item = (f64, f64)>) -> RawTrace {
+        let voltage_noise = self.config.noise;
+        let current_noise = self.config.noise / self.ad2.current_shunt_ohms;
+
+        let mut vs = Vec::new();
+        let mut is = Vec::new();
+        let mut lost_run = 0;
+        let mut total_lost = 0;
+
+        for (v, i) in points {
+            if lost_run == 0 && rng.next_f64() < self.config.lost_samples {
+                lost_run = 1 + rng.next_u64() % MAX_LOST_RUN;
+                total_lost += lost_run;
+            }
+            if lost_run > 0 {
+                lost_run -= 1;
+                vs.push(std::f64::NAN);
+                is.push(std::f64::NAN);
+            } else {
+                vs.push(v + rng.normal() * voltage_noise);
+                is.push(i + rng.normal() * current_noise);
+            }
+        }
+
+        if total_lost > 0 {
+            debug!("Simulated {} lost sample(-s)", total_lost);
+        }
+        RawTrace::new(is, vs)
+    }
+}
+
+impl Backend for Sim {
+    fn capture_config(&self) -> Option<AD2Config> {
+        Some(self.ad2.clone())
+    }
+
+    fn trace_2(&self, range: SweepRange, control: &TraceControl) -> crate::Result<RawTrace> {
+        control.check()?;
+        let mut rng = Rng::new(self.config.seed);
+
+        let (min_v, max_v) = self.ad2.sweep_limits(range);
+        let series_ohms = self.ad2.current_shunt_ohms + self.config.diode_rs;
+
+        let points = self
+            .sweep(min_v, max_v)
+            .map(|v_src| {
+                let v_junction = bisect(v_src.min(0.0), v_src.max(0.0), |v| {
+                    v + series_ohms * self.diode_current(v) - v_src
+                });
+                let i = self.diode_current(v_junction);
+                (v_junction + self.config.diode_rs * i, i)
+            })
+            .collect_vec();
+
+        control.report(Progress::new(None, 1.0));
+        Ok(self.acquire(&mut rng, points.into_iter()))
+    }
+
+    fn trace_3(
+        &self,
+        polarity: R64,
+        bias_drive: BiasDrive,
+        bias_levels: Vec<R64>,
+        control: &TraceControl,
+    ) -> crate::Result<Vec<BiasedTrace>> {
+        let mut rng = Rng::new(self.config.seed);
+
+        let max_v = self.ad2.sweep_max_v(polarity.raw());
+
+        let fet_vth = match self.config.fet_mode {
+            FetMode::Enhancement => self.config.fet_vth,
+            FetMode::Depletion => -self.config.fet_vth,
+        };
+
+        let mut traces = vec![];
+        let count = bias_levels.len();
+
+        for (ix, bias) in bias_levels.into_iter().enumerate() {
+            control.check()?;
+            let device_current = |v: f64| match bias_drive {
+                BiasDrive::Current => self.bjt_collector_current(bias.raw().abs(), v),
+                BiasDrive::Voltage => self.fet_drain_current((bias * polarity).raw(), fet_vth, v),
+            };
+
+            let points = self
+                .sweep(0.0, max_v)
+                .map(|v_src| {
+                    let v = bisect(v_src.min(0.0), v_src.max(0.0), |v| {
+                        v + self.ad2.current_shunt_ohms * device_current(v) - v_src
+                    });
+                    (v, device_current(v))
+                })
+                .collect_vec();
+
+            let trace = self.acquire(&mut rng, points.into_iter());
+            let vs = trace.iter().map(|(v, _)| v * polarity.raw()).collect_vec();
+            let is = trace.iter().map(|(_, i)| i).collect_vec();
+
+            traces.push(BiasedTrace {
+                bias,
+                trace: RawTrace::new(is, vs),
+            });
+            control.report(Progress::new(Some((ix, count)), 1.0));
+        }
+
+        Ok(traces)
+    }
+}
+
+/// Finds a root of a monotonically increasing `f` within `[lo, hi]`
+fn bisect<F: Fn(f64) -> f64>(mut lo: f64, mut hi: f64, f: F) -> f64 {
+    for _ in 0..SOLVER_ITERATIONS {
+        let mid = (lo + hi) / 2.0;
+        if f(mid) > 0.0 {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// xorshift64*, good enough for noise and perfectly reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+    use noisy_float::prelude::r64;
+
+    use crate::backend::{AD2Config, Backend, Sim, SimConfig, SweepRange, TraceControl};
+    use crate::dut::BiasDrive;
+    use crate::model::diode::{diode_model, leakage_current};
+
+    fn config() -> SimConfig {
+        SimConfig {
+            samples: 2000,
+            ..SimConfig::default()
+        }
+    }
+
+    #[test]
+    pub fn traces_are_reproducible() {
+        let sim = Sim::new(
+            SimConfig {
+                noise: 0.001,
+                lost_samples: 0.001,
+                ..config()
+            },
+            AD2Config::default(),
+        );
+
+        let bits = |sim: &Sim| {
+            sim.trace_2(SweepRange::Positive, &TraceControl::new())
+                .unwrap()
+                .iter()
+                .map(|(v, i)| (v.to_bits(), i.to_bits()))
+                .collect_vec()
+        };
+        assert_eq!(bits(&sim), bits(&sim));
+    }
+
+    #[test]
+    pub fn diode_model_matches_simulated_diode() {
+        let sim = Sim::new(
+            SimConfig {
+                diode_rs: 0.0,
+                ..config()
+            },
+            AD2Config::default(),
+        );
+
+        let model = diode_model(
+            &sim.trace_2(SweepRange::Positive, &TraceControl::new())
+                .unwrap(),
+            &AD2Config::default(),
+        )
+        .unwrap();
+
+        assert_relative_eq!(model.is(), 1e-8, max_relative = 0.05);
+        assert_relative_eq!(model.n_vt(), 0.05, max_relative = 0.01);
+    }
+
+    #[test]
+    pub fn diode_model_finds_simulated_series_resistance() {
+        let sim = Sim::new(
+            SimConfig {
+                diode_rs: 10.0,
+                ..config()
+            },
+            AD2Config::default(),
+        );
+
+        let model = diode_model(
+            &sim.trace_2(SweepRange::Positive, &TraceControl::new())
+                .unwrap(),
+            &AD2Config::default(),
+        )
+        .unwrap();
+
+        assert_relative_eq!(model.rs().unwrap(), 10.0, max_relative = 0.05);
+        assert_relative_eq!(model.is(), 1e-8, max_relative = 0.05);
+        assert_relative_eq!(model.n_vt(), 0.05, max_relative = 0.01);
+    }
+
+    #[test]
+    pub fn bipolar_sweep_reaches_reverse_leakage() {
+        let sim = Sim::new(config(), AD2Config::default());
+
+        let trace = sim
+            .trace_2(SweepRange::Bipolar, &TraceControl::new())
+            .unwrap();
+
+        let min_v = trace.iter().map(|(v, _)| v).fold(0.0, f64::min);
+        assert_relative_eq!(min_v, -2.2, max_relative = 0.01);
+        assert_relative_eq!(
+            leakage_current(&trace, -2.0).unwrap(),
+            -1e-8,
+            max_relative = 1e-3
+        );
+        assert!(diode_model(&trace, &AD2Config::default()).is_some());
+    }
+
+    #[test]
+    pub fn bjt_collector_current_follows_bias() {
+        let sim = Sim::new(config(), AD2Config::default());
+
+        let traces = sim
+            .trace_3(
+                r64(-1.0),
+                BiasDrive::Current,
+                vec![r64(-0.000_010), r64(-0.000_020)],
+                &TraceControl::new(),
+            )
+            .unwrap();
+
+        let max_current = |ix: usize| traces[ix].trace.iter().map(|(_, i)| i).fold(0.0, f64::max);
+        assert!(traces[0].trace.iter().all(|(v, _)| v <= 0.0));
+        assert_relative_eq!(max_current(0), 0.002, max_relative = 0.1);
+        assert_relative_eq!(max_current(1), 0.004, max_relative = 0.1);
+    }
+}
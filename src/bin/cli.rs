@@ -1,19 +1,254 @@
 #[macro_use]
 extern crate log;
 
+use std::fs::File;
+use std::io::BufRead;
+use std::path::Path;
+
 use structopt::StructOpt;
 
+use ks_curve_tracer::backend::{
+    list_devices, Backend, Calibration, CalibrationProfiles, RawTrace, TraceControl, AD2,
+    CALIBRATION_SWEEP,
+};
+use ks_curve_tracer::dut::batch::{analyze, trace_files};
+use ks_curve_tracer::dut::matching::{load_parts, MatchReport};
+use ks_curve_tracer::dut::report::TraceReport;
+use ks_curve_tracer::dut::session::Session;
+use ks_curve_tracer::dut::trace::save_spice_card;
+use ks_curve_tracer::dut::trace::Trace;
+use ks_curve_tracer::error::{describe, exit_code, TracerError};
+use ks_curve_tracer::options::AnalyzeOpt;
 use ks_curve_tracer::options::CliOpt;
+use ks_curve_tracer::options::MatchOpt;
 use ks_curve_tracer::options::Opt;
+use ks_curve_tracer::options::PlotOpt;
+use ks_curve_tracer::options::ReportFormat;
+use ks_curve_tracer::util::{strip_markup, Engineering};
 use ks_curve_tracer::Result;
 
-fn main() -> Result<()> {
-    let opt = CliOpt::from_args();
-    opt.initialize_logging()?;
+fn capture_fixture(ad2: &AD2, fixture: &str) -> Result<RawTrace> {
+    println!("Connect {} between A and K and press Enter", fixture);
+    std::io::stdin().lock().lines().next().transpose()?;
+    ad2.trace_2(CALIBRATION_SWEEP, &TraceControl::new())
+}
+
+fn calibrate(opt: &CliOpt, fixture_ohms: f64) -> Result<()> {
+    let ad2 = opt.ad2()?.uncalibrated();
+
+    let open = capture_fixture(&ad2, "nothing")?;
+    let closed = if fixture_ohms > 0.0 {
+        capture_fixture(&ad2, &format!("a {}Ω resistor", fixture_ohms))?
+    } else {
+        println!("A short doesn't calibrate the shunt, use --fixture-ohms with a known resistor for that");
+        capture_fixture(&ad2, "a short")?
+    };
+    let calibration = Calibration::from_fixtures(&open, &closed, ad2.config(), fixture_ohms)?;
+    println!(
+        "Voltage offset {}V, gain {:.4}\n\
+         Current offset {}A, leakage {}S\n\
+         Shunt {}Ω, nominal {}Ω",
+        Engineering(calibration.voltage_offset),
+        calibration.voltage_gain,
+        Engineering(calibration.current_offset),
+        Engineering(calibration.leakage_conductance),
+        Engineering(calibration.shunt_ohms),
+        Engineering(calibration.nominal_shunt_ohms)
+    );
+
+    let mut profiles = CalibrationProfiles::load()?;
+    profiles.insert(ad2.serial().to_string(), calibration);
+    profiles.save()?;
+    println!("Saved the calibration for {}", ad2.serial());
+    Ok(())
+}
+
+fn plot(opt: &CliOpt, plot_opt: &PlotOpt) -> Result<()> {
+    let mut trace = opt.load_from_csv(plot_opt.file())?;
+    trace.fill_model();
+    plot_opt
+        .plot(trace.as_ref())
+        .save(trace.as_ref(), plot_opt.output())?;
+    info!("Saved the plot to {}", plot_opt.output().display());
+    Ok(())
+}
+
+fn analyze_traces(opt: &CliOpt, analyze_opt: &AnalyzeOpt) -> Result<()> {
+    let files = trace_files(analyze_opt.inputs())?;
+    if files.is_empty() {
+        return Err(TracerError::NoTraceFiles.into());
+    }
+    info!("Analyzing {} traces", files.len());
+
+    let summary = analyze(&files, |path| opt.load_from_csv(path));
+    for failed in &summary.failures {
+        warn!("Skipped {}: {}", failed.file.display(), failed.error);
+    }
+
+    match analyze_opt.output() {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| TracerError::io(path, e))?;
+            summary.write(analyze_opt.format(), file)?;
+            info!("Saved the summary to {}", path.display());
+        }
+        None => summary.write(analyze_opt.format(), std::io::stdout())?,
+    }
+    info!(
+        "Analyzed {} of {} traces",
+        summary.traces.len(),
+        files.len()
+    );
+    Ok(())
+}
+
+fn match_lot(opt: &CliOpt, match_opt: &MatchOpt) -> Result<()> {
+    let metric = match_opt.metric()?;
+    let files = trace_files(match_opt.inputs())?;
+    if files.is_empty() {
+        return Err(TracerError::NoTraceFiles.into());
+    }
+    info!("Matching {} parts by {}", files.len(), metric);
+
+    let (parts, failures) = load_parts(&files, |path| opt.load_from_csv(path), &metric);
+    for failed in &failures {
+        warn!("Skipped {}: {}", failed.file.display(), failed.error);
+    }
+    let report = MatchReport::new(
+        &parts,
+        failures,
+        &metric,
+        match_opt.tolerance(),
+        match_opt.grouping(),
+    );
+
+    match match_opt.output() {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| TracerError::io(path, e))?;
+            report.write(match_opt.format(), file)?;
+            info!("Saved the grouping to {}", path.display());
+        }
+        None => report.write(match_opt.format(), std::io::stdout())?,
+    }
+    info!(
+        "Formed {} groups, {} parts unmatched, {}",
+        report.groups.len(),
+        report.unmatched.len(),
+        report.method
+    );
+    Ok(())
+}
+
+fn session(opt: &CliOpt, part_number: &str) -> Result<()> {
+    let mut session = Session::new(part_number, opt.session_dir())?;
+    let stdin = std::io::stdin();
+    loop {
+        println!(
+            "Connect {} #{} and press Enter, or enter q to finish",
+            session.part_number(),
+            session.next_serial()
+        );
+        let line = match stdin.lock().lines().next().transpose()? {
+            Some(line) => line,
+            None => break,
+        };
+        if line.trim() == "q" {
+            break;
+        }
+
+        let mut trace = match opt.trace() {
+            Ok(trace) => trace,
+            Err(e) => {
+                eprintln!("Error: {}", describe(&e));
+                continue;
+            }
+        };
+        let path = match session.record(trace.as_mut()) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Error: {}", describe(&e));
+                continue;
+            }
+        };
+        println!("Saved {}", path.display());
+        print!("{}", strip_markup(&trace.model_report()));
+    }
+
+    let summary_path = opt
+        .session_summary_path()
+        .map_or_else(|| session.summary_path(), Path::to_path_buf);
+    session.export(&summary_path)?;
+    println!(
+        "Saved the table of {} parts to {}",
+        session.summary().traces.len(),
+        summary_path.display()
+    );
+    Ok(())
+}
+
+fn run(opt: &CliOpt) -> Result<()> {
+    if opt.list_devices() {
+        let devices = list_devices()?;
+        if devices.is_empty() {
+            println!("No devices found");
+        }
+        for device in devices {
+            println!("{}", device);
+        }
+        return Ok(());
+    }
+    if let Some(fixture_ohms) = opt.calibration_fixture_ohms() {
+        return calibrate(opt, fixture_ohms);
+    }
+    if let Some(plot_opt) = opt.plot_opt() {
+        return plot(opt, plot_opt);
+    }
+    if let Some(analyze_opt) = opt.analyze_opt() {
+        return analyze_traces(opt, analyze_opt);
+    }
+    if let Some(match_opt) = opt.match_opt() {
+        return match_lot(opt, match_opt);
+    }
+    if let Some(part_number) = opt.session_part_number() {
+        return session(opt, part_number);
+    }
 
     let mut trace = opt.trace()?;
+    if let Some(path) = opt.output_path() {
+        trace.save_as_csv(path)?;
+        info!("Saved the trace to {}", path.display());
+    }
 
     trace.fill_model();
-    info!("Diode model: {:?}", trace.model_report());
+    let report = trace.model_report();
+    if report.is_empty() {
+        return Err(TracerError::FitFailed {
+            message: "Can't fit a model to the trace".to_string(),
+        }
+        .into());
+    }
+    match opt.format() {
+        ReportFormat::Text => print!("{}", strip_markup(&report)),
+        ReportFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&TraceReport::new(trace.as_ref()))?
+        ),
+    }
+
+    if let Some(path) = opt.spice_path() {
+        save_spice_card(trace.as_ref(), path, opt.temperature())?;
+        info!("Saved the SPICE model to {}", path.display());
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let opt = CliOpt::from_args();
+    opt.initialize_logging()?;
+
+    if let Err(e) = run(&opt) {
+        eprintln!("Error: {}", describe(&e));
+        debug!("{}", e.backtrace());
+        std::process::exit(exit_code(&e));
+    }
     Ok(())
 }
@@ -5,14 +5,13 @@ extern crate relm;
 #[macro_use]
 extern crate relm_derive;
 
-use cairo::FontSlant;
-use cairo::FontWeight;
 use gdk_pixbuf::Pixbuf;
 use gtk;
 use gtk::ButtonBoxExt;
 use gtk::ButtonBoxStyle;
 use gtk::ButtonExt;
 use gtk::ButtonsType;
+use gtk::Cast;
 use gtk::ContainerExt;
 use gtk::CssProviderExt;
 use gtk::DialogExt;
@@ -29,6 +28,7 @@ use gtk::Orientation;
 use gtk::Orientation::Horizontal;
 use gtk::Orientation::Vertical;
 use gtk::OverlayExt;
+use gtk::ProgressBarExt;
 use gtk::ResponseType;
 use gtk::SpinnerExt;
 use gtk::StyleContextExt;
@@ -37,7 +37,6 @@ use gtk::WidgetExt;
 use gtk::Window;
 use gtk::WindowType;
 use gtk::{Button, CssProvider, STYLE_PROVIDER_PRIORITY_APPLICATION};
-use itertools_num::linspace;
 use relm::DrawHandler;
 use relm::Relm;
 use relm::Update;
@@ -46,8 +45,14 @@ use relm::{Channel, ContainerWidget};
 use structopt::StructOpt;
 
 use core::borrow::Borrow;
+use ks_curve_tracer::backend::{
+    list_devices, AD2Config, DeviceSelection, Progress, TraceCancelled, TraceControl,
+};
+use ks_curve_tracer::dut::detect_device_type;
+use ks_curve_tracer::dut::session::Session;
+use ks_curve_tracer::dut::trace::save_spice_card;
 use ks_curve_tracer::dut::trace::NullTrace;
-use ks_curve_tracer::dut::trace::{GuiTrace, ShareableTrace};
+use ks_curve_tracer::dut::trace::{FittedModel, GuiTrace, ShareableTrace};
 use ks_curve_tracer::dut::DeviceType;
 use ks_curve_tracer::dut::SomeDevice;
 use ks_curve_tracer::dut::SomeDeviceType;
@@ -55,6 +60,11 @@ use ks_curve_tracer::dut::TwoTerminalDevice;
 use ks_curve_tracer::dut::{
     CurrentBiasedDeviceType, Device, TwoTerminalDeviceType, VoltageBiasedDeviceType,
 };
+use ks_curve_tracer::error::{describe, exit_code, TracerError};
+use ks_curve_tracer::gui::plot::Plot;
+use ks_curve_tracer::gui::settings::{
+    ad2_settings_dialog, device_picker_dialog, save_trace_dialog, session_dialog,
+};
 use ks_curve_tracer::gui::widgets::{DeviceConfig, DeviceConfigMsg, DeviceConfigWidget};
 use ks_curve_tracer::options::GuiOpt;
 use ks_curve_tracer::options::Opt;
@@ -62,6 +72,21 @@ use ks_curve_tracer::util::VERSION;
 use ks_curve_tracer::Result;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+
+const LIVE_FIT_INTERVAL: Duration = Duration::from_secs(1);
+const LIVE_MIN_FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+struct LiveState {
+    sender: relm::Sender<Msg>,
+    /// Number of the frame on display
+    frame: u64,
+    last_frame: Option<Instant>,
+    frame_rate: Option<f64>,
+    last_fit: Option<Instant>,
+    fitting: bool,
+    frozen: bool,
+}
 
 struct Model {
     relm: Relm<Win>,
@@ -71,20 +96,58 @@ struct Model {
     v_zoom: f64,
     i_zoom: f64,
     device: SomeDevice,
+    ad2_config: AD2Config,
+    device_selection: DeviceSelection,
+    trace_control: Option<TraceControl>,
+    live: Option<LiveState>,
+    /// Set while each capture is saved under an incrementing serial
+    session: Option<Session>,
+}
+
+/// The model report, headed by a warning if the fit explains too little of the trace
+fn model_markup(trace: &dyn GuiTrace, min_r_squared: f64) -> String {
+    let report = trace.model_report();
+    match trace.model_quality() {
+        Some(quality) if quality.r_squared < min_r_squared => format!(
+            "<span foreground=\"red\">Poor fit, R² {:.4} below {}</span>\n{}",
+            quality.r_squared, min_r_squared, report
+        ),
+        _ => report,
+    }
+}
+
+/// A model fitted in the background to a live frame
+#[derive(Clone, Debug)]
+struct LiveFit {
+    frame: u64,
+    model: Option<FittedModel>,
+    report: String,
 }
 
 struct ModelParam {
     opt: GuiOpt,
+    ad2_config: AD2Config,
+    device_selection: DeviceSelection,
 }
 
 #[derive(Msg, Clone, Debug)]
 enum Msg {
     Trace,
+    StopTrace,
+    Live(bool),
+    Freeze(bool),
+    Session(bool),
+    LiveFrame(Arc<dyn ShareableTrace>),
+    LiveModel(LiveFit),
+    TraceProgress(Progress),
     TraceSucceeded(Arc<dyn ShareableTrace>),
     TraceFailed(Arc<failure::Error>),
     FitModel,
     LoadTrace,
     SaveTrace,
+    ExportModel,
+    EditSettings,
+    PickDevice,
     UpdateDrawBuffer,
     Quit,
     VZoom(f64),
@@ -98,7 +161,16 @@ struct Widgets {
     window: Window,
     drawing_area: DrawingArea,
     drawing_area_overlay: gtk::Overlay,
+    trace_button: Button,
+    stop_button: Button,
+    live_button: gtk::ToggleButton,
+    freeze_button: gtk::ToggleButton,
+    refresh_rate_text: Label,
+    session_button: gtk::ToggleButton,
+    session_text: Label,
+    progress_bar: gtk::ProgressBar,
     device_config: relm::Component<DeviceConfigWidget>,
+    device_type_buttons: Vec<(SomeDeviceType, gtk::RadioButton)>,
     connection_hint_text: Label,
     legend_text: Label,
     model_text: Label,
@@ -124,7 +196,156 @@ impl Win {
     }
 
     fn error_box_error(&self, err: &failure::Error) {
-        self.error_box(&format!("Error: {}\nBacktrace: {}", err, err.backtrace()));
+        debug!("{}", err.backtrace());
+        self.error_box(&format!("Error: {}", describe(err)));
+    }
+
+    fn set_tracing(&mut self, control: Option<TraceControl>) {
+        let tracing = control.is_some();
+        self.model.trace_control = control;
+        self.widgets.trace_button.set_sensitive(!tracing);
+        self.widgets.stop_button.set_sensitive(tracing);
+        self.widgets.progress_bar.set_fraction(0.0);
+        self.widgets.progress_bar.set_text(Some(""));
+    }
+
+    /// Traces on a worker thread, once or, in live mode, until cancelled
+    fn spawn_trace(&mut self, live: bool) {
+        let stream = self.model.relm.stream().clone();
+        let (_, sender) = Channel::new(move |msg| {
+            stream.emit(msg);
+        });
+        let control = {
+            let sender = sender.clone();
+            TraceControl::with_progress(move |progress| {
+                sender
+                    .send(Msg::TraceProgress(progress))
+                    .expect("send message");
+            })
+        };
+        self.set_tracing(Some(control.clone()));
+        if live {
+            self.model.live = Some(LiveState {
+                sender: sender.clone(),
+                frame: 0,
+                last_frame: None,
+                frame_rate: None,
+                last_fit: None,
+                fitting: false,
+                frozen: false,
+            });
+            self.widgets.freeze_button.set_sensitive(true);
+        }
+
+        let res = (|| {
+            let capture_device = self
+                .model
+                .opt
+                .device(&self.model.device_selection, &self.model.ad2_config)?;
+            let dut = self.model.device.clone();
+
+            thread::spawn(move || {
+                let res = (|| loop {
+                    let started = Instant::now();
+                    control.check()?;
+                    let trace: Arc<dyn ShareableTrace> =
+                        Arc::from(dut.trace(&*capture_device, &control)?);
+                    if !live {
+                        info!("Got the trace");
+                        sender
+                            .send(Msg::TraceSucceeded(trace))
+                            .expect("send message");
+                        return Ok(());
+                    }
+                    sender.send(Msg::LiveFrame(trace)).expect("send message");
+                    if let Some(pause) = LIVE_MIN_FRAME_INTERVAL.checked_sub(started.elapsed()) {
+                        thread::sleep(pause);
+                    }
+                })();
+                if let Err(err) = res {
+                    sender.send(Msg::TraceFailed(err)).expect("send message");
+                }
+            });
+            Ok(())
+        })();
+        if let Err(err) = res {
+            self.model.relm.stream().emit(Msg::TraceFailed(err));
+        }
+    }
+
+    fn stop_live(&mut self) {
+        if self.model.live.take().is_some() {
+            self.widgets.live_button.set_active(false);
+            self.widgets.freeze_button.set_active(false);
+            self.widgets.freeze_button.set_sensitive(false);
+            self.widgets.refresh_rate_text.set_text("");
+        }
+    }
+
+    fn show_session(&self) {
+        let text = match &self.model.session {
+            Some(session) => format!(
+                "{}: {} saved, next #{:04}",
+                session.part_number(),
+                session.summary().traces.len() + session.summary().failures.len(),
+                session.next_serial()
+            ),
+            None => String::new(),
+        };
+        self.widgets.session_text.set_text(&text);
+    }
+
+    /// Offers to export the session table, then ends the session
+    fn end_session(&mut self) {
+        if let Some(session) = self.model.session.take() {
+            let dialog = gtk::FileChooserDialog::with_buttons(
+                Some("Export session table"),
+                Some(&self.widgets.window),
+                FileChooserAction::Save,
+                &[
+                    ("_Discard", ResponseType::Cancel),
+                    ("_Export", ResponseType::Accept),
+                ],
+            );
+            dialog.set_do_overwrite_confirmation(true);
+            let summary_path = session.summary_path();
+            if let Some(folder) = summary_path.parent() {
+                dialog.set_current_folder(folder);
+            }
+            if let Some(name) = summary_path.file_name() {
+                dialog.set_current_name(&*name.to_string_lossy());
+            }
+
+            if dialog.run() == gtk::ResponseType::Accept {
+                if let Some(filename) = dialog.get_filename() {
+                    let _ = self.handle_error(session.export(&filename));
+                }
+            }
+            dialog.close();
+        }
+        self.show_session();
+    }
+
+    /// Makes `device` the current one, selecting its type and showing its config
+    fn select_device(&mut self, device: SomeDevice) {
+        let device_type = device.device_type();
+        self.widgets
+            .device_config
+            .stream()
+            .emit(DeviceConfigMsg::SetConfig(device.config()));
+        self.model.device = device;
+        self.widgets
+            .connection_hint_text
+            .set_markup(device_type.connection_hint());
+
+        if let Some((_, button)) = self
+            .widgets
+            .device_type_buttons
+            .iter()
+            .find(|(t, _)| *t == device_type)
+        {
+            button.set_active(true);
+        }
     }
 
     fn handle_error<T>(&self, res: Result<T>) -> Option<T> {
@@ -151,7 +372,14 @@ impl Update for Win {
             opt: param.opt,
             v_zoom: 1.0,
             i_zoom: 0.05,
-            device: SomeDevice::TwoTerminal(TwoTerminalDevice::Diode),
+            device: SomeDevice::TwoTerminal(TwoTerminalDevice::from_type(
+                TwoTerminalDeviceType::Diode,
+            )),
+            ad2_config: param.ad2_config,
+            device_selection: param.device_selection,
+            trace_control: None,
+            live: None,
+            session: None,
         }
     }
 
@@ -167,50 +395,160 @@ impl Update for Win {
                     .legend_text
                     .set_markup(&self.model.device.legend());
 
-                let stream = self.model.relm.stream().clone();
-                let (_, sender) = Channel::new(move |msg| {
-                    stream.emit(msg);
-                });
-                let res = (|| {
-                    let capture_device = self.model.opt.device()?;
-                    let dut = self.model.device.clone();
-
-                    thread::spawn(move || {
-                        let res = (|| {
-                            let trace: Arc<dyn ShareableTrace> =
-                                Arc::from(dut.trace(&*capture_device)?);
-                            info!("Got the trace");
-                            sender
-                                .send(Msg::TraceSucceeded(trace))
-                                .expect("send message");
-                            Ok(())
-                        })();
-                        if let Err(err) = res {
-                            sender.send(Msg::TraceFailed(err)).expect("send message");
-                        }
+                self.spawn_trace(false);
+            }
+            Msg::Live(true) => {
+                if self.model.trace_control.is_none() {
+                    self.widgets.model_text.set_markup("");
+                    self.widgets
+                        .legend_text
+                        .set_markup(&self.model.device.legend());
+
+                    self.spawn_trace(true);
+                } else if self.model.live.is_none() {
+                    self.widgets.live_button.set_active(false);
+                }
+            }
+            Msg::Live(false) => {
+                if let (Some(_), Some(control)) = (&self.model.live, &self.model.trace_control) {
+                    info!("Stopping live tracing");
+                    control.cancel();
+                }
+            }
+            Msg::Freeze(frozen) => {
+                if let Some(live) = &mut self.model.live {
+                    live.frozen = frozen;
+                    if frozen {
+                        self.model.relm.stream().emit(Msg::FitModel);
+                    }
+                }
+            }
+            Msg::Session(true) => {
+                if self.model.session.is_none() {
+                    let started = session_dialog(
+                        &self.widgets.window,
+                        self.model.trace.metadata().part_number.as_deref(),
+                        &std::env::current_dir().unwrap_or_default(),
+                    )
+                    .and_then(|(part_number, folder)| {
+                        self.handle_error(Session::new(&part_number, &folder))
                     });
-                    Ok(())
-                })();
-                if let Err(err) = res {
-                    self.model.relm.stream().emit(Msg::TraceFailed(err));
+                    match started {
+                        Some(session) => {
+                            self.model.session = Some(session);
+                            self.show_session();
+                        }
+                        None => self.widgets.session_button.set_active(false),
+                    }
+                }
+            }
+            Msg::Session(false) => self.end_session(),
+            Msg::LiveFrame(trace) => {
+                if let Some(live) = &mut self.model.live {
+                    let now = Instant::now();
+                    if let Some(last_frame) = live.last_frame {
+                        let rate = 1.0 / (now - last_frame).as_secs_f64();
+                        let rate = match live.frame_rate {
+                            Some(smoothed) => smoothed * 0.8 + rate * 0.2,
+                            None => rate,
+                        };
+                        live.frame_rate = Some(rate);
+                        self.widgets
+                            .refresh_rate_text
+                            .set_text(&format!("{:.1} traces/s", rate));
+                    }
+                    live.last_frame = Some(now);
+
+                    if !live.frozen {
+                        live.frame += 1;
+                        if !live.fitting
+                            && live
+                                .last_fit
+                                .map_or(true, |last_fit| now - last_fit >= LIVE_FIT_INTERVAL)
+                        {
+                            live.fitting = true;
+                            live.last_fit = Some(now);
+                            let sender = live.sender.clone();
+                            let frame = live.frame;
+                            let trace = trace.clone();
+                            let min_r_squared = self.model.opt.min_r_squared();
+                            thread::spawn(move || {
+                                let mut trace = trace.as_gui_trace();
+                                trace.fill_model();
+                                sender
+                                    .send(Msg::LiveModel(LiveFit {
+                                        frame,
+                                        model: trace.fitted_model(),
+                                        report: model_markup(trace.as_ref(), min_r_squared),
+                                    }))
+                                    .expect("send message");
+                            });
+                        }
+                        self.model.trace = trace.as_gui_trace();
+                        self.model.relm.stream().emit(Msg::UpdateDrawBuffer);
+                    }
                 }
             }
+            Msg::LiveModel(fit) => {
+                if let Some(live) = &mut self.model.live {
+                    live.fitting = false;
+                    if !live.frozen {
+                        self.widgets.model_text.set_markup(&fit.report);
+                        // The model is only drawn over the frame it was fitted to
+                        let current = fit.frame == live.frame;
+                        if let Some(model) = fit.model.filter(|_| current) {
+                            self.model.trace.set_fitted_model(model);
+                            self.model.relm.stream().emit(Msg::UpdateDrawBuffer);
+                        }
+                    }
+                }
+            }
+            Msg::StopTrace => {
+                if let Some(control) = &self.model.trace_control {
+                    info!("Cancelling the trace");
+                    control.cancel();
+                }
+            }
+            Msg::TraceProgress(progress) => {
+                self.widgets.progress_bar.set_fraction(progress.fraction);
+                self.widgets
+                    .progress_bar
+                    .set_text(Some(&progress.to_string()));
+            }
             Msg::TraceSucceeded(trace) => {
+                self.set_tracing(None);
                 self.model.trace = trace.as_gui_trace();
+                let recorded = match &mut self.model.session {
+                    Some(session) => Some(session.record(self.model.trace.as_mut())),
+                    None => None,
+                };
+                if let Some(res) = recorded {
+                    if let Some(path) = self.handle_error(res) {
+                        info!("Saved the trace to {}", path.display());
+                    }
+                    self.show_session();
+                }
                 self.model.relm.stream().emit(Msg::UpdateDrawBuffer);
                 self.model.relm.stream().emit(Msg::FitModel);
             }
             Msg::TraceFailed(error) => {
+                self.set_tracing(None);
+                self.stop_live();
                 self.model.relm.stream().emit(Msg::UpdateDrawBuffer);
                 self.model.relm.stream().emit(Msg::FitModel);
-                self.error_box_error(error.borrow());
+                if error.downcast_ref::<TraceCancelled>().is_some() {
+                    info!("Trace cancelled");
+                } else {
+                    self.error_box_error(error.borrow());
+                }
             }
             Msg::FitModel => {
                 self.model.trace.fill_model();
                 info!("Fit model to the trace");
-                self.widgets
-                    .model_text
-                    .set_markup(&self.model.trace.model_report());
+                self.widgets.model_text.set_markup(&model_markup(
+                    self.model.trace.as_ref(),
+                    self.model.opt.min_r_squared(),
+                ));
                 self.model.relm.stream().emit(Msg::UpdateDrawBuffer);
                 let drawing_area_overlay_style =
                     self.widgets.drawing_area_overlay.get_style_context();
@@ -219,122 +557,19 @@ impl Update for Win {
             Msg::UpdateDrawBuffer => {
                 let cr = self.model.draw_handler.get_context();
                 let allocation = self.widgets.drawing_area.get_allocation();
-                let width = f64::from(allocation.width);
-                let height = f64::from(allocation.height);
-
-                cr.identity_matrix();
-                cr.translate(0.5, 0.5);
-
-                cr.set_source_rgb(1.0, 1.0, 1.0);
-                cr.paint();
-
-                cr.set_source_rgb(0.0, 0.0, 0.0);
-                cr.set_line_width(1.0);
-
-                cr.translate(10.0, 10.0);
-
-                let max_i = self.model.i_zoom;
-                let max_v = self.model.v_zoom * self.model.trace.area_of_interest().v_polarity();
-
-                let i_factor = (height - 20.0) / max_i;
-                let v_factor = (width - 20.0) / max_v;
-
-                for (ix, i_gridline) in linspace(0.0, max_i, 11).enumerate() {
-                    match ix {
-                        0 | 10 => {
-                            cr.set_dash(&[], 0.0);
-                        }
-                        5 => {
-                            cr.set_dash(&[1.0, 2.0], 0.0);
-                        }
-                        _ => {
-                            cr.set_dash(&[1.0, 3.0], 0.0);
-                        }
-                    }
-
-                    cr.move_to(0.0, height - 20.0 - i_gridline * i_factor);
-                    cr.line_to(width - 20.0, height - 20.0 - i_gridline * i_factor);
-                    cr.stroke();
-                }
-
-                for (ix, v_gridline) in linspace(0.0, max_v, 11).enumerate() {
-                    match ix {
-                        0 | 10 => {
-                            cr.set_dash(&[], 0.0);
-                        }
-                        5 => {
-                            cr.set_dash(&[1.0, 2.0], 0.0);
-                        }
-                        _ => {
-                            cr.set_dash(&[1.0, 3.0], 0.0);
-                        }
-                    }
 
-                    cr.move_to(v_gridline * v_factor, 0.0);
-                    cr.line_to(v_gridline * v_factor, height - 20.0);
-                    cr.stroke();
-                }
-
-                self.model.trace.draw(&*cr, v_factor, i_factor, height);
-
-                for (ix, i_gridline) in linspace(0.0, max_i, 11).enumerate() {
-                    if ix > 0 {
-                        cr.select_font_face("Monospace", FontSlant::Normal, FontWeight::Normal);
-                        cr.set_font_size(13.0);
-                        let text = format!("{:04.1}mA", i_gridline * 1000.0);
-                        let extents = cr.text_extents(&text);
-                        cr.move_to(
-                            2.0,
-                            height - 20.0 - i_gridline * i_factor + extents.height + 2.0,
-                        );
-                        cr.text_path(&text);
-                        cr.set_source_rgba(1.0, 1.0, 1.0, 1.0);
-                        cr.set_line_width(2.0);
-                        cr.stroke_preserve();
-                        cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
-                        cr.fill();
-                    }
-                }
-
-                for (ix, v_gridline) in linspace(0.0, max_v, 11).enumerate() {
-                    if ix > 0 {
-                        cr.select_font_face("Monospace", FontSlant::Normal, FontWeight::Normal);
-                        cr.set_font_size(13.0);
-                        let text = format!("{:.2}V", v_gridline);
-                        let extents = cr.text_extents(&text);
-                        if ix == 10 {
-                            cr.move_to(
-                                v_gridline * v_factor - extents.width - 2.0,
-                                extents.height + 2.0,
-                            );
-                        } else {
-                            cr.move_to(
-                                v_gridline * v_factor - extents.width / 2.0,
-                                extents.height + 2.0,
-                            );
-                        }
-                        cr.text_path(&text);
-                        cr.set_source_rgba(1.0, 1.0, 1.0, 1.0);
-                        cr.set_line_width(2.0);
-                        cr.stroke_preserve();
-                        cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
-                        cr.fill();
-                    }
-                }
-
-                self.model.trace.draw_model(&cr, v_factor, i_factor, height);
+                Plot::new(
+                    f64::from(allocation.width),
+                    f64::from(allocation.height),
+                    self.model.v_zoom,
+                    self.model.i_zoom,
+                )
+                .draw(&cr, &*self.model.trace);
             }
             Msg::DeviceType(device_type) => {
-                let device = device_type.to_device();
-                self.widgets
-                    .device_config
-                    .stream()
-                    .emit(DeviceConfigMsg::SetConfig(device.config()));
-
-                self.model.device = device;
-                self.widgets
-                    .connection_hint_text
-                    .set_markup(device_type.connection_hint());
+                if self.model.device.device_type() != device_type {
+                    self.select_device(device_type.to_device());
+                }
             }
             Msg::UpdateConfig(config) => {
                 self.model.device.set_config(&config);
@@ -348,24 +583,62 @@ impl Update for Win {
                 self.model.relm.stream().emit(Msg::UpdateDrawBuffer);
             }
             Msg::SaveTrace => {
+                if let Some((filename, metadata)) =
+                    save_trace_dialog(&self.widgets.window, &self.model.trace.metadata())
+                {
+                    self.model.trace.set_metadata(metadata);
+                    let _ = self.handle_error(self.model.trace.save_as_csv(&filename));
+                }
+            }
+            Msg::ExportModel => {
                 let dialog = gtk::FileChooserDialog::with_buttons(
-                    Some("Save trace"),
+                    Some("Export model"),
                     Some(&self.widgets.window),
                     FileChooserAction::Save,
                     &[
                         ("_Cancel", ResponseType::Cancel),
-                        ("_Save", ResponseType::Accept),
+                        ("_Export", ResponseType::Accept),
                     ],
                 );
                 dialog.set_do_overwrite_confirmation(true);
+                dialog.set_current_name("DUT.lib");
 
                 if dialog.run() == gtk::ResponseType::Accept {
                     if let Some(filename) = dialog.get_filename() {
-                        let _ = self.model.trace.save_as_csv(&filename);
+                        let res = save_spice_card(
+                            self.model.trace.as_ref(),
+                            &filename,
+                            self.model.opt.temperature(),
+                        );
+                        let _ = self.handle_error(res);
                     }
                 }
                 dialog.close();
             }
+            Msg::EditSettings => {
+                if let Some(config) =
+                    ad2_settings_dialog(&self.widgets.window, &self.model.ad2_config)
+                {
+                    if self.handle_error(config.validate()).is_some() {
+                        self.model.ad2_config = config;
+                    }
+                }
+            }
+            Msg::PickDevice => {
+                if let Some(devices) = self.handle_error(list_devices()) {
+                    if let Some(selection) = device_picker_dialog(
+                        &self.widgets.window,
+                        &devices,
+                        &self.model.device_selection,
+                    ) {
+                        let resolved = selection.resolve(&devices).map(|_| ());
+                        if self.handle_error(resolved).is_some() {
+                            self.handle_error(selection.remember());
+                            self.model.device_selection = selection;
+                        }
+                    }
+                }
+            }
             Msg::LoadTrace => {
                 let dialog = gtk::FileChooserDialog::with_buttons(
                     Some("Load trace"),
@@ -381,6 +654,22 @@ impl Update for Win {
                 if dialog.run() == gtk::ResponseType::Accept {
                     if let Some(filename) = dialog.get_filename() {
                         let res = (|| {
+                            match detect_device_type(&filename) {
+                                Ok(detection) => {
+                                    info!("Detected a {} trace", detection.device_type);
+                                    self.select_device(detection.device());
+                                }
+                                Err(err) => match err.downcast_ref::<TracerError>() {
+                                    Some(TracerError::AmbiguousTrace { .. }) => {
+                                        self.error_box(&format!(
+                                            "{}\nLoading it as a {} trace",
+                                            describe(&err),
+                                            self.model.device.device_type()
+                                        ));
+                                    }
+                                    _ => return Err(err),
+                                },
+                            }
                             self.model.trace =
                                 self.model.device.load_from_csv(filename)?.as_gui_trace();
                             info!("Got the trace");
@@ -492,28 +781,42 @@ impl Widget for Win {
             Some((button_box, last_button))
         }
 
+        fn radio_buttons(button_box: &gtk::ButtonBox) -> Vec<gtk::RadioButton> {
+            button_box
+                .get_children()
+                .into_iter()
+                .filter_map(|child| child.downcast::<gtk::RadioButton>().ok())
+                .collect()
+        }
+
+        let mut device_type_buttons = Vec::new();
         {
-            let options = [
+            let device_types = [
                 SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Diode),
+                SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Zener),
                 SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::NPN),
                 SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::PNP),
-            ]
-            .iter()
-            .copied()
-            .map(|d| (format!("{}", d), Msg::DeviceType(d)));
+            ];
+            let options = device_types
+                .iter()
+                .copied()
+                .map(|d| (format!("{}", d), Msg::DeviceType(d)));
             let (buttons, last_button) = radio_button_box(&relm, options, 0, None).unwrap();
+            device_type_buttons.extend(device_types.iter().copied().zip(radio_buttons(&buttons)));
             right_pane.add(&buttons);
 
-            let options = [
+            let device_types = [
                 SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::NEFET),
                 SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::PEFET),
                 SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::NDFET),
                 SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::PDFET),
-            ]
-            .iter()
-            .copied()
-            .map(|d| (format!("{}", d), Msg::DeviceType(d)));
+            ];
+            let options = device_types
+                .iter()
+                .copied()
+                .map(|d| (format!("{}", d), Msg::DeviceType(d)));
             let (buttons, _) = radio_button_box(&relm, options, 0, Some(last_button)).unwrap();
+            device_type_buttons.extend(device_types.iter().copied().zip(radio_buttons(&buttons)));
             right_pane.add(&buttons);
         }
 
@@ -528,14 +831,66 @@ impl Widget for Win {
         trace_button.set_hexpand(true);
         action_box.add(&trace_button);
 
+        let stop_button = Button::new_from_icon_name(Some("process-stop"), gtk::IconSize::Button);
+        stop_button.set_sensitive(false);
+        action_box.add(&stop_button);
+
         let save_button = Button::new_from_icon_name(Some("document-save"), gtk::IconSize::Button);
         action_box.add(&save_button);
 
         let load_button = Button::new_from_icon_name(Some("document-open"), gtk::IconSize::Button);
         action_box.add(&load_button);
 
+        let export_button =
+            Button::new_from_icon_name(Some("document-send"), gtk::IconSize::Button);
+        export_button.set_tooltip_text(Some("Export model"));
+        action_box.add(&export_button);
+
+        let settings_button =
+            Button::new_from_icon_name(Some("preferences-system"), gtk::IconSize::Button);
+        action_box.add(&settings_button);
+
+        let device_button = Button::new_from_icon_name(Some("computer"), gtk::IconSize::Button);
+        action_box.add(&device_button);
+
         right_pane.add(&action_box);
 
+        let live_box = gtk::Box::new(Orientation::Horizontal, 8);
+
+        let live_button = gtk::ToggleButton::new_with_label("Live");
+        live_box.add(&live_button);
+
+        let freeze_button = gtk::ToggleButton::new_with_label("Freeze");
+        freeze_button.set_sensitive(false);
+        live_box.add(&freeze_button);
+
+        let refresh_rate_text = gtk::Label::new(Some(""));
+        refresh_rate_text.set_hexpand(true);
+        refresh_rate_text.set_xalign(1.0);
+        live_box.add(&refresh_rate_text);
+
+        right_pane.add(&live_box);
+
+        let session_box = gtk::Box::new(Orientation::Horizontal, 8);
+
+        let session_button = gtk::ToggleButton::new_with_label("Session");
+        session_button.set_tooltip_text(Some(
+            "Save every trace under the part number and an incrementing serial",
+        ));
+        session_box.add(&session_button);
+
+        let session_text = gtk::Label::new(Some(""));
+        session_text.set_hexpand(true);
+        session_text.set_xalign(1.0);
+        session_box.add(&session_text);
+
+        right_pane.add(&session_box);
+
+        let progress_bar = gtk::ProgressBar::new();
+        progress_bar.set_show_text(true);
+        progress_bar.set_text(Some(""));
+        right_pane.add(&progress_bar);
+
         let device_config = right_pane.add_widget::<DeviceConfigWidget>(model.device.config());
         {
             let relm = relm.clone();
@@ -633,8 +988,30 @@ impl Widget for Win {
             Msg::UpdateDrawBuffer
         );
         connect!(relm, trace_button, connect_clicked(_), Msg::Trace);
+        connect!(relm, stop_button, connect_clicked(_), Msg::StopTrace);
+        connect!(
+            relm,
+            live_button,
+            connect_toggled(button),
+            Msg::Live(button.get_active())
+        );
+        connect!(
+            relm,
+            freeze_button,
+            connect_toggled(button),
+            Msg::Freeze(button.get_active())
+        );
+        connect!(
+            relm,
+            session_button,
+            connect_toggled(button),
+            Msg::Session(button.get_active())
+        );
         connect!(relm, save_button, connect_clicked(_), Msg::SaveTrace);
         connect!(relm, load_button, connect_clicked(_), Msg::LoadTrace);
+        connect!(relm, export_button, connect_clicked(_), Msg::ExportModel);
+        connect!(relm, settings_button, connect_clicked(_), Msg::EditSettings);
+        connect!(relm, device_button, connect_clicked(_), Msg::PickDevice);
         connect!(
             relm,
             window,
@@ -648,7 +1025,16 @@ impl Widget for Win {
                 window,
                 drawing_area,
                 drawing_area_overlay,
+                trace_button,
+                stop_button,
+                live_button,
+                freeze_button,
+                refresh_rate_text,
+                session_button,
+                session_text,
+                progress_bar,
                 device_config,
+                device_type_buttons,
                 model_text,
                 connection_hint_text,
                 legend_text,
@@ -660,7 +1046,20 @@ impl Widget for Win {
 fn main() -> Result<()> {
     let opt = GuiOpt::from_args();
     opt.initialize_logging()?;
-
-    Win::run(ModelParam { opt }).unwrap();
+    let ad2_config = match opt.ad2_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", describe(&e));
+            std::process::exit(exit_code(&e));
+        }
+    };
+    let device_selection = opt.device_selection();
+
+    Win::run(ModelParam {
+        opt,
+        ad2_config,
+        device_selection,
+    })
+    .unwrap();
     Ok(())
 }
@@ -25,6 +25,24 @@ impl AreaOfInterest {
         }
     }
 
+    pub fn new_neg_i_neg_v(i: f64, v: f64) -> Self {
+        Self {
+            min_v: -v,
+            max_v: 0.0,
+            min_i: -i,
+            max_i: 0.0,
+        }
+    }
+
+    pub fn new_bipolar(i: f64, v: f64) -> Self {
+        Self {
+            min_v: -v,
+            max_v: v,
+            min_i: -i,
+            max_i: i,
+        }
+    }
+
     pub fn extended(&self) -> Self {
         let slack = 0.1;
         let v_range = self.max_v - self.min_v;
@@ -38,14 +56,35 @@ impl AreaOfInterest {
     }
 
     pub fn v_polarity(&self) -> f64 {
-        if self.min_v < 0.0 && self.min_v.abs() > 2.0 * self.max_v.abs() {
-            -1.0
-        } else {
-            1.0
-        }
+        polarity(self.min_v, self.max_v)
+    }
+
+    pub fn i_polarity(&self) -> f64 {
+        polarity(self.min_i, self.max_i)
+    }
+
+    pub fn v_bipolar(&self) -> bool {
+        bipolar(self.min_v, self.max_v)
+    }
+
+    pub fn i_bipolar(&self) -> bool {
+        bipolar(self.min_i, self.max_i)
+    }
+}
+
+fn polarity(min: f64, max: f64) -> f64 {
+    if min < 0.0 && min.abs() > 2.0 * max.abs() {
+        -1.0
+    } else {
+        1.0
     }
 }
 
+/// Whether the range reaches well into both polarities, beyond the slack of `extended`
+fn bipolar(min: f64, max: f64) -> bool {
+    min < 0.0 && max > 0.0 && min.abs() > 0.5 * max && max > 0.5 * min.abs()
+}
+
 impl Default for AreaOfInterest {
     fn default() -> Self {
         Self::new_pos_i_pos_v(0.005, 5.0)
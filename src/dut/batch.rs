@@ -0,0 +1,316 @@
+//! Analysis of many saved traces at once, such as those of an incoming inspection
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+use crate::dut::report::TraceReport;
+use crate::dut::trace::GuiTrace;
+use crate::error::TracerError;
+use crate::model::Param;
+use crate::Result;
+
+/// Format of the summary table
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SummaryFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for SummaryFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(SummaryFormat::Csv),
+            "json" => Ok(SummaryFormat::Json),
+            _ => Err(TracerError::invalid_argument(&format!(
+                "Unknown summary format {}, expected csv or json",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AnalyzedTrace {
+    pub file: PathBuf,
+    #[serde(flatten)]
+    pub report: TraceReport,
+}
+
+/// A file that was skipped, and why
+#[derive(Serialize, Clone, Debug)]
+pub struct FailedTrace {
+    pub file: PathBuf,
+    pub error: String,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct BatchSummary {
+    pub traces: Vec<AnalyzedTrace>,
+    pub failures: Vec<FailedTrace>,
+}
+
+fn is_trace_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    path.is_file() && (name.ends_with(".csv") || name.ends_with(".csv.gz"))
+}
+
+/// The trace files `inputs` name: the `.csv` and `.csv.gz` files of directories and among the
+/// matches of glob patterns, and files as they are, sorted and deduplicated. Directories that
+/// can't be read fail with `TracerError::Io`.
+pub fn trace_files<S: AsRef<str>>(inputs: &[S]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let input = input.as_ref();
+        let path = Path::new(input);
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path).map_err(|e| TracerError::io(path, e))? {
+                let file = entry.map_err(|e| TracerError::io(path, e))?.path();
+                if is_trace_file(&file) {
+                    files.push(file);
+                }
+            }
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        } else {
+            let pattern = glob::glob(input).map_err(|e| {
+                TracerError::invalid_argument(&format!("Bad pattern {}: {}", input, e))
+            })?;
+            for file in pattern {
+                let file = file.map_err(|e| {
+                    let path = e.path().to_path_buf();
+                    TracerError::io(&path, e.into_error())
+                })?;
+                if is_trace_file(&file) {
+                    files.push(file);
+                }
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn analyze_file<F>(path: &Path, load: &F) -> Result<TraceReport>
+where
+    F: Fn(&Path) -> Result<Box<dyn GuiTrace>>,
+{
+    let mut trace = load(path)?;
+    trace.fill_model();
+    let report = TraceReport::new(trace.as_ref());
+    if report.params.is_empty() {
+        return Err(TracerError::FitFailed {
+            message: "Can't fit a model to the trace".to_string(),
+        }
+        .into());
+    }
+    Ok(report)
+}
+
+/// Loads each file with `load` and fits its model, in parallel. Files that fail to load or
+/// fit are listed with the error rather than failing the batch.
+pub fn analyze<F>(files: &[PathBuf], load: F) -> BatchSummary
+where
+    F: Fn(&Path) -> Result<Box<dyn GuiTrace>> + Sync,
+{
+    let results = files
+        .par_iter()
+        .map(|file| (file, analyze_file(file, &load)))
+        .collect::<Vec<_>>();
+
+    let mut summary = BatchSummary::default();
+    for (file, result) in results {
+        match result {
+            Ok(report) => summary.traces.push(AnalyzedTrace {
+                file: file.clone(),
+                report,
+            }),
+            Err(e) => summary.failures.push(FailedTrace {
+                file: file.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    summary
+}
+
+/// Column names of `params`, suffixed with their occurrence if a name repeats, as `hfe` does
+/// once per bias level
+pub(crate) fn param_columns(params: &[Param]) -> Vec<String> {
+    let mut counts = BTreeMap::new();
+    for param in params {
+        *counts.entry(param.name.as_str()).or_insert(0) += 1;
+    }
+    let mut seen = BTreeMap::new();
+    params
+        .iter()
+        .map(|param| {
+            if counts[param.name.as_str()] > 1 {
+                let ix = seen.entry(param.name.as_str()).or_insert(0);
+                *ix += 1;
+                format!("{}.{}", param.name, ix)
+            } else {
+                param.name.clone()
+            }
+        })
+        .collect()
+}
+
+impl BatchSummary {
+    /// Writes one row per analyzed file, with a value and an uncertainty column for every
+    /// parameter any of them has
+    pub fn write_csv<W: Write>(&self, out: W) -> Result<()> {
+        let rows = self
+            .traces
+            .iter()
+            .map(|trace| {
+                param_columns(&trace.report.params)
+                    .into_iter()
+                    .zip(trace.report.params.iter())
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut columns: Vec<String> = Vec::new();
+        for trace in &self.traces {
+            for column in param_columns(&trace.report.params) {
+                if !columns.contains(&column) {
+                    columns.push(column);
+                }
+            }
+        }
+
+        let mut writer = csv::Writer::from_writer(out);
+        let mut header = vec![
+            "file".to_string(),
+            "device_type".to_string(),
+            "part_number".to_string(),
+            "bias_levels".to_string(),
+            "r_squared".to_string(),
+        ];
+        for column in &columns {
+            header.push(column.clone());
+            header.push(format!("{}_u95", column));
+        }
+        writer.write_record(&header)?;
+
+        let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+        for (trace, row) in self.traces.iter().zip(rows.iter()) {
+            let report = &trace.report;
+            let mut record = vec![
+                trace.file.display().to_string(),
+                report
+                    .device_type
+                    .map(|device_type| device_type.to_string())
+                    .unwrap_or_default(),
+                report.part_number.clone().unwrap_or_default(),
+                report.bias_levels.len().to_string(),
+                optional(report.quality.as_ref().map(|quality| quality.r_squared)),
+            ];
+            for column in &columns {
+                let param = row.get(column);
+                record.push(optional(param.map(|param| param.value)));
+                record.push(optional(param.and_then(|param| param.uncertainty)));
+            }
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, format: SummaryFormat, mut out: W) -> Result<()> {
+        match format {
+            SummaryFormat::Csv => self.write_csv(out),
+            SummaryFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, self)?;
+                writeln!(out)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use crate::dut::batch::{analyze, trace_files, SummaryFormat};
+    use crate::dut::trace::{GuiTrace, ShareableTrace};
+    use crate::dut::{detect_device_type, Device};
+    use crate::error::TracerError;
+    use crate::Result;
+
+    fn load(path: &Path) -> Result<Box<dyn GuiTrace>> {
+        Ok(detect_device_type(path)?
+            .device()
+            .load_from_csv(path)?
+            .as_gui_trace())
+    }
+
+    #[test]
+    pub fn finds_the_traces_of_a_directory() {
+        let files = trace_files(&["res"]).unwrap();
+        assert!(files.contains(&PathBuf::from("res/1N4148.csv")));
+        assert!(files.contains(&PathBuf::from("res/2N3904.csv.gz")));
+        assert!(files
+            .iter()
+            .all(|file| file.to_string_lossy().contains(".csv")));
+
+        assert_eq!(
+            trace_files(&["res/1N4148.csv", "res/1N41*.csv"]).unwrap(),
+            vec![PathBuf::from("res/1N4148.csv")]
+        );
+        assert_eq!(trace_files(&["res/*"]).unwrap(), files);
+        assert!(trace_files(&["res/icon*"]).unwrap().is_empty());
+        assert!(matches!(
+            trace_files(&["res/[1N"])
+                .err()
+                .unwrap()
+                .downcast_ref::<TracerError>(),
+            Some(TracerError::InvalidArgument { .. })
+        ));
+    }
+
+    #[test]
+    pub fn reports_files_that_fail() {
+        let files = vec![
+            PathBuf::from("res/1N4148.csv"),
+            PathBuf::from("res/2N3904.csv.gz"),
+            PathBuf::from("res/missing.csv"),
+        ];
+        let summary = analyze(&files, load);
+        assert_eq!(summary.traces.len(), 2);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.failures[0].file, PathBuf::from("res/missing.csv"));
+
+        let mut csv = Vec::new();
+        summary.write(SummaryFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("file,device_type,part_number,bias_levels,r_squared"));
+        assert!(header.contains(",hfe.1,hfe.1_u95,"));
+        assert_eq!(lines.count(), 2);
+
+        let mut json = Vec::new();
+        summary.write(SummaryFormat::Json, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            json["traces"][1]["bias_levels"].as_array().unwrap().len(),
+            5
+        );
+        assert_eq!(json["failures"][0]["file"], "res/missing.csv");
+    }
+}
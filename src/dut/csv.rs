@@ -1,42 +1,67 @@
 use crate::backend::RawTrace;
 use crate::dut::aoi::AreaOfInterest;
+use crate::dut::meta::{FileHeader, FORMAT_VERSION};
 use crate::dut::trace::{ThreeTerminalTrace, TwoTerminalTrace};
+use crate::error::TracerError;
 use crate::Result;
 use noisy_float::prelude::r64;
 use serde::de::DeserializeOwned;
 use std::collections::btree_map::BTreeMap;
 use std::ffi::OsStr;
 use std::fs::File;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 
 pub trait CsvWriter {
     fn write_record(&mut self, record: &[&str]) -> Result<()>;
     fn close(self: Box<Self>) -> Result<()>;
 }
 
-impl<W: std::io::Write> CsvWriter for csv::Writer<libflate::gzip::Encoder<W>> {
+/// A CSV writer that tells the file it failed to write to
+struct FileCsvWriter<W: std::io::Write> {
+    path: PathBuf,
+    writer: csv::Writer<W>,
+}
+
+impl<W: std::io::Write> FileCsvWriter<W> {
     fn write_record(&mut self, record: &[&str]) -> Result<()> {
-        csv::Writer::write_record(self, record)?;
+        self.writer
+            .write_record(record)
+            .map_err(|e| TracerError::csv(&self.path, e))?;
         Ok(())
     }
 
+    fn into_inner(self) -> Result<W> {
+        let path = self.path;
+        self.writer.into_inner().map_err(|e| {
+            let cause = std::io::Error::new(e.error().kind(), e.error().to_string());
+            TracerError::io(&path, cause).into()
+        })
+    }
+}
+
+impl<W: std::io::Write> CsvWriter for FileCsvWriter<libflate::gzip::Encoder<W>> {
+    fn write_record(&mut self, record: &[&str]) -> Result<()> {
+        FileCsvWriter::write_record(self, record)
+    }
+
     fn close(self: Box<Self>) -> Result<()> {
-        self.into_inner()
-            .map_err(|_| failure::err_msg("Error writing the file"))?
+        let path = self.path.clone();
+        self.into_inner()?
             .finish()
-            .into_result()?;
+            .into_result()
+            .map_err(|e| TracerError::io(&path, e))?;
         Ok(())
     }
 }
 
-impl CsvWriter for csv::Writer<std::fs::File> {
+impl CsvWriter for FileCsvWriter<File> {
     fn write_record(&mut self, record: &[&str]) -> Result<()> {
-        csv::Writer::write_record(self, record)?;
-        Ok(())
+        FileCsvWriter::write_record(self, record)
     }
 
     fn close(self: Box<Self>) -> Result<()> {
-        self.into_inner().map_err(failure::Error::from)?;
+        self.into_inner()?;
         Ok(())
     }
 }
@@ -45,31 +70,114 @@ fn is_gz(path: &Path) -> bool {
     path.extension().and_then(OsStr::to_str) == Some("gz")
 }
 
-pub fn csv_writer_from_path(path: &Path) -> Result<Box<dyn CsvWriter>> {
+/// The header as TOML commented out for CSV readers
+pub fn header_preamble(header: &FileHeader) -> Result<String> {
+    let mut preamble = String::new();
+    for line in header.to_toml()?.lines() {
+        preamble.push_str("# ");
+        preamble.push_str(line);
+        preamble.push('\n');
+    }
+    Ok(preamble)
+}
+
+/// Reads the commented header of a trace file, the default one for files without it. Headers
+/// that don't parse are ignored, but files of a newer format are refused.
+pub fn read_header(path: &Path) -> Result<FileHeader> {
+    let io_error = |e| TracerError::io(path, e);
+    let file = File::open(path).map_err(io_error)?;
+    let reader: Box<dyn BufRead> = if is_gz(&path) {
+        Box::new(BufReader::new(
+            libflate::gzip::Decoder::new(file).map_err(io_error)?,
+        ))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let mut preamble = String::new();
+    for line in reader.lines() {
+        let line = line.map_err(io_error)?;
+        if !line.starts_with('#') {
+            break;
+        }
+        let line = &line[1..];
+        preamble.push_str(if line.starts_with(' ') {
+            &line[1..]
+        } else {
+            line
+        });
+        preamble.push('\n');
+    }
+
+    let header = FileHeader::from_toml(&preamble).unwrap_or_else(|e| {
+        warn!("Ignoring the header of {}: {}", path.display(), e);
+        FileHeader::default()
+    });
+    if header.format > FORMAT_VERSION {
+        return Err(TracerError::csv_schema(
+            path,
+            &format!(
+                "The trace is in format version {}, newer than the supported {}",
+                header.format, FORMAT_VERSION
+            ),
+        )
+        .into());
+    }
+    Ok(header)
+}
+
+pub fn csv_writer_from_path(path: &Path, preamble: &str) -> Result<Box<dyn CsvWriter>> {
+    let io_error = |e| TracerError::io(path, e);
     let mut out_builder = csv::WriterBuilder::new();
     out_builder.delimiter(b'\t');
     Ok(if is_gz(&path) {
-        Box::new(out_builder.from_writer(libflate::gzip::Encoder::new(File::create(path)?)?))
+        let mut out = libflate::gzip::Encoder::new(File::create(path).map_err(io_error)?)
+            .map_err(io_error)?;
+        out.write_all(preamble.as_bytes()).map_err(io_error)?;
+        Box::new(FileCsvWriter {
+            path: path.to_path_buf(),
+            writer: out_builder.from_writer(out),
+        })
     } else {
-        Box::new(out_builder.from_path(path)?)
+        let mut out = File::create(path).map_err(io_error)?;
+        out.write_all(preamble.as_bytes()).map_err(io_error)?;
+        Box::new(FileCsvWriter {
+            path: path.to_path_buf(),
+            writer: out_builder.from_writer(out),
+        })
     })
 }
 
+/// Records fail with `TracerError::CsvSchema` if they don't deserialize into `D`
 pub fn csv_reader_from_path<D: DeserializeOwned + 'static>(
     path: &Path,
-) -> Result<Box<dyn Iterator<Item = csv::Result<D>>>> {
+) -> Result<Box<dyn Iterator<Item = Result<D>>>> {
+    let io_error = |e| TracerError::io(path, e);
     let mut builder = csv::ReaderBuilder::new();
     builder.has_headers(true);
     builder.delimiter(b'\t');
-    Ok(if is_gz(&path) {
+    builder.comment(Some(b'#'));
+    let records: Box<dyn Iterator<Item = csv::Result<D>>> = if is_gz(&path) {
         Box::new(
             builder
-                .from_reader(libflate::gzip::Decoder::new(File::open(path)?)?)
+                .from_reader(
+                    libflate::gzip::Decoder::new(File::open(path).map_err(io_error)?)
+                        .map_err(io_error)?,
+                )
                 .into_deserialize(),
         )
     } else {
-        Box::new(builder.from_path(path)?.into_deserialize())
-    })
+        Box::new(
+            builder
+                .from_path(path)
+                .map_err(|e| TracerError::csv(path, e))?
+                .into_deserialize(),
+        )
+    };
+    let path = path.to_path_buf();
+    Ok(Box::new(records.map(move |record| {
+        record.map_err(|e| TracerError::csv(&path, e).into())
+    })))
 }
 
 #[derive(Deserialize)]
@@ -84,6 +192,7 @@ pub fn load3_from_csv<P: AsRef<Path>>(
     reverse_order: bool,
     aoi: AreaOfInterest,
 ) -> Result<ThreeTerminalTrace> {
+    let header = read_header(path.as_ref())?;
     let mut traces = BTreeMap::new();
 
     for result in csv_reader_from_path(path.as_ref())? {
@@ -105,9 +214,11 @@ pub fn load3_from_csv<P: AsRef<Path>>(
             .map(|(bias, (vs, is))| {
                 (
                     bias,
-                    TwoTerminalTrace::from_raw_trace(RawTrace::new(is, vs), aoi),
+                    TwoTerminalTrace::from_raw_trace(RawTrace::new(is, vs), aoi)
+                        .with_capture_config(header.ad2.clone()),
                 )
             })
             .collect(),
-    ))
+    )
+    .with_metadata(header.meta))
 }
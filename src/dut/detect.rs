@@ -0,0 +1,354 @@
+//! Tells what kind of device a trace file is of, from its header or else its samples
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use noisy_float::prelude::{r64, R64};
+
+use crate::backend::SweepRange;
+use crate::dut::csv::{csv_reader_from_path, read_header};
+use crate::dut::{
+    BiasDrive, CurrentBiasedDevice, CurrentBiasedDeviceConfig, CurrentBiasedDeviceType, Device,
+    SomeDevice, SomeDeviceType, TwoTerminalDevice, TwoTerminalDeviceConfig, TwoTerminalDeviceType,
+    VoltageBiasedDevice, VoltageBiasedDeviceConfig, VoltageBiasedDeviceType,
+};
+use crate::error::TracerError;
+use crate::Result;
+
+/// Voltages closer to zero than this don't count towards the polarity of a sweep
+const POLARITY_THRESHOLD_V: f64 = 0.1;
+
+/// Share of the samples of the minority polarity above which a sweep counts as bipolar
+const BIPOLAR_SHARE: f64 = 0.1;
+
+/// Bias levels of a larger magnitude are taken for gate voltages, smaller ones for base currents
+const BIAS_CURRENT_LIMIT: f64 = 0.01;
+
+/// A two-terminal device conducting this much in reverse...
+const BREAKDOWN_CURRENT: f64 = 0.001;
+
+/// ...only past this reverse voltage is taken for a zener
+const MIN_BREAKDOWN_V: f64 = 1.5;
+
+#[derive(Deserialize)]
+struct Sample {
+    v: f64,
+    i: f64,
+    bias: Option<f64>,
+}
+
+/// What a trace file holds
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detection {
+    pub device_type: SomeDeviceType,
+    /// Whether the device type comes from the file header rather than a guess
+    pub from_header: bool,
+    pub sweep_range: SweepRange,
+    /// Distinct bias levels, empty for two-terminal traces
+    pub bias_levels: Vec<R64>,
+}
+
+impl Detection {
+    /// A device of the detected type configured like the one traced
+    pub fn device(&self) -> SomeDevice {
+        let (min_bias, max_bias) = self
+            .bias_levels
+            .iter()
+            .map(|bias| bias.abs())
+            .fold(None, |range, bias| match range {
+                None => Some((bias, bias)),
+                Some((min, max)) => Some((bias.min(min), bias.max(max))),
+            })
+            .unwrap_or((r64(0.0), r64(0.0)));
+
+        match self.device_type {
+            SomeDeviceType::TwoTerminal(device_type) => {
+                let mut device = TwoTerminalDevice::from_type(device_type);
+                device.set_config(&TwoTerminalDeviceConfig {
+                    sweep_range: self.sweep_range,
+                    ..device.config()
+                });
+                SomeDevice::TwoTerminal(device)
+            }
+            SomeDeviceType::VoltageBiased(device_type) => {
+                let mut device = VoltageBiasedDevice::from_type(device_type);
+                device.set_config(&VoltageBiasedDeviceConfig {
+                    min_bias_voltage: min_bias,
+                    max_bias_voltage: max_bias,
+                    ..device.config()
+                });
+                SomeDevice::VoltageBiased(device)
+            }
+            SomeDeviceType::CurrentBiased(device_type) => {
+                let mut device = CurrentBiasedDevice::from_type(device_type);
+                device.set_config(&CurrentBiasedDeviceConfig {
+                    min_bias_current: min_bias,
+                    max_bias_current: max_bias,
+                    ..device.config()
+                });
+                SomeDevice::CurrentBiased(device)
+            }
+        }
+    }
+}
+
+/// Sign shared by all the values, ignoring zeros, `None` if they disagree or are all zero
+fn common_sign(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let mut sign = None;
+    for value in values.filter(|&value| value != 0.0) {
+        match sign {
+            None => sign = Some(value.signum()),
+            Some(s) if s != value.signum() => return None,
+            _ => {}
+        }
+    }
+    sign
+}
+
+fn sweep_range(positive: usize, negative: usize) -> SweepRange {
+    let total = (positive + negative) as f64;
+    if positive as f64 > total * BIPOLAR_SHARE && negative as f64 > total * BIPOLAR_SHARE {
+        SweepRange::Bipolar
+    } else if negative > positive {
+        SweepRange::Negative
+    } else {
+        SweepRange::Positive
+    }
+}
+
+fn guess_two_terminal(samples: &[Sample]) -> TwoTerminalDeviceType {
+    let breakdown_v = samples
+        .iter()
+        .filter(|s| s.i < -BREAKDOWN_CURRENT)
+        .map(|s| s.v)
+        .fold(None, |max: Option<f64>, v| {
+            Some(max.map_or(v, |max| max.max(v)))
+        });
+    match breakdown_v {
+        Some(v) if v < -MIN_BREAKDOWN_V => TwoTerminalDeviceType::Zener,
+        _ => TwoTerminalDeviceType::Diode,
+    }
+}
+
+fn guess_three_terminal(
+    path: &Path,
+    bias_drive: Option<BiasDrive>,
+    sweep_range: SweepRange,
+    bias_levels: &[R64],
+) -> Result<SomeDeviceType> {
+    let ambiguous = |message: &str| -> Result<SomeDeviceType> {
+        Err(TracerError::ambiguous_trace(path, message).into())
+    };
+
+    let polarity = match sweep_range {
+        SweepRange::Positive => 1.0,
+        SweepRange::Negative => -1.0,
+        SweepRange::Bipolar => {
+            return ambiguous("the sweep covers both polarities, can't tell N from P type")
+        }
+    };
+    let bias_polarity = match common_sign(bias_levels.iter().map(|bias| bias.raw())) {
+        Some(sign) => sign,
+        None => return ambiguous("the bias levels are all zero or of both signs"),
+    };
+    let max_bias = bias_levels
+        .iter()
+        .map(|bias| bias.raw().abs())
+        .fold(0.0, f64::max);
+    let bias_drive = bias_drive.unwrap_or(if max_bias < BIAS_CURRENT_LIMIT {
+        BiasDrive::Current
+    } else {
+        BiasDrive::Voltage
+    });
+
+    Ok(match (bias_drive, polarity > 0.0, bias_polarity > 0.0) {
+        (BiasDrive::Current, true, true) => {
+            SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::NPN)
+        }
+        (BiasDrive::Current, false, false) => {
+            SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::PNP)
+        }
+        (BiasDrive::Current, _, _) => {
+            return ambiguous("the base current and collector voltage are of opposite signs")
+        }
+        (BiasDrive::Voltage, true, true) => {
+            SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::NEFET)
+        }
+        (BiasDrive::Voltage, true, false) => {
+            SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::NDFET)
+        }
+        (BiasDrive::Voltage, false, false) => {
+            SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::PEFET)
+        }
+        (BiasDrive::Voltage, false, true) => {
+            SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::PDFET)
+        }
+    })
+}
+
+/// Detects the device type of a trace file. The type recorded in the header wins, otherwise
+/// it's guessed from the columns, the signs of the voltages and bias levels and their magnitude.
+/// Fails with `TracerError::AmbiguousTrace` if the samples don't point at a single type.
+pub fn detect_device_type<P: AsRef<Path>>(path: P) -> Result<Detection> {
+    let path = path.as_ref();
+    let header = read_header(path)?;
+
+    let mut samples = Vec::new();
+    for result in csv_reader_from_path(path)? {
+        let sample: Sample = result?;
+        samples.push(sample);
+    }
+    if samples.is_empty() {
+        return Err(TracerError::csv_schema(path, "contains no samples").into());
+    }
+
+    let biased = samples[0].bias.is_some();
+    let bias_levels = samples
+        .iter()
+        .filter_map(|s| s.bias.map(r64))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let count = |sign: f64| {
+        samples
+            .iter()
+            .filter(|s| s.v * sign > POLARITY_THRESHOLD_V)
+            .count()
+    };
+    let sweep_range = sweep_range(count(1.0), count(-1.0));
+
+    let from_header = header.meta.device_type.is_some();
+    let device_type = match header.meta.device_type {
+        Some(device_type) => {
+            if biased == matches!(device_type, SomeDeviceType::TwoTerminal(_)) {
+                return Err(TracerError::csv_schema(
+                    path,
+                    &format!(
+                        "the header says it's a {} trace, but the file {} a bias column",
+                        device_type,
+                        if biased { "has" } else { "lacks" }
+                    ),
+                )
+                .into());
+            }
+            device_type
+        }
+        None if biased => {
+            guess_three_terminal(path, header.meta.bias_drive, sweep_range, &bias_levels)?
+        }
+        None => SomeDeviceType::TwoTerminal(guess_two_terminal(&samples)),
+    };
+
+    Ok(Detection {
+        device_type,
+        from_header,
+        sweep_range,
+        bias_levels,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backend::SweepRange;
+    use crate::dut::detect::detect_device_type;
+    use crate::dut::{
+        CurrentBiasedDeviceType, Device, SomeDevice, SomeDeviceType, TwoTerminalDeviceType,
+        VoltageBiasedDeviceType,
+    };
+    use crate::error::{exit_code, TracerError};
+    use noisy_float::prelude::r64;
+
+    fn detect_in(name: &str, contents: &str) -> crate::Result<SomeDeviceType> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, contents).unwrap();
+        let detection = detect_device_type(&path);
+        std::fs::remove_file(&path).unwrap();
+        detection.map(|detection| detection.device_type)
+    }
+
+    #[test]
+    pub fn detects_saved_traces() {
+        let diode = detect_device_type("res/1N4148.csv").unwrap();
+        assert_eq!(
+            diode.device_type,
+            SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Diode)
+        );
+        assert_eq!(diode.sweep_range, SweepRange::Positive);
+        assert!(!diode.from_header);
+
+        let npn = detect_device_type("res/2N3904.csv.gz").unwrap();
+        assert_eq!(
+            npn.device_type,
+            SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::NPN)
+        );
+        assert_eq!(npn.bias_levels.len(), 5);
+
+        let pnp = detect_device_type("res/2N3906.csv.gz").unwrap();
+        assert_eq!(
+            pnp.device_type,
+            SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::PNP)
+        );
+        match pnp.device() {
+            SomeDevice::CurrentBiased(device) => {
+                assert_eq!(device.config().min_bias_current, r64(0.000_010));
+                assert_eq!(device.config().max_bias_current, r64(0.000_050));
+            }
+            _ => panic!("not a current-biased device"),
+        }
+    }
+
+    #[test]
+    pub fn detects_from_signs() {
+        assert_eq!(
+            detect_in(
+                "ks-curve-tracer-zener.csv",
+                "v\ti\n0.7\t0.01\n-1.0\t-0.000001\n-5.1\t-0.005\n"
+            )
+            .unwrap(),
+            SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Zener)
+        );
+        assert_eq!(
+            detect_in(
+                "ks-curve-tracer-ndfet.csv",
+                "v\ti\tbias\n1.0\t0.01\t-1.0\n2.0\t0.02\t0.0\n"
+            )
+            .unwrap(),
+            SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::NDFET)
+        );
+        assert_eq!(
+            detect_in(
+                "ks-curve-tracer-pefet.csv",
+                "v\ti\tbias\n-1.0\t-0.01\t-2.5\n-2.0\t-0.02\t-5.0\n"
+            )
+            .unwrap(),
+            SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::PEFET)
+        );
+    }
+
+    #[test]
+    pub fn header_wins() {
+        assert_eq!(
+            detect_in(
+                "ks-curve-tracer-header.csv",
+                "# format = 2\n# [meta]\n# device_type = \"PDFET\"\nv\ti\tbias\n\
+                 -1.0\t-0.01\t-2.5\n"
+            )
+            .unwrap(),
+            SomeDeviceType::VoltageBiased(VoltageBiasedDeviceType::PDFET)
+        );
+    }
+
+    #[test]
+    pub fn reports_ambiguity() {
+        let err = detect_in(
+            "ks-curve-tracer-ambiguous.csv",
+            "v\ti\tbias\n1.0\t0.01\t0.0\n-1.0\t-0.01\t0.0\n",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TracerError>(),
+            Some(TracerError::AmbiguousTrace { .. })
+        ));
+        assert_eq!(exit_code(&err), 8);
+    }
+}
@@ -1,4 +1,6 @@
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 use crate::dut::two::TwoTerminalDevice;
 use crate::dut::CurrentBiasedDevice;
@@ -6,8 +8,11 @@ use crate::dut::{
     CurrentBiasedDeviceType, Device, SomeDevice, TwoTerminalDeviceType, VoltageBiasedDevice,
     VoltageBiasedDeviceType,
 };
+use crate::error::TracerError;
+use crate::Result;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(into = "String", try_from = "String")]
 pub enum SomeDeviceType {
     TwoTerminal(TwoTerminalDeviceType),
     VoltageBiased(VoltageBiasedDeviceType),
@@ -24,6 +29,9 @@ impl SomeDeviceType {
     pub fn connection_hint(self) -> &'static str {
         match self {
             SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Diode) => "Top row: AKKKKKK",
+            SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Zener) => {
+                "Top row: AKKKKKK (cathode band towards K)"
+            }
             SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::NPN) => "Bottom row: CBECBEC",
             SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::PNP) => {
                 "Bottom row: EBCEBCE (reversed E/C)"
@@ -45,8 +53,8 @@ impl DeviceType for SomeDeviceType {
 
     fn to_device(&self) -> Self::Device {
         match *self {
-            SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Diode) => {
-                SomeDevice::TwoTerminal(TwoTerminalDevice::Diode)
+            SomeDeviceType::TwoTerminal(t) => {
+                SomeDevice::TwoTerminal(TwoTerminalDevice::from_type(t))
             }
             SomeDeviceType::CurrentBiased(t) => {
                 SomeDevice::CurrentBiased(CurrentBiasedDevice::from_type(t))
@@ -67,3 +75,32 @@ impl Display for SomeDeviceType {
         }
     }
 }
+
+impl FromStr for SomeDeviceType {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        s.parse()
+            .map(SomeDeviceType::TwoTerminal)
+            .or_else(|_| s.parse().map(SomeDeviceType::VoltageBiased))
+            .or_else(|_| s.parse().map(SomeDeviceType::CurrentBiased))
+            .map_err(|_| {
+                TracerError::invalid_argument(&format!("unknown device type {:?}", s)).into()
+            })
+    }
+}
+
+impl TryFrom<String> for SomeDeviceType {
+    type Error = failure::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<SomeDeviceType> for String {
+    fn from(device_type: SomeDeviceType) -> Self {
+        device_type.to_string()
+    }
+}
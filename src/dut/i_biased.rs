@@ -1,7 +1,8 @@
-use crate::backend::{Backend, BiasedTrace};
+use crate::backend::{Backend, BiasedTrace, TraceControl};
 use crate::dut::aoi::AreaOfInterest;
-use crate::dut::trace::{ThreeTerminalTrace, TwoTerminalTrace};
-use crate::dut::{BiasDrive, Device};
+use crate::dut::meta::TraceMetadata;
+use crate::dut::trace::{ThreeTerminalModelKind, ThreeTerminalTrace, TwoTerminalTrace};
+use crate::dut::{BiasDrive, Device, SomeDeviceType};
 use crate::Result;
 use itertools::Itertools;
 use itertools_num::linspace;
@@ -16,6 +17,8 @@ use std::str::FromStr;
 pub struct CurrentBiasedDeviceConfig {
     pub min_bias_current: R64,
     pub max_bias_current: R64,
+    /// Collector current VCE(sat) is measured at
+    pub sat_current: f64,
 }
 
 impl Default for CurrentBiasedDeviceConfig {
@@ -23,6 +26,7 @@ impl Default for CurrentBiasedDeviceConfig {
         CurrentBiasedDeviceConfig {
             min_bias_current: r64(0.000_010),
             max_bias_current: r64(0.000_050),
+            sat_current: 0.001,
         }
     }
 }
@@ -54,6 +58,12 @@ impl CurrentBiasedDevice {
         }
     }
 
+    fn model_kind(&self) -> ThreeTerminalModelKind {
+        ThreeTerminalModelKind::Bjt {
+            sat_current: self.config.sat_current,
+        }
+    }
+
     pub fn bias_levels(&self) -> Vec<R64> {
         let polarity = self.polarity();
 
@@ -84,22 +94,40 @@ impl Device for CurrentBiasedDevice {
         }
     }
 
-    fn trace(&self, backend: &dyn Backend) -> Result<Self::Trace> {
+    fn trace(&self, backend: &dyn Backend, control: &TraceControl) -> Result<Self::Trace> {
         let aoi = self.area_of_interest();
+        let capture_config = backend.capture_config();
         Ok(ThreeTerminalTrace::new(
             self.polarity() < 0.0,
             backend
-                .trace_3(self.polarity(), BiasDrive::Current, self.bias_levels())?
+                .trace_3(
+                    self.polarity(),
+                    BiasDrive::Current,
+                    self.bias_levels(),
+                    control,
+                )?
                 .into_iter()
                 .map(|BiasedTrace { bias, trace }| {
-                    (bias, TwoTerminalTrace::from_raw_trace(trace, aoi))
+                    (
+                        bias,
+                        TwoTerminalTrace::from_raw_trace(trace, aoi)
+                            .with_capture_config(capture_config.clone()),
+                    )
                 })
                 .collect(),
+        )
+        .with_model_kind(Some(self.model_kind()))
+        .with_metadata(
+            TraceMetadata::captured(SomeDeviceType::CurrentBiased(self.device_type))
+                .with_bias(BiasDrive::Current, self.polarity().raw()),
         ))
     }
 
     fn load_from_csv<P: AsRef<Path>>(&self, path: P) -> Result<Self::Trace> {
-        load3_from_csv(path, self.polarity() < 0.0, self.area_of_interest())
+        Ok(
+            load3_from_csv(path, self.polarity() < 0.0, self.area_of_interest())?
+                .with_model_kind(Some(self.model_kind())),
+        )
     }
 
     fn config(&self) -> CurrentBiasedDeviceConfig {
@@ -0,0 +1,630 @@
+//! Matching of parts across a measured lot: pairs and quads for differential pairs and ring
+//! modulators, or bins of alike parts
+
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use itertools::Itertools;
+use itertools_num::linspace;
+use rayon::prelude::*;
+
+use crate::dut::batch::{param_columns, FailedTrace, SummaryFormat};
+use crate::dut::trace::GuiTrace;
+use crate::error::TracerError;
+use crate::model::IVModel;
+use crate::Result;
+
+/// Voltage bounds searched for the voltage at a current, for models valid over any voltage
+const MAX_SEARCH_V: f64 = 100.0;
+
+/// Rounds of partner swaps between groups, a bound rarely reached
+const MAX_REFINE_ROUNDS: usize = 100;
+
+/// How alike two parts are, the lower the more
+#[derive(Clone, Debug, PartialEq)]
+pub enum Metric {
+    /// Largest relative difference of the named parameters, a name repeated per bias level, such
+    /// as `hfe`, standing for all of them
+    Params(Vec<String>),
+    /// Largest relative difference of the model currents at `points` voltages across the range,
+    /// on every curve
+    Current {
+        min_v: f64,
+        max_v: f64,
+        points: usize,
+    },
+    /// Largest difference, in V, of the model voltages at `points` currents across the range,
+    /// spaced logarithmically unless it spans zero
+    Voltage {
+        min_i: f64,
+        max_i: f64,
+        points: usize,
+    },
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Params(names) => write!(f, "parameters {}", names.join(", ")),
+            Metric::Current {
+                min_v,
+                max_v,
+                points,
+            } => write!(f, "current at {} points over {}..{}V", points, min_v, max_v),
+            Metric::Voltage {
+                min_i,
+                max_i,
+                points,
+            } => write!(f, "voltage at {} points over {}..{}A", points, min_i, max_i),
+        }
+    }
+}
+
+fn relative_difference(a: f64, b: f64) -> f64 {
+    let scale = (a.abs() + b.abs()) / 2.0;
+    if scale == 0.0 {
+        0.0
+    } else {
+        (a - b).abs() / scale
+    }
+}
+
+/// The voltage `model` conducts `i` at, if it does within its range. Models are monotonic in
+/// the voltage, so it's bisected for.
+fn voltage_at(model: &dyn IVModel, i: f64) -> Option<f64> {
+    let mut lo = model.min_v().max(-MAX_SEARCH_V);
+    let mut hi = model.max_v().min(MAX_SEARCH_V);
+    let rising = model.evaluate(hi) > model.evaluate(lo);
+    let (i_lo, i_hi) = if rising {
+        (model.evaluate(lo), model.evaluate(hi))
+    } else {
+        (model.evaluate(hi), model.evaluate(lo))
+    };
+    if !(i_lo..=i_hi).contains(&i) {
+        return None;
+    }
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if (model.evaluate(mid) < i) == rising {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Some((lo + hi) / 2.0)
+}
+
+impl Metric {
+    /// The values of a part the metric compares, in the same order for parts traced alike. Parts
+    /// whose model gives a value that isn't finite can't be matched.
+    pub fn signature(&self, trace: &dyn GuiTrace) -> Result<Vec<f64>> {
+        let signature: Vec<f64> = match self {
+            Metric::Params(names) => {
+                let params = trace.model_params();
+                let columns = param_columns(&params);
+                let mut signature = Vec::new();
+                for name in names {
+                    let prefix = format!("{}.", name);
+                    let values = columns
+                        .iter()
+                        .zip(params.iter())
+                        .filter(|(column, _)| *column == name || column.starts_with(&prefix))
+                        .map(|(_, param)| param.value)
+                        .collect_vec();
+                    if values.is_empty() {
+                        return Err(TracerError::NotComparable {
+                            message: format!("The model has no {}", name),
+                        }
+                        .into());
+                    }
+                    signature.extend(values);
+                }
+                signature
+            }
+            Metric::Current {
+                min_v,
+                max_v,
+                points,
+            } => {
+                let curves = trace.model_curves();
+                if curves.is_empty() {
+                    return Err(TracerError::FitFailed {
+                        message: "Can't fit a model to the trace".to_string(),
+                    }
+                    .into());
+                }
+                curves
+                    .iter()
+                    .flat_map(|curve| {
+                        linspace(*min_v, *max_v, *points).map(move |v| curve.evaluate(v))
+                    })
+                    .collect()
+            }
+            Metric::Voltage {
+                min_i,
+                max_i,
+                points,
+            } => {
+                let curves = trace.model_curves();
+                if curves.is_empty() {
+                    return Err(TracerError::FitFailed {
+                        message: "Can't fit a model to the trace".to_string(),
+                    }
+                    .into());
+                }
+                let currents = if min_i * max_i > 0.0 {
+                    linspace(min_i.abs().ln(), max_i.abs().ln(), *points)
+                        .map(|ln_i| ln_i.exp() * min_i.signum())
+                        .collect_vec()
+                } else {
+                    linspace(*min_i, *max_i, *points).collect_vec()
+                };
+                let mut signature = Vec::new();
+                for curve in curves {
+                    for &i in &currents {
+                        signature.push(voltage_at(curve, i).ok_or_else(|| {
+                            TracerError::NotComparable {
+                                message: format!("The model doesn't reach {}A", i),
+                            }
+                        })?);
+                    }
+                }
+                signature
+            }
+        };
+        match signature.iter().find(|value| !value.is_finite()) {
+            Some(value) => Err(TracerError::NotComparable {
+                message: format!("The model gives {} for the {}", value, self),
+            }
+            .into()),
+            None => Ok(signature),
+        }
+    }
+
+    /// Distance between two signatures, infinite if the parts weren't traced alike or any of
+    /// their values differ by something that isn't finite
+    pub fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        if a.len() != b.len() {
+            return f64::INFINITY;
+        }
+        let difference = |(a, b): (&f64, &f64)| {
+            let difference = match self {
+                Metric::Params(_) | Metric::Current { .. } => relative_difference(*a, *b),
+                Metric::Voltage { .. } => (a - b).abs(),
+            };
+            if difference.is_finite() {
+                difference
+            } else {
+                f64::INFINITY
+            }
+        };
+        a.iter().zip(b.iter()).map(difference).fold(0.0, f64::max)
+    }
+}
+
+/// How to group the parts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Grouping {
+    Pairs,
+    Quads,
+    /// Groups of any size, every part landing in one
+    Bins,
+}
+
+impl Grouping {
+    fn size(self) -> Option<usize> {
+        match self {
+            Grouping::Pairs => Some(2),
+            Grouping::Quads => Some(4),
+            Grouping::Bins => None,
+        }
+    }
+
+    /// How `group_parts` forms the groups, a heuristic that neither finds the most groups nor
+    /// the least spread for sure
+    pub fn method(self) -> &'static str {
+        match self {
+            Grouping::Pairs | Grouping::Quads => {
+                "greedy, around the parts with the fewest others within tolerance first, then \
+                 refined by swapping members; not guaranteed the most groups or the least spread"
+            }
+            Grouping::Bins => {
+                "greedy, largest bins first; not guaranteed the fewest bins or the least spread"
+            }
+        }
+    }
+}
+
+impl FromStr for Grouping {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pairs" => Ok(Grouping::Pairs),
+            "quads" => Ok(Grouping::Quads),
+            "bins" => Ok(Grouping::Bins),
+            _ => Err(TracerError::invalid_argument(&format!(
+                "Unknown grouping {}, expected pairs, quads or bins",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
+/// Largest distance between two members of `group`
+fn spread(group: &[usize], distances: &[Vec<f64>]) -> f64 {
+    group
+        .iter()
+        .tuple_combinations()
+        .map(|(&a, &b)| distances[a][b])
+        .fold(0.0, f64::max)
+}
+
+/// Grows a group around `seed` from the nearest of `candidates` while every two members stay
+/// within `tolerance` of each other
+fn grow(
+    seed: usize,
+    candidates: &[usize],
+    distances: &[Vec<f64>],
+    tolerance: f64,
+    size: Option<usize>,
+) -> Vec<usize> {
+    let nearest = candidates
+        .iter()
+        .copied()
+        .filter(|&part| part != seed && distances[seed][part] <= tolerance)
+        .sorted_by(|&a, &b| distances[seed][a].partial_cmp(&distances[seed][b]).unwrap())
+        .collect_vec();
+    let mut group = vec![seed];
+    for part in nearest {
+        if size.map_or(false, |size| group.len() >= size) {
+            break;
+        }
+        if group
+            .iter()
+            .all(|&member| distances[member][part] <= tolerance)
+        {
+            group.push(part);
+        }
+    }
+    group
+}
+
+/// Swaps members between groups of a fixed size, and with the parts left out, while that lowers
+/// their total spread and keeps every group within `tolerance`
+fn refine(
+    groups: &mut Vec<Vec<usize>>,
+    unassigned: &mut Vec<usize>,
+    distances: &[Vec<f64>],
+    tolerance: f64,
+) {
+    for _ in 0..MAX_REFINE_ROUNDS {
+        let mut improved = false;
+        for group in groups.iter_mut() {
+            for (i, u) in (0..group.len()).cartesian_product(0..unassigned.len()) {
+                let mut new_group = group.clone();
+                std::mem::swap(&mut new_group[i], &mut unassigned[u]);
+                if spread(&new_group, distances) < spread(group, distances) * (1.0 - 1e-9) {
+                    *group = new_group;
+                    improved = true;
+                } else {
+                    std::mem::swap(&mut new_group[i], &mut unassigned[u]);
+                }
+            }
+        }
+        for (g, h) in (0..groups.len()).tuple_combinations() {
+            for (i, j) in (0..groups[g].len()).cartesian_product(0..groups[h].len()) {
+                let before = spread(&groups[g], distances) + spread(&groups[h], distances);
+                let (mut new_g, mut new_h) = (groups[g].clone(), groups[h].clone());
+                std::mem::swap(&mut new_g[i], &mut new_h[j]);
+                let (spread_g, spread_h) = (spread(&new_g, distances), spread(&new_h, distances));
+                if spread_g <= tolerance
+                    && spread_h <= tolerance
+                    && spread_g + spread_h < before * (1.0 - 1e-9)
+                {
+                    groups[g] = new_g;
+                    groups[h] = new_h;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// Groups parts by their pairwise `distances`, no two members of a group further apart than
+/// `tolerance`. Fixed-size groups are formed around the parts with the fewest others within
+/// tolerance first, so as to form as many as possible, and then refined by swapping members.
+/// Bins are formed largest first. This is a heuristic, for pairs not a minimum-weight maximum
+/// matching: a lot may allow more groups, or tighter ones, than it proposes.
+pub fn group_parts(distances: &[Vec<f64>], tolerance: f64, grouping: Grouping) -> Vec<Vec<usize>> {
+    let size = grouping.size();
+    let mut unassigned = (0..distances.len()).collect_vec();
+    let mut groups = Vec::new();
+    loop {
+        let best = unassigned
+            .iter()
+            .map(|&seed| {
+                let options = unassigned
+                    .iter()
+                    .filter(|&&part| part != seed && distances[seed][part] <= tolerance)
+                    .count();
+                (options, grow(seed, &unassigned, distances, tolerance, size))
+            })
+            .filter(|(_, group)| size.map_or(true, |size| group.len() == size))
+            .map(|(options, group)| (options, spread(&group, distances), group))
+            .min_by(|(options_a, spread_a, a), (options_b, spread_b, b)| {
+                match size {
+                    Some(_) => options_a.cmp(options_b),
+                    None => b.len().cmp(&a.len()),
+                }
+                .then(spread_a.partial_cmp(spread_b).unwrap_or(Ordering::Equal))
+            });
+        match best {
+            Some((_, _, group)) => {
+                unassigned.retain(|part| !group.contains(part));
+                groups.push(group);
+            }
+            None => break,
+        }
+    }
+    if size.is_some() {
+        refine(&mut groups, &mut unassigned, distances, tolerance);
+    }
+    for group in &mut groups {
+        group.sort();
+    }
+    groups
+}
+
+/// A part of the lot and the values it's matched by
+#[derive(Clone, Debug)]
+pub struct Part {
+    pub file: PathBuf,
+    pub signature: Vec<f64>,
+}
+
+/// Loads each file with `load`, fits its model and takes its signature, in parallel
+pub fn load_parts<F>(files: &[PathBuf], load: F, metric: &Metric) -> (Vec<Part>, Vec<FailedTrace>)
+where
+    F: Fn(&Path) -> Result<Box<dyn GuiTrace>> + Sync,
+{
+    let results = files
+        .par_iter()
+        .map(|file| {
+            let signature = load(file).and_then(|mut trace| {
+                trace.fill_model();
+                metric.signature(trace.as_ref())
+            });
+            (file, signature)
+        })
+        .collect::<Vec<_>>();
+
+    let mut parts = Vec::new();
+    let mut failures = Vec::new();
+    for (file, signature) in results {
+        match signature {
+            Ok(signature) => parts.push(Part {
+                file: file.clone(),
+                signature,
+            }),
+            Err(e) => failures.push(FailedTrace {
+                file: file.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    (parts, failures)
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MatchGroup {
+    /// Largest distance between two of the parts
+    pub spread: f64,
+    pub parts: Vec<PathBuf>,
+}
+
+/// The proposed grouping of a lot
+#[derive(Serialize, Clone, Debug)]
+pub struct MatchReport {
+    pub metric: String,
+    pub tolerance: f64,
+    /// How the groups were formed, see `Grouping::method`
+    pub method: String,
+    pub groups: Vec<MatchGroup>,
+    /// Parts left out of every group of a fixed size
+    pub unmatched: Vec<PathBuf>,
+    pub failures: Vec<FailedTrace>,
+}
+
+impl MatchReport {
+    pub fn new(
+        parts: &[Part],
+        failures: Vec<FailedTrace>,
+        metric: &Metric,
+        tolerance: f64,
+        grouping: Grouping,
+    ) -> Self {
+        let distances = parts
+            .iter()
+            .map(|a| {
+                parts
+                    .iter()
+                    .map(|b| metric.distance(&a.signature, &b.signature))
+                    .collect_vec()
+            })
+            .collect_vec();
+        let groups = group_parts(&distances, tolerance, grouping);
+        let unmatched = (0..parts.len())
+            .filter(|part| !groups.iter().any(|group| group.contains(part)))
+            .map(|part| parts[part].file.clone())
+            .collect();
+        MatchReport {
+            metric: metric.to_string(),
+            tolerance,
+            method: grouping.method().to_string(),
+            groups: groups
+                .iter()
+                .map(|group| MatchGroup {
+                    spread: spread(group, &distances),
+                    parts: group.iter().map(|&part| parts[part].file.clone()).collect(),
+                })
+                .collect(),
+            unmatched,
+            failures,
+        }
+    }
+
+    /// Writes one row per part with its group, numbered from 1, and the spread of the group;
+    /// unmatched parts have neither
+    pub fn write_csv<W: Write>(&self, out: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(&["group", "spread", "file"])?;
+        for (ix, group) in self.groups.iter().enumerate() {
+            for file in &group.parts {
+                writer.write_record(&[
+                    (ix + 1).to_string(),
+                    group.spread.to_string(),
+                    file.display().to_string(),
+                ])?;
+            }
+        }
+        for file in &self.unmatched {
+            writer.write_record(&[String::new(), String::new(), file.display().to_string()])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, format: SummaryFormat, mut out: W) -> Result<()> {
+        match format {
+            SummaryFormat::Csv => self.write_csv(out),
+            SummaryFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, self)?;
+                writeln!(out)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use crate::dut::matching::{group_parts, load_parts, Grouping, MatchReport, Metric};
+    use crate::dut::trace::{GuiTrace, ShareableTrace};
+    use crate::dut::{Device, TwoTerminalDevice, TwoTerminalDeviceType};
+    use crate::error::TracerError;
+    use crate::Result;
+
+    fn distances(values: &[f64]) -> Vec<Vec<f64>> {
+        values
+            .iter()
+            .map(|a| values.iter().map(|b| (a - b).abs()).collect())
+            .collect()
+    }
+
+    #[test]
+    pub fn pairs_are_the_closest_parts() {
+        let distances = distances(&[1.0, 5.0, 1.1, 5.3, 9.0, 5.2]);
+        let mut groups = group_parts(&distances, 0.5, Grouping::Pairs);
+        groups.sort();
+        assert_eq!(groups, vec![vec![0, 2], vec![3, 5]]);
+
+        let groups = group_parts(&distances, 0.5, Grouping::Bins);
+        assert_eq!(groups, vec![vec![1, 3, 5], vec![0, 2], vec![4]]);
+    }
+
+    #[test]
+    pub fn forms_as_many_pairs_as_possible() {
+        // Pairing the closest two first, 1.0 and 1.1, would leave 0.5 and 1.6 unmatched
+        let distances = distances(&[0.5, 1.0, 1.1, 1.6]);
+        let mut groups = group_parts(&distances, 0.6, Grouping::Pairs);
+        groups.sort();
+        assert_eq!(groups, vec![vec![0, 1], vec![2, 3]]);
+    }
+
+    fn load(path: &Path) -> Result<Box<dyn GuiTrace>> {
+        Ok(TwoTerminalDevice::from_type(TwoTerminalDeviceType::Diode)
+            .load_from_csv(path)?
+            .as_gui_trace())
+    }
+
+    #[test]
+    pub fn matches_diodes_by_forward_voltage() {
+        let files = (1..=5)
+            .map(|ix| PathBuf::from(format!("res/1N914B-{}.csv", ix)))
+            .collect::<Vec<_>>();
+        let metric = Metric::Voltage {
+            min_i: 1e-4,
+            max_i: 1e-3,
+            points: 4,
+        };
+        let (parts, failures) = load_parts(&files, load, &metric);
+        assert!(failures.is_empty());
+        assert_eq!(parts[0].signature.len(), 4);
+        assert!(parts[0].signature[0] > 0.3 && parts[0].signature[0] < 0.8);
+
+        // -1 and -3 are within 0.5mV, -2, -4 and -5 within 1mV of each other, and the two lots
+        // more than 2mV apart
+        let report = MatchReport::new(&parts, failures, &metric, 1.5e-3, Grouping::Pairs);
+        let mut groups = report
+            .groups
+            .iter()
+            .map(|group| group.parts.clone())
+            .collect::<Vec<_>>();
+        groups.sort();
+        assert_eq!(
+            groups,
+            vec![
+                vec![files[0].clone(), files[2].clone()],
+                vec![files[3].clone(), files[4].clone()]
+            ]
+        );
+        assert_eq!(report.unmatched, vec![files[1].clone()]);
+        assert!(report.groups.iter().all(|group| group.spread < 0.5e-3));
+    }
+
+    #[test]
+    pub fn non_finite_differences_are_infinite() {
+        let metric = Metric::Voltage {
+            min_i: 1e-4,
+            max_i: 1e-3,
+            points: 2,
+        };
+        assert_eq!(metric.distance(&[0.5, 0.5], &[0.5, 0.75]), 0.25);
+        assert_eq!(
+            metric.distance(&[0.5, f64::NAN], &[0.5, 0.75]),
+            f64::INFINITY
+        );
+        let metric = Metric::Params(vec!["is".into()]);
+        assert_eq!(
+            metric.distance(&[f64::INFINITY], &[f64::INFINITY]),
+            f64::INFINITY
+        );
+    }
+
+    #[test]
+    pub fn parts_without_the_parameter_fail() {
+        let files = vec![PathBuf::from("res/1N4148.csv")];
+        let metric = Metric::Params(vec!["hfe".into()]);
+        let (parts, failures) = load_parts(&files, load, &metric);
+        assert!(parts.is_empty());
+        assert_eq!(failures.len(), 1);
+
+        let mut trace = load(&files[0]).unwrap();
+        trace.fill_model();
+        let error = metric.signature(trace.as_ref()).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref::<TracerError>(),
+            Some(TracerError::NotComparable { .. })
+        ));
+    }
+}
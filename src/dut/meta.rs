@@ -0,0 +1,133 @@
+//! What a saved trace is of, kept in the header of the file
+
+use crate::backend::AD2Config;
+use crate::dut::{BiasDrive, SomeDeviceType};
+use crate::Result;
+
+/// Version of the trace files written. Version 1 files have at most the `[ad2]` table and bare
+/// CSV files no header at all.
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct TraceMetadata {
+    pub device_type: Option<SomeDeviceType>,
+    pub bias_drive: Option<BiasDrive>,
+    /// Unit of the bias column
+    pub bias_unit: Option<String>,
+    /// Sign of the swept voltage
+    pub polarity: Option<f64>,
+    /// Capture time, RFC 3339 in UTC
+    pub timestamp: Option<String>,
+    pub part_number: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl TraceMetadata {
+    /// Metadata of a trace of `device_type` captured just now
+    pub fn captured(device_type: SomeDeviceType) -> Self {
+        TraceMetadata {
+            device_type: Some(device_type),
+            timestamp: Some(time::now_utc().rfc3339().to_string()),
+            ..TraceMetadata::default()
+        }
+    }
+
+    pub fn with_bias(self, bias_drive: BiasDrive, polarity: f64) -> Self {
+        let bias_unit = match bias_drive {
+            BiasDrive::Voltage => "V",
+            BiasDrive::Current => "A",
+        };
+        TraceMetadata {
+            bias_drive: Some(bias_drive),
+            bias_unit: Some(bias_unit.to_string()),
+            polarity: Some(polarity),
+            ..self
+        }
+    }
+}
+
+/// The TOML header of a trace file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct FileHeader {
+    pub format: u32,
+    pub meta: TraceMetadata,
+    pub ad2: Option<AD2Config>,
+}
+
+impl Default for FileHeader {
+    /// The header of files written before the format was versioned
+    fn default() -> Self {
+        FileHeader {
+            format: 1,
+            meta: TraceMetadata::default(),
+            ad2: None,
+        }
+    }
+}
+
+impl FileHeader {
+    pub fn new(meta: TraceMetadata, ad2: Option<AD2Config>) -> Self {
+        FileHeader {
+            format: FORMAT_VERSION,
+            meta,
+            ad2,
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::backend::AD2Config;
+    use crate::dut::csv::read_header;
+    use crate::dut::meta::{FileHeader, TraceMetadata, FORMAT_VERSION};
+    use crate::dut::{BiasDrive, CurrentBiasedDeviceType, SomeDeviceType};
+
+    #[test]
+    pub fn header_round_trips() {
+        let meta = TraceMetadata {
+            part_number: Some("2N3904".to_string()),
+            notes: Some("from the blue reel".to_string()),
+            ..TraceMetadata::captured(SomeDeviceType::CurrentBiased(CurrentBiasedDeviceType::NPN))
+                .with_bias(BiasDrive::Current, 1.0)
+        };
+        let header = FileHeader::new(meta, Some(AD2Config::default()));
+
+        let parsed = FileHeader::from_toml(&header.to_toml().unwrap()).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed.format, FORMAT_VERSION);
+        assert_eq!(parsed.meta.bias_unit.as_deref(), Some("A"));
+    }
+
+    #[test]
+    pub fn reads_version_1_header() {
+        let header = FileHeader::from_toml("[ad2]\ncycles_to_sample = 7\n").unwrap();
+
+        assert_eq!(header.format, 1);
+        assert_eq!(header.meta, TraceMetadata::default());
+        assert_eq!(header.ad2.unwrap().cycles_to_sample, 7);
+    }
+
+    #[test]
+    pub fn bare_csv_has_default_header() {
+        assert_eq!(
+            read_header(Path::new("res/1N4148.csv")).unwrap(),
+            FileHeader::default()
+        );
+        assert_eq!(
+            read_header(Path::new("res/2N3904.csv.gz")).unwrap(),
+            FileHeader::default()
+        );
+    }
+}
@@ -1,11 +1,12 @@
 use std::path::Path;
 
-use crate::backend::Backend;
+use crate::backend::{Backend, TraceControl};
 use crate::dut::aoi::AreaOfInterest;
 use crate::dut::trace::{ShareableTrace, Trace};
 use crate::gui::COLORS_HEX;
 use crate::Result;
 
+pub use self::detect::*;
 pub use self::device_type::*;
 pub use self::i_biased::*;
 pub use self::two::*;
@@ -13,10 +14,16 @@ pub use self::v_biased::*;
 use crate::gui::widgets::DeviceConfig;
 use crate::util::Engineering;
 
-mod aoi;
-mod csv;
+pub mod aoi;
+pub mod batch;
+pub(crate) mod csv;
+mod detect;
 mod device_type;
 mod i_biased;
+pub mod matching;
+pub mod meta;
+pub mod report;
+pub mod session;
 pub mod trace;
 mod two;
 mod v_biased;
@@ -28,6 +35,8 @@ pub enum SomeDevice {
     CurrentBiased(CurrentBiasedDevice),
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum BiasDrive {
     Voltage,
     Current,
@@ -37,7 +46,7 @@ pub trait Device: Sized {
     type Trace: Trace;
     type Config;
     fn area_of_interest(&self) -> AreaOfInterest;
-    fn trace(&self, backend: &dyn Backend) -> Result<Self::Trace>;
+    fn trace(&self, backend: &dyn Backend, control: &TraceControl) -> Result<Self::Trace>;
     fn load_from_csv<P: AsRef<Path>>(&self, path: P) -> Result<Self::Trace>;
     fn config(&self) -> Self::Config;
     fn set_config(&mut self, config: &Self::Config);
@@ -60,7 +69,7 @@ impl SomeDevice {
 
     pub fn legend(&self) -> String {
         match self {
-            SomeDevice::TwoTerminal(TwoTerminalDevice::Diode) => String::new(),
+            SomeDevice::TwoTerminal(_) => String::new(),
             SomeDevice::CurrentBiased(device) => {
                 let mut legend = String::from("I<sub>BE</sub>:");
                 for (bias, color) in device.bias_levels().iter().zip(COLORS_HEX.iter()) {
@@ -90,8 +99,8 @@ impl SomeDevice {
 impl From<SomeDeviceType> for SomeDevice {
     fn from(t: SomeDeviceType) -> Self {
         match t {
-            SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Diode) => {
-                SomeDevice::TwoTerminal(TwoTerminalDevice::Diode)
+            SomeDeviceType::TwoTerminal(device_type) => {
+                SomeDevice::TwoTerminal(TwoTerminalDevice::from_type(device_type))
             }
             SomeDeviceType::VoltageBiased(device_type) => {
                 SomeDevice::VoltageBiased(VoltageBiasedDevice::from_type(device_type))
@@ -115,11 +124,11 @@ impl Device for SomeDevice {
         }
     }
 
-    fn trace(&self, backend: &dyn Backend) -> Result<Self::Trace> {
+    fn trace(&self, backend: &dyn Backend, control: &TraceControl) -> Result<Self::Trace> {
         Ok(match self {
-            SomeDevice::TwoTerminal(device) => Box::new(device.trace(backend)?),
-            SomeDevice::VoltageBiased(device) => Box::new(device.trace(backend)?),
-            SomeDevice::CurrentBiased(device) => Box::new(device.trace(backend)?),
+            SomeDevice::TwoTerminal(device) => Box::new(device.trace(backend, control)?),
+            SomeDevice::VoltageBiased(device) => Box::new(device.trace(backend, control)?),
+            SomeDevice::CurrentBiased(device) => Box::new(device.trace(backend, control)?),
         })
     }
 
@@ -133,7 +142,7 @@ impl Device for SomeDevice {
 
     fn config(&self) -> Self::Config {
         match self {
-            SomeDevice::TwoTerminal(_) => DeviceConfig::None,
+            SomeDevice::TwoTerminal(device) => DeviceConfig::TwoTerminal(device.config()),
             SomeDevice::VoltageBiased(device) => DeviceConfig::FET(device.config()),
             SomeDevice::CurrentBiased(device) => DeviceConfig::BJT(device.config()),
         }
@@ -0,0 +1,108 @@
+//! Machine-readable summary of a trace and its fitted model
+
+use crate::backend::RawTrace;
+use crate::dut::trace::{Trace, TraceWithModel};
+use crate::dut::SomeDeviceType;
+use crate::model::curvefit::FitQuality;
+use crate::model::Param;
+
+/// Extent of a single curve of a trace
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CurveStats {
+    /// Bias level of the curve, `None` for two-terminal traces
+    pub bias: Option<f64>,
+    pub samples: usize,
+    pub min_v: f64,
+    pub max_v: f64,
+    pub min_i: f64,
+    pub max_i: f64,
+}
+
+impl CurveStats {
+    pub fn new(bias: Option<f64>, trace: &RawTrace) -> Self {
+        let (min_v, max_v, min_i, max_i) = trace.iter().fold(
+            (
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+            ),
+            |(min_v, max_v, min_i, max_i), (v, i)| {
+                (min_v.min(v), max_v.max(v), min_i.min(i), max_i.max(i))
+            },
+        );
+        CurveStats {
+            bias,
+            samples: trace.len(),
+            min_v,
+            max_v,
+            min_i,
+            max_i,
+        }
+    }
+}
+
+/// What the CLI prints with `--format json`
+#[derive(Serialize, Clone, Debug)]
+pub struct TraceReport {
+    pub device_type: Option<SomeDeviceType>,
+    pub part_number: Option<String>,
+    /// Unit of the bias levels
+    pub bias_unit: Option<String>,
+    pub bias_levels: Vec<f64>,
+    pub params: Vec<Param>,
+    pub quality: Option<FitQuality>,
+    pub curves: Vec<CurveStats>,
+}
+
+impl TraceReport {
+    /// Reports on `trace`, whose model should be filled beforehand
+    pub fn new<T: Trace + TraceWithModel + ?Sized>(trace: &T) -> Self {
+        let metadata = trace.metadata();
+        let curves = trace.curve_stats();
+        TraceReport {
+            device_type: metadata.device_type,
+            part_number: metadata.part_number,
+            bias_unit: metadata.bias_unit,
+            bias_levels: curves.iter().filter_map(|curve| curve.bias).collect(),
+            params: trace.model_params(),
+            quality: trace.model_quality(),
+            curves,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dut::report::TraceReport;
+    use crate::dut::trace::{ShareableTrace, TraceWithModel};
+    use crate::dut::{CurrentBiasedDevice, CurrentBiasedDeviceType, Device};
+
+    #[test]
+    pub fn reports_every_bias_level() {
+        let device = CurrentBiasedDevice::from_type(CurrentBiasedDeviceType::NPN);
+        let mut trace = device
+            .load_from_csv("res/2N3904.csv.gz")
+            .unwrap()
+            .as_gui_trace();
+        trace.fill_model();
+
+        let report = TraceReport::new(trace.as_ref());
+        assert_eq!(report.bias_levels.len(), 5);
+        assert_eq!(report.curves.len(), 5);
+        assert!(report.curves.iter().all(|curve| curve.samples > 0));
+        assert!(report.params.iter().any(|param| param.name == "early_v"));
+        assert_eq!(
+            report
+                .params
+                .iter()
+                .filter(|param| param.name == "hfe")
+                .count(),
+            5
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["params"][0]["name"], "hfe");
+        assert!(json["params"][0]["at"]["ib"].is_number());
+    }
+}
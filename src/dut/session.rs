@@ -0,0 +1,201 @@
+//! Capture sessions: a run of parts of the same number traced one after another, each saved
+//! under an incrementing serial
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::dut::batch::{AnalyzedTrace, BatchSummary, FailedTrace, SummaryFormat};
+use crate::dut::report::TraceReport;
+use crate::dut::trace::GuiTrace;
+use crate::error::TracerError;
+use crate::Result;
+
+const TRACE_EXTENSION: &str = ".csv.gz";
+
+pub struct Session {
+    part_number: String,
+    folder: PathBuf,
+    next_serial: u32,
+    summary: BatchSummary,
+}
+
+/// `part_number` fit for a file name
+fn file_stem(part_number: &str) -> String {
+    part_number
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Serial of `file_name` if it's that of a trace of a session of parts named `stem`
+fn serial_of(file_name: &str, stem: &str) -> Option<u32> {
+    let prefix = format!("{}-", stem);
+    if !file_name.starts_with(&prefix) || !file_name.ends_with(TRACE_EXTENSION) {
+        return None;
+    }
+    let serial = &file_name[prefix.len()..file_name.len() - TRACE_EXTENSION.len()];
+    if !serial.is_empty() && serial.chars().all(|c| c.is_ascii_digit()) {
+        serial.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl Session {
+    /// Starts a session saving to `folder`, created if need be. Serials carry on from those of
+    /// the traces of the same part number already in it, so that a session can be resumed.
+    pub fn new(part_number: &str, folder: &Path) -> Result<Self> {
+        let part_number = part_number.trim();
+        if part_number.is_empty() {
+            return Err(TracerError::invalid_argument("A session needs a part number").into());
+        }
+        std::fs::create_dir_all(folder).map_err(|e| TracerError::io(folder, e))?;
+
+        let stem = file_stem(part_number);
+        let mut last_serial = 0;
+        for entry in std::fs::read_dir(folder).map_err(|e| TracerError::io(folder, e))? {
+            let entry = entry.map_err(|e| TracerError::io(folder, e))?;
+            if let Some(serial) = entry
+                .file_name()
+                .to_str()
+                .and_then(|file_name| serial_of(file_name, &stem))
+            {
+                last_serial = last_serial.max(serial);
+            }
+        }
+
+        Ok(Session {
+            part_number: part_number.to_string(),
+            folder: folder.to_path_buf(),
+            next_serial: last_serial + 1,
+            summary: BatchSummary::default(),
+        })
+    }
+
+    pub fn part_number(&self) -> &str {
+        &self.part_number
+    }
+
+    pub fn next_serial(&self) -> u32 {
+        self.next_serial
+    }
+
+    /// File the next capture is saved to
+    pub fn next_path(&self) -> PathBuf {
+        self.folder.join(format!(
+            "{}-{:04}{}",
+            file_stem(&self.part_number),
+            self.next_serial,
+            TRACE_EXTENSION
+        ))
+    }
+
+    /// Default file for the session table
+    pub fn summary_path(&self) -> PathBuf {
+        self.folder
+            .join(format!("{}-session.csv", file_stem(&self.part_number)))
+    }
+
+    /// Parameters of the parts captured so far, those that couldn't be fitted among the
+    /// failures
+    pub fn summary(&self) -> &BatchSummary {
+        &self.summary
+    }
+
+    /// Saves `trace` under the next serial with the part number of the session, fits its model
+    /// and adds its parameters to the session table
+    pub fn record(&mut self, trace: &mut dyn GuiTrace) -> Result<PathBuf> {
+        let path = self.next_path();
+        let mut metadata = trace.metadata();
+        metadata.part_number = Some(self.part_number.clone());
+        trace.set_metadata(metadata);
+        trace.save_as_csv(&path)?;
+        self.next_serial += 1;
+
+        trace.fill_model();
+        let report = TraceReport::new(&*trace);
+        if report.params.is_empty() {
+            self.summary.failures.push(FailedTrace {
+                file: path.clone(),
+                error: "Can't fit a model to the trace".to_string(),
+            });
+        } else {
+            self.summary.traces.push(AnalyzedTrace {
+                file: path.clone(),
+                report,
+            });
+        }
+        Ok(path)
+    }
+
+    /// Writes the session table to `path`, as JSON if it ends in `.json` and CSV otherwise
+    pub fn export(&self, path: &Path) -> Result<()> {
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => SummaryFormat::Json,
+            _ => SummaryFormat::Csv,
+        };
+        let file = File::create(path).map_err(|e| TracerError::io(path, e))?;
+        self.summary.write(format, file)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dut::session::{serial_of, Session};
+    use crate::dut::trace::ShareableTrace;
+    use crate::dut::{Device, TwoTerminalDevice, TwoTerminalDeviceType};
+    use crate::error::TracerError;
+
+    #[test]
+    pub fn serials_are_parsed_from_file_names() {
+        assert_eq!(serial_of("1N4148-0007.csv.gz", "1N4148"), Some(7));
+        assert_eq!(serial_of("1N4148-session.csv", "1N4148"), None);
+        assert_eq!(serial_of("1N4148A-0007.csv.gz", "1N4148"), None);
+    }
+
+    #[test]
+    pub fn captures_are_numbered_and_tabulated() {
+        let folder = std::env::temp_dir().join("ks-curve-tracer-session");
+        let _ = std::fs::remove_dir_all(&folder);
+
+        let device = TwoTerminalDevice::from_type(TwoTerminalDeviceType::Diode);
+        let trace = device.load_from_csv("res/1N4148.csv").unwrap();
+
+        let mut session = Session::new("1N4148", &folder).unwrap();
+        for serial in 1..=2 {
+            let path = session.record(trace.as_gui_trace().as_mut()).unwrap();
+            assert_eq!(path, folder.join(format!("1N4148-000{}.csv.gz", serial)));
+        }
+        assert_eq!(session.summary().traces.len(), 2);
+        assert_eq!(
+            device
+                .load_from_csv(folder.join("1N4148-0002.csv.gz"))
+                .unwrap()
+                .metadata
+                .part_number
+                .as_deref(),
+            Some("1N4148")
+        );
+
+        session.export(&session.summary_path()).unwrap();
+        assert!(session.summary_path().exists());
+
+        let resumed = Session::new("1N4148", &folder).unwrap();
+        assert_eq!(resumed.next_serial(), 3);
+
+        let unnamed = Session::new(" ", &folder).err().unwrap();
+        assert!(matches!(
+            unnamed.downcast_ref::<TracerError>(),
+            Some(TracerError::InvalidArgument { .. })
+        ));
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -6,21 +6,79 @@ pub use self::null::*;
 pub use self::three::*;
 pub use self::two::*;
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Formatter};
 use std::path::Path;
+use std::sync::Arc;
 
 use crate::dut::aoi::AreaOfInterest;
+use crate::dut::meta::TraceMetadata;
+use crate::dut::report::CurveStats;
+use crate::error::TracerError;
+use crate::gui::viewport::Viewport;
+use crate::model::curvefit::FitQuality;
+use crate::model::spice::ModelCard;
+use crate::model::{BiasedModel, IVModel, Param};
 use crate::Result;
 use cairo::Context;
+use itertools_num::linspace;
 
 pub trait Trace {
     fn area_of_interest(&self) -> AreaOfInterest;
     fn save_as_csv(&self, path: &Path) -> Result<()>;
+    /// What was measured, saved along with the trace
+    fn metadata(&self) -> TraceMetadata;
+    fn set_metadata(&mut self, metadata: TraceMetadata);
+    /// Extent of each curve, in the order of the bias levels
+    fn curve_stats(&self) -> Vec<CurveStats>;
+}
+
+/// A fitted model apart from its trace, to hand a fit done on another thread over
+#[derive(Clone)]
+pub enum FittedModel {
+    TwoTerminal(Arc<dyn IVModel>),
+    ThreeTerminal(Arc<dyn BiasedModel>),
+}
+
+impl Debug for FittedModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FittedModel::TwoTerminal(model) => write!(f, "TwoTerminal({})", model),
+            FittedModel::ThreeTerminal(model) => write!(f, "ThreeTerminal({})", model),
+        }
+    }
 }
 
 pub trait TraceWithModel {
     fn fill_model(&mut self);
     fn model_report(&self) -> String;
+
+    /// The model once it's filled
+    fn fitted_model(&self) -> Option<FittedModel> {
+        None
+    }
+
+    /// Takes over a model fitted to a copy of the trace
+    fn set_fitted_model(&mut self, _model: FittedModel) {}
+
+    /// Quality of the fitted model, `None` until it's filled or if it wasn't fitted
+    fn model_quality(&self) -> Option<FitQuality> {
+        None
+    }
+
+    /// Parameters of the fitted model and the measurements reported along with it
+    fn model_params(&self) -> Vec<Param> {
+        Vec::new()
+    }
+
+    /// Fitted curves, one per bias level, empty until the model is filled
+    fn model_curves(&self) -> Vec<&dyn IVModel> {
+        Vec::new()
+    }
+
+    /// SPICE model card of the fitted model, `temperature` in °C
+    fn spice_card(&self, _name: &str, _temperature: f64) -> Option<ModelCard> {
+        None
+    }
 }
 
 pub trait ShareableTrace: Trace + Send + Sync + Debug {
@@ -30,8 +88,53 @@ pub trait ShareableTrace: Trace + Send + Sync + Debug {
 pub trait GuiTrace: Trace + DrawableTrace {}
 
 pub trait DrawableTrace: TraceWithModel {
-    fn draw(&self, cr: &Context, v_factor: f64, i_factor: f64, height: f64);
-    fn draw_model(&self, cr: &Context, v_factor: f64, i_factor: f64, height: f64);
+    fn draw(&self, cr: &Context, viewport: &Viewport);
+    fn draw_model(&self, cr: &Context, viewport: &Viewport);
+
+    /// Labels and colors of the curves, empty for single-curve traces
+    fn legend(&self) -> Vec<(String, (f64, f64, f64))> {
+        Vec::new()
+    }
+}
+
+/// Strokes the part of `model` that falls within the viewport
+pub fn draw_iv_model(cr: &Context, viewport: &Viewport, model: &dyn IVModel) {
+    let (min_v, max_v) = viewport.v_range();
+    let min_v = model.min_v().max(min_v);
+    let max_v = model.max_v().min(max_v);
+    if min_v >= max_v {
+        return;
+    }
+
+    cr.set_source_rgba(1.0, 0.0, 0.0, 0.8);
+
+    for (ix, v) in linspace(min_v, max_v, 101).enumerate() {
+        let (x, y) = (viewport.x(v), viewport.y(model.evaluate(v)));
+        if ix == 0 {
+            cr.move_to(x, y);
+        } else {
+            cr.line_to(x, y);
+        }
+    }
+    cr.stroke();
+}
+
+/// Writes the SPICE card of the fitted model to `path`, named after the file
+pub fn save_spice_card<T: TraceWithModel + ?Sized>(
+    trace: &T,
+    path: &Path,
+    temperature: f64,
+) -> Result<()> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let card = trace
+        .spice_card(&name, temperature)
+        .ok_or_else(|| TracerError::FitFailed {
+            message: "There's no fitted model to export".to_string(),
+        })?;
+    card.save(path)
 }
 
 impl Trace for Box<dyn ShareableTrace> {
@@ -42,6 +145,18 @@ impl Trace for Box<dyn ShareableTrace> {
     fn save_as_csv(&self, path: &Path) -> Result<()> {
         ShareableTrace::save_as_csv(&**self, path)
     }
+
+    fn metadata(&self) -> TraceMetadata {
+        ShareableTrace::metadata(&**self)
+    }
+
+    fn set_metadata(&mut self, metadata: TraceMetadata) {
+        ShareableTrace::set_metadata(&mut **self, metadata)
+    }
+
+    fn curve_stats(&self) -> Vec<CurveStats> {
+        ShareableTrace::curve_stats(&**self)
+    }
 }
 
 impl GuiTrace for NullTrace {}
@@ -1,7 +1,11 @@
 use crate::dut::aoi::AreaOfInterest;
+use crate::dut::meta::TraceMetadata;
+use crate::dut::report::CurveStats;
 use crate::dut::trace::DrawableTrace;
 use crate::dut::trace::Trace;
 use crate::dut::trace::TraceWithModel;
+use crate::error::TracerError;
+use crate::gui::viewport::Viewport;
 use crate::Result;
 use cairo::Context;
 use std::path::Path;
@@ -14,7 +18,14 @@ impl Trace for NullTrace {
         AreaOfInterest::default()
     }
     fn save_as_csv(&self, _: &Path) -> Result<()> {
-        Err(failure::err_msg("No trace to save"))
+        Err(TracerError::NoTrace.into())
+    }
+    fn metadata(&self) -> TraceMetadata {
+        TraceMetadata::default()
+    }
+    fn set_metadata(&mut self, _: TraceMetadata) {}
+    fn curve_stats(&self) -> Vec<CurveStats> {
+        Vec::new()
     }
 }
 
@@ -26,6 +37,6 @@ impl TraceWithModel for NullTrace {
 }
 
 impl DrawableTrace for NullTrace {
-    fn draw(&self, _: &Context, _: f64, _: f64, _: f64) {}
-    fn draw_model(&self, _: &Context, _: f64, _: f64, _: f64) {}
+    fn draw(&self, _: &Context, _: &Viewport) {}
+    fn draw_model(&self, _: &Context, _: &Viewport) {}
 }
@@ -1,29 +1,55 @@
 use std::collections::btree_map::BTreeMap;
 use std::path::Path;
+use std::sync::Arc;
 
 use cairo::Context;
 use itertools::Itertools;
 use noisy_float::prelude::R64;
 
 use crate::dut::aoi::AreaOfInterest;
-use crate::dut::csv::csv_writer_from_path;
+use crate::dut::csv::{csv_writer_from_path, header_preamble};
+use crate::dut::meta::{FileHeader, TraceMetadata};
+use crate::dut::report::CurveStats;
 use crate::dut::trace::{
-    DrawableTrace, Trace, TraceWithModel, TwoTerminalGuiTrace, TwoTerminalTrace,
+    draw_iv_model, DrawableTrace, FittedModel, Trace, TraceWithModel, TwoTerminalGuiTrace,
+    TwoTerminalTrace,
 };
+use crate::gui::viewport::Viewport;
 use crate::gui::COLORS_F64;
 use crate::gui::{MASK_HEIGHT, MASK_WIDTH};
+use crate::model::bjt::bjt_model;
+use crate::model::curvefit::FitQuality;
+use crate::model::mosfet::mosfet_model;
+use crate::model::spice::ModelCard;
+use crate::model::{BiasedModel, IVModel, Param};
+use crate::util::Engineering;
 use crate::Result;
 
 #[derive(Clone, Debug)]
 pub struct ThreeTerminalTrace {
     reverse_order: bool,
     pub traces: BTreeMap<R64, TwoTerminalTrace>,
+    pub model_kind: Option<ThreeTerminalModelKind>,
+    pub metadata: TraceMetadata,
+}
+
+/// The kind of model to fit to the curves
+#[derive(Copy, Clone, Debug)]
+pub enum ThreeTerminalModelKind {
+    /// Saturation voltage is measured at `sat_current`
+    Bjt {
+        sat_current: f64,
+    },
+    Mosfet,
 }
 
 #[derive(Clone)]
 pub struct ThreeTerminalGuiTrace {
     reverse_order: bool,
     pub traces: BTreeMap<R64, TwoTerminalGuiTrace>,
+    model_kind: Option<ThreeTerminalModelKind>,
+    pub metadata: TraceMetadata,
+    pub model: Option<Arc<dyn BiasedModel>>,
 }
 
 impl ThreeTerminalTrace {
@@ -31,8 +57,18 @@ impl ThreeTerminalTrace {
         ThreeTerminalTrace {
             reverse_order,
             traces,
+            model_kind: None,
+            metadata: TraceMetadata::default(),
         }
     }
+
+    pub fn with_model_kind(self, model_kind: Option<ThreeTerminalModelKind>) -> Self {
+        Self { model_kind, ..self }
+    }
+
+    pub fn with_metadata(self, metadata: TraceMetadata) -> Self {
+        Self { metadata, ..self }
+    }
 }
 
 impl ThreeTerminalGuiTrace {
@@ -43,6 +79,9 @@ impl ThreeTerminalGuiTrace {
         ThreeTerminalGuiTrace {
             reverse_order,
             traces,
+            model_kind: None,
+            metadata: TraceMetadata::default(),
+            model: None,
         }
     }
 
@@ -54,6 +93,8 @@ impl ThreeTerminalGuiTrace {
                 .into_iter()
                 .map(|(k, v)| (k, v.into()))
                 .collect(),
+            model_kind: self.model_kind,
+            metadata: self.metadata,
         }
     }
 }
@@ -67,6 +108,9 @@ impl From<ThreeTerminalTrace> for ThreeTerminalGuiTrace {
                 .into_iter()
                 .map(|(k, v)| (k, v.into()))
                 .collect(),
+            model_kind: trace.model_kind,
+            metadata: trace.metadata,
+            model: None,
         }
     }
 }
@@ -81,7 +125,15 @@ impl Trace for ThreeTerminalTrace {
     }
 
     fn save_as_csv(&self, path: &Path) -> Result<()> {
-        let mut out = csv_writer_from_path(path)?;
+        let capture_config = self
+            .traces
+            .values()
+            .next()
+            .and_then(|trace| trace.capture_config.clone());
+        let mut out = csv_writer_from_path(
+            path,
+            &header_preamble(&FileHeader::new(self.metadata.clone(), capture_config))?,
+        )?;
 
         let header = ["v", "i", "bias"];
         out.write_record(&header)?;
@@ -97,6 +149,21 @@ impl Trace for ThreeTerminalTrace {
         out.close()?;
         Ok(())
     }
+
+    fn metadata(&self) -> TraceMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: TraceMetadata) {
+        self.metadata = metadata;
+    }
+
+    fn curve_stats(&self) -> Vec<CurveStats> {
+        self.traces
+            .iter()
+            .map(|(bias, trace)| CurveStats::new(Some(bias.raw()), &trace.trace))
+            .collect()
+    }
 }
 
 impl Trace for ThreeTerminalGuiTrace {
@@ -107,17 +174,95 @@ impl Trace for ThreeTerminalGuiTrace {
     fn save_as_csv(&self, path: &Path) -> Result<()> {
         self.clone().into_three_terminal_trace().save_as_csv(path)
     }
+
+    fn metadata(&self) -> TraceMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: TraceMetadata) {
+        self.metadata = metadata;
+    }
+
+    fn curve_stats(&self) -> Vec<CurveStats> {
+        self.traces
+            .iter()
+            .map(|(bias, trace)| CurveStats::new(Some(bias.raw()), &trace.trace.trace))
+            .collect()
+    }
 }
 
 impl TraceWithModel for ThreeTerminalGuiTrace {
-    fn fill_model(&mut self) {}
+    fn fill_model(&mut self) {
+        if self.model.is_some() {
+            return;
+        }
+        let traces = self
+            .traces
+            .iter()
+            .map(|(bias, trace)| (bias.raw(), &trace.trace.trace));
+        self.model = match self.model_kind {
+            Some(ThreeTerminalModelKind::Bjt { sat_current }) => {
+                // Traces saved without their capture config were taken with the default one
+                let config = self
+                    .traces
+                    .values()
+                    .next()
+                    .and_then(|trace| trace.trace.capture_config.clone())
+                    .unwrap_or_default();
+                bjt_model(traces, sat_current, &config)
+                    .map(|model| Arc::new(model) as Arc<dyn BiasedModel>)
+            }
+            Some(ThreeTerminalModelKind::Mosfet) => {
+                mosfet_model(traces).map(|model| Arc::new(model) as Arc<dyn BiasedModel>)
+            }
+            None => None,
+        }
+    }
+
     fn model_report(&self) -> String {
-        String::new()
+        self.model
+            .as_ref()
+            .map(std::string::ToString::to_string)
+            .unwrap_or_else(String::new)
+    }
+
+    fn fitted_model(&self) -> Option<FittedModel> {
+        self.model.clone().map(FittedModel::ThreeTerminal)
+    }
+
+    fn set_fitted_model(&mut self, model: FittedModel) {
+        if let FittedModel::ThreeTerminal(model) = model {
+            self.model = Some(model);
+        }
+    }
+
+    fn model_quality(&self) -> Option<FitQuality> {
+        self.model
+            .as_ref()
+            .and_then(|model| model.quality().cloned())
+    }
+
+    fn model_curves(&self) -> Vec<&dyn IVModel> {
+        self.model
+            .as_ref()
+            .map_or_else(Vec::new, |model| model.curves())
+    }
+
+    fn model_params(&self) -> Vec<Param> {
+        self.model
+            .as_ref()
+            .map_or_else(Vec::new, |model| model.params())
+    }
+
+    fn spice_card(&self, name: &str, temperature: f64) -> Option<ModelCard> {
+        self.model
+            .as_ref()
+            .and_then(|model| model.spice_card(name, temperature))
     }
 }
 
 impl DrawableTrace for ThreeTerminalGuiTrace {
-    fn draw(&self, cr: &Context, v_factor: f64, i_factor: f64, height: f64) {
+    fn draw(&self, cr: &Context, viewport: &Viewport) {
         let traces = if self.reverse_order {
             self.traces.iter().rev().collect_vec()
         } else {
@@ -131,21 +276,43 @@ impl DrawableTrace for ThreeTerminalGuiTrace {
             let aoi = trace.trace.aoi;
 
             let v_k = w / (aoi.max_v - aoi.min_v);
-            let v_b = aoi.min_v * v_k;
-
             let i_k = h / (aoi.max_i - aoi.min_i);
-            let i_b = aoi.min_i * i_k;
 
             cr.save();
             cr.set_source_rgba(color.0, color.1, color.2, 1.0);
-            cr.translate(0.0, height - 20.0);
-            cr.scale(v_factor / v_k, i_factor / -i_k);
-            cr.translate(v_b, i_b);
+            cr.translate(viewport.x(aoi.min_v), viewport.y(aoi.min_i));
+            cr.scale(viewport.v_factor() / v_k, viewport.i_factor() / -i_k);
             trace.apply_mask(cr).unwrap();
             cr.fill();
             cr.restore();
         }
     }
 
-    fn draw_model(&self, _: &Context, _: f64, _: f64, _: f64) {}
+    fn draw_model(&self, cr: &Context, viewport: &Viewport) {
+        if let Some(model) = &self.model {
+            for curve in model.curves() {
+                draw_iv_model(cr, viewport, curve);
+            }
+        }
+    }
+
+    fn legend(&self) -> Vec<(String, (f64, f64, f64))> {
+        let unit = match (&self.metadata.bias_unit, &self.model_kind) {
+            (Some(unit), _) => unit.as_str(),
+            (None, Some(ThreeTerminalModelKind::Bjt { .. })) => "A",
+            (None, Some(ThreeTerminalModelKind::Mosfet)) => "V",
+            (None, None) => "",
+        };
+        let biases = if self.reverse_order {
+            self.traces.keys().rev().collect_vec()
+        } else {
+            self.traces.keys().collect_vec()
+        };
+
+        biases
+            .into_iter()
+            .zip(COLORS_F64.iter())
+            .map(|(bias, &color)| (format!("{}{}", Engineering(bias.raw()), unit), color))
+            .collect()
+    }
 }
@@ -1,13 +1,20 @@
-use crate::backend::RawTrace;
+use crate::backend::{AD2Config, RawTrace};
 use crate::dut::aoi::AreaOfInterest;
-use crate::dut::csv::csv_writer_from_path;
-use crate::dut::trace::{DrawableTrace, Trace, TraceWithModel};
+use crate::dut::csv::{csv_writer_from_path, header_preamble};
+use crate::dut::meta::{FileHeader, TraceMetadata};
+use crate::dut::report::CurveStats;
+use crate::dut::trace::{draw_iv_model, DrawableTrace, FittedModel, Trace, TraceWithModel};
+use crate::error::TracerError;
+use crate::gui::viewport::Viewport;
 use crate::gui::{MASK_HEIGHT, MASK_WIDTH, SCATTER_PLOT_ALPHA};
-use crate::model::diode::diode_model;
-use crate::model::IVModel;
+use crate::model::curvefit::FitQuality;
+use crate::model::diode::{diode_model, leakage_current};
+use crate::model::spice::ModelCard;
+use crate::model::zener::zener_model;
+use crate::model::{IVModel, Param};
+use crate::util::Engineering;
 use crate::Result;
 use cairo::{Context, Format, ImageSurface, Operator};
-use itertools_num::linspace;
 use std::cell::RefCell;
 use std::f64::consts::PI;
 use std::path::Path;
@@ -17,6 +24,12 @@ use std::sync::Arc;
 pub struct TwoTerminalTrace {
     pub trace: RawTrace,
     pub aoi: AreaOfInterest,
+    pub capture_config: Option<AD2Config>,
+    /// Reverse voltage to report the leakage current at
+    pub leakage_v: Option<f64>,
+    /// Set for zeners, whose breakdown is modelled at this current
+    pub zener_test_current: Option<f64>,
+    pub metadata: TraceMetadata,
 }
 
 #[derive(Clone)]
@@ -28,7 +41,42 @@ pub struct TwoTerminalGuiTrace {
 
 impl TwoTerminalTrace {
     pub fn from_raw_trace(trace: RawTrace, aoi: AreaOfInterest) -> Self {
-        Self { trace, aoi }
+        Self {
+            trace,
+            aoi,
+            capture_config: None,
+            leakage_v: None,
+            zener_test_current: None,
+            metadata: TraceMetadata::default(),
+        }
+    }
+
+    pub fn with_capture_config(self, capture_config: Option<AD2Config>) -> Self {
+        Self {
+            capture_config,
+            ..self
+        }
+    }
+
+    pub fn with_leakage_v(self, leakage_v: Option<f64>) -> Self {
+        Self { leakage_v, ..self }
+    }
+
+    pub fn with_zener_test_current(self, zener_test_current: Option<f64>) -> Self {
+        Self {
+            zener_test_current,
+            ..self
+        }
+    }
+
+    pub fn with_metadata(self, metadata: TraceMetadata) -> Self {
+        Self { metadata, ..self }
+    }
+
+    /// Mean current at the leakage voltage, if the trace reaches it
+    pub fn leakage(&self) -> Option<(f64, f64)> {
+        let v = -self.leakage_v?.abs();
+        leakage_current(&self.trace, v).map(|i| (v, i))
     }
 }
 
@@ -51,17 +99,71 @@ impl From<TwoTerminalTrace> for TwoTerminalGuiTrace {
 impl TraceWithModel for TwoTerminalGuiTrace {
     fn fill_model(&mut self) {
         if self.model.is_none() {
-            if let Some(model) = diode_model(&self.trace.trace) {
-                self.model = Some(Arc::new(model))
+            // Traces saved without their capture config were taken with the default one
+            let config = self.trace.capture_config.clone().unwrap_or_default();
+            self.model = match self.trace.zener_test_current {
+                Some(test_current) => zener_model(&self.trace.trace, test_current, &config)
+                    .map(|model| Arc::new(model) as Arc<dyn IVModel>),
+                None => diode_model(&self.trace.trace, &config)
+                    .map(|model| Arc::new(model) as Arc<dyn IVModel>),
             }
         }
     }
 
     fn model_report(&self) -> String {
-        self.model
+        let mut report = self
+            .model
             .as_ref()
             .map(std::string::ToString::to_string)
-            .unwrap_or_else(String::new)
+            .unwrap_or_else(String::new);
+        if let Some((v, i)) = self.trace.leakage() {
+            report.push_str(&format!(
+                "I<sub>R</sub>@{}V\t{:.3}A\n",
+                Engineering(v),
+                Engineering(i)
+            ));
+        }
+        report
+    }
+
+    fn fitted_model(&self) -> Option<FittedModel> {
+        self.model.clone().map(FittedModel::TwoTerminal)
+    }
+
+    fn set_fitted_model(&mut self, model: FittedModel) {
+        if let FittedModel::TwoTerminal(model) = model {
+            self.model = Some(model);
+        }
+    }
+
+    fn model_quality(&self) -> Option<FitQuality> {
+        self.model
+            .as_ref()
+            .and_then(|model| model.quality().cloned())
+    }
+
+    fn model_curves(&self) -> Vec<&dyn IVModel> {
+        self.model
+            .iter()
+            .map(|model| model.as_ref() as &dyn IVModel)
+            .collect()
+    }
+
+    fn model_params(&self) -> Vec<Param> {
+        let mut params = self
+            .model
+            .as_ref()
+            .map_or_else(Vec::new, |model| model.params());
+        if let Some((v, i)) = self.trace.leakage() {
+            params.push(Param::new("ir", i, "A").at("vr", v));
+        }
+        params
+    }
+
+    fn spice_card(&self, name: &str, temperature: f64) -> Option<ModelCard> {
+        self.model
+            .as_ref()
+            .and_then(|model| model.spice_card(name, temperature))
     }
 }
 
@@ -71,7 +173,13 @@ impl Trace for TwoTerminalTrace {
     }
 
     fn save_as_csv(&self, path: &Path) -> Result<()> {
-        let mut out = csv_writer_from_path(path)?;
+        let mut out = csv_writer_from_path(
+            path,
+            &header_preamble(&FileHeader::new(
+                self.metadata.clone(),
+                self.capture_config.clone(),
+            ))?,
+        )?;
 
         let header = ["v", "i"];
         out.write_record(&header)?;
@@ -84,6 +192,18 @@ impl Trace for TwoTerminalTrace {
         out.close()?;
         Ok(())
     }
+
+    fn metadata(&self) -> TraceMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: TraceMetadata) {
+        self.metadata = metadata;
+    }
+
+    fn curve_stats(&self) -> Vec<CurveStats> {
+        vec![CurveStats::new(None, &self.trace)]
+    }
 }
 
 impl TwoTerminalGuiTrace {
@@ -93,7 +213,7 @@ impl TwoTerminalGuiTrace {
             let w = MASK_WIDTH;
             let h = MASK_HEIGHT;
             let surface = ImageSurface::create(Format::A8, w, h)
-                .map_err(|_| failure::err_msg("Can't create an off-screen surface"))?;
+                .map_err(|_| TracerError::render("Can't create an off-screen surface"))?;
             let cr = Context::new(&surface);
             cr.save();
             cr.set_source_rgba(0.0, 0.0, 0.0, 0.0);
@@ -135,42 +255,41 @@ impl Trace for TwoTerminalGuiTrace {
     fn save_as_csv(&self, path: &Path) -> Result<()> {
         self.trace.save_as_csv(path)
     }
+
+    fn metadata(&self) -> TraceMetadata {
+        self.trace.metadata()
+    }
+
+    fn set_metadata(&mut self, metadata: TraceMetadata) {
+        self.trace.set_metadata(metadata)
+    }
+
+    fn curve_stats(&self) -> Vec<CurveStats> {
+        self.trace.curve_stats()
+    }
 }
 
 impl DrawableTrace for TwoTerminalGuiTrace {
-    fn draw(&self, cr: &Context, v_factor: f64, i_factor: f64, height: f64) {
+    fn draw(&self, cr: &Context, viewport: &Viewport) {
         let w = f64::from(MASK_WIDTH);
         let h = f64::from(MASK_HEIGHT);
+        let aoi = self.trace.aoi;
 
-        let v_k = w / (self.trace.aoi.max_v - self.trace.aoi.min_v);
-        let v_b = self.trace.aoi.min_v * v_k;
-
-        let i_k = h / (self.trace.aoi.max_i - self.trace.aoi.min_i);
-        let i_b = self.trace.aoi.min_i * i_k;
+        let v_k = w / (aoi.max_v - aoi.min_v);
+        let i_k = h / (aoi.max_i - aoi.min_i);
 
         cr.save();
         cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
-        cr.translate(0.0, height - 20.0);
-        cr.scale(v_factor / v_k, i_factor / -i_k);
-        cr.translate(v_b, i_b);
+        cr.translate(viewport.x(aoi.min_v), viewport.y(aoi.min_i));
+        cr.scale(viewport.v_factor() / v_k, viewport.i_factor() / -i_k);
         self.apply_mask(cr).unwrap();
         cr.fill();
         cr.restore();
     }
 
-    fn draw_model(&self, cr: &Context, v_factor: f64, i_factor: f64, height: f64) {
+    fn draw_model(&self, cr: &Context, viewport: &Viewport) {
         if let Some(model) = &self.model {
-            cr.set_source_rgba(1.0, 0.0, 0.0, 0.8);
-
-            for (ix, v) in linspace(model.min_v().max(0.0), model.max_v().min(5.0), 101).enumerate()
-            {
-                if ix == 0 {
-                    cr.move_to(v * v_factor, height - 20.0 - i_factor * model.evaluate(v));
-                } else {
-                    cr.line_to(v * v_factor, height - 20.0 - i_factor * model.evaluate(v));
-                }
-            }
-            cr.stroke();
+            draw_iv_model(cr, viewport, model.as_ref());
         }
     }
 }
@@ -1,31 +1,98 @@
-use crate::backend::{Backend, RawTrace};
+use crate::backend::{Backend, RawTrace, SweepRange, TraceControl};
 use crate::dut::aoi::AreaOfInterest;
-use crate::dut::csv::csv_reader_from_path;
+use crate::dut::csv::{csv_reader_from_path, read_header};
+use crate::dut::meta::TraceMetadata;
 use crate::dut::trace::TwoTerminalTrace;
-use crate::dut::Device;
+use crate::dut::{Device, SomeDeviceType};
 use crate::Result;
 use std::fmt::{Display, Formatter};
 use std::path::Path;
 use std::str::FromStr;
 
 #[derive(Clone, Debug)]
-pub struct TwoTerminalDeviceConfig {}
+pub struct TwoTerminalDeviceConfig {
+    pub sweep_range: SweepRange,
+    /// Reverse voltage magnitude to report the leakage current at
+    pub leakage_v: f64,
+    /// Reverse current magnitude to measure the breakdown voltage at, zeners only
+    pub zener_test_current: Option<f64>,
+}
+
+impl Default for TwoTerminalDeviceConfig {
+    fn default() -> Self {
+        TwoTerminalDeviceConfig {
+            sweep_range: SweepRange::Positive,
+            leakage_v: 2.0,
+            zener_test_current: None,
+        }
+    }
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum TwoTerminalDeviceType {
     Diode,
+    Zener,
 }
 
 #[derive(Clone, Debug)]
-pub enum TwoTerminalDevice {
-    Diode,
+pub struct TwoTerminalDevice {
+    config: TwoTerminalDeviceConfig,
+    device_type: TwoTerminalDeviceType,
+}
+
+impl TwoTerminalDevice {
+    pub fn from_type(device_type: TwoTerminalDeviceType) -> Self {
+        let config = match device_type {
+            TwoTerminalDeviceType::Diode => TwoTerminalDeviceConfig::default(),
+            TwoTerminalDeviceType::Zener => TwoTerminalDeviceConfig {
+                sweep_range: SweepRange::Bipolar,
+                zener_test_current: Some(0.005),
+                ..TwoTerminalDeviceConfig::default()
+            },
+        };
+        TwoTerminalDevice {
+            config,
+            device_type,
+        }
+    }
+
+    fn area_of_interest_for(range: SweepRange) -> AreaOfInterest {
+        match range {
+            SweepRange::Positive => AreaOfInterest::new_pos_i_pos_v(0.05, 5.0),
+            SweepRange::Negative => AreaOfInterest::new_neg_i_neg_v(0.05, 5.0),
+            SweepRange::Bipolar => AreaOfInterest::new_bipolar(0.05, 5.0),
+        }
+        .extended()
+    }
+
+    fn leakage_v(&self, range: SweepRange) -> Option<f64> {
+        if range.covers_negative() {
+            Some(self.config.leakage_v)
+        } else {
+            None
+        }
+    }
+
+    fn to_trace(&self, trace: RawTrace, range: SweepRange) -> TwoTerminalTrace {
+        TwoTerminalTrace::from_raw_trace(trace, Self::area_of_interest_for(range))
+            .with_leakage_v(self.leakage_v(range))
+            .with_zener_test_current(self.config.zener_test_current)
+    }
+}
+
+/// The range a saved trace was swept over, judging by the voltages it reaches
+fn sweep_range_of(trace: &RawTrace) -> SweepRange {
+    let reaches = |sign: f64| trace.iter().any(|(v, _)| v * sign > 0.1);
+    match (reaches(1.0), reaches(-1.0)) {
+        (true, true) => SweepRange::Bipolar,
+        (false, true) => SweepRange::Negative,
+        _ => SweepRange::Positive,
+    }
 }
 
 impl From<&TwoTerminalDevice> for TwoTerminalDeviceType {
     fn from(d: &TwoTerminalDevice) -> Self {
-        match d {
-            TwoTerminalDevice::Diode => TwoTerminalDeviceType::Diode,
-        }
+        d.device_type
     }
 }
 
@@ -33,6 +100,7 @@ impl Display for TwoTerminalDeviceType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             TwoTerminalDeviceType::Diode => f.write_str("PN"),
+            TwoTerminalDeviceType::Zener => f.write_str("Zener"),
         }
     }
 }
@@ -43,6 +111,7 @@ impl FromStr for TwoTerminalDeviceType {
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         match s {
             "PN" => Ok(TwoTerminalDeviceType::Diode),
+            "Zener" => Ok(TwoTerminalDeviceType::Zener),
             _ => Err(()),
         }
     }
@@ -53,17 +122,21 @@ impl Device for TwoTerminalDevice {
     type Config = TwoTerminalDeviceConfig;
 
     fn area_of_interest(&self) -> AreaOfInterest {
-        AreaOfInterest::new_pos_i_pos_v(0.05, 5.0).extended()
+        Self::area_of_interest_for(self.config.sweep_range)
     }
 
-    fn trace(&self, backend: &dyn Backend) -> Result<Self::Trace> {
-        Ok(TwoTerminalTrace::from_raw_trace(
-            backend.trace_2()?,
-            self.area_of_interest(),
-        ))
+    fn trace(&self, backend: &dyn Backend, control: &TraceControl) -> Result<Self::Trace> {
+        let range = self.config.sweep_range;
+        Ok(self
+            .to_trace(backend.trace_2(range, control)?, range)
+            .with_capture_config(backend.capture_config())
+            .with_metadata(TraceMetadata::captured(SomeDeviceType::TwoTerminal(
+                self.device_type,
+            ))))
     }
 
     fn load_from_csv<P: AsRef<Path>>(&self, path: P) -> Result<Self::Trace> {
+        let header = read_header(path.as_ref())?;
         let mut vs = Vec::new();
         let mut is = Vec::new();
 
@@ -73,17 +146,21 @@ impl Device for TwoTerminalDevice {
             is.push(record.i);
         }
 
-        Ok(TwoTerminalTrace::from_raw_trace(
-            RawTrace::new(is, vs),
-            self.area_of_interest(),
-        ))
+        let trace = RawTrace::new(is, vs);
+        let range = sweep_range_of(&trace);
+        Ok(self
+            .to_trace(trace, range)
+            .with_capture_config(header.ad2)
+            .with_metadata(header.meta))
     }
 
     fn config(&self) -> TwoTerminalDeviceConfig {
-        TwoTerminalDeviceConfig {}
+        self.config.clone()
     }
 
-    fn set_config(&mut self, _: &Self::Config) {}
+    fn set_config(&mut self, config: &TwoTerminalDeviceConfig) {
+        self.config = config.clone();
+    }
 }
 
 #[derive(Deserialize)]
@@ -6,11 +6,12 @@ use itertools::Itertools;
 use itertools_num::linspace;
 use noisy_float::prelude::{r64, R64};
 
-use crate::backend::{Backend, BiasedTrace};
+use crate::backend::{Backend, BiasedTrace, TraceControl};
 use crate::dut::aoi::AreaOfInterest;
 use crate::dut::csv::load3_from_csv;
-use crate::dut::trace::{ThreeTerminalTrace, TwoTerminalTrace};
-use crate::dut::{BiasDrive, Device};
+use crate::dut::meta::TraceMetadata;
+use crate::dut::trace::{ThreeTerminalModelKind, ThreeTerminalTrace, TwoTerminalTrace};
+use crate::dut::{BiasDrive, Device, SomeDeviceType};
 use crate::Result;
 
 #[derive(Clone, Debug)]
@@ -100,22 +101,40 @@ impl Device for VoltageBiasedDevice {
         }
     }
 
-    fn trace(&self, backend: &dyn Backend) -> Result<Self::Trace> {
+    fn trace(&self, backend: &dyn Backend, control: &TraceControl) -> Result<Self::Trace> {
         let aoi = self.area_of_interest();
+        let capture_config = backend.capture_config();
         Ok(ThreeTerminalTrace::new(
             self.bias_polarity() < 0.0,
             backend
-                .trace_3(self.polarity(), BiasDrive::Voltage, self.bias_levels())?
+                .trace_3(
+                    self.polarity(),
+                    BiasDrive::Voltage,
+                    self.bias_levels(),
+                    control,
+                )?
                 .into_iter()
                 .map(|BiasedTrace { bias, trace }| {
-                    (bias, TwoTerminalTrace::from_raw_trace(trace, aoi))
+                    (
+                        bias,
+                        TwoTerminalTrace::from_raw_trace(trace, aoi)
+                            .with_capture_config(capture_config.clone()),
+                    )
                 })
                 .collect(),
+        )
+        .with_model_kind(Some(ThreeTerminalModelKind::Mosfet))
+        .with_metadata(
+            TraceMetadata::captured(SomeDeviceType::VoltageBiased(self.device_type))
+                .with_bias(BiasDrive::Voltage, self.polarity().raw()),
         ))
     }
 
     fn load_from_csv<P: AsRef<Path>>(&self, path: P) -> Result<Self::Trace> {
-        load3_from_csv(path, self.polarity() < 0.0, self.area_of_interest())
+        Ok(
+            load3_from_csv(path, self.polarity() < 0.0, self.area_of_interest())?
+                .with_model_kind(Some(ThreeTerminalModelKind::Mosfet)),
+        )
     }
 
     fn config(&self) -> VoltageBiasedDeviceConfig {
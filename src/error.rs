@@ -0,0 +1,300 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use failure::Fail;
+
+use crate::backend::TraceCancelled;
+
+/// Failures worth telling apart. They travel as `crate::Error`, so match on them after
+/// `error.downcast_ref::<TracerError>()`.
+#[derive(Debug)]
+pub enum TracerError {
+    /// No device is connected, or none matches the selection
+    NoDevice {
+        message: String,
+    },
+    DeviceBusy {
+        name: String,
+        serial: String,
+    },
+    /// Too many samples were lost or corrupted during an acquisition
+    SamplesLost {
+        lost: usize,
+        corrupted: usize,
+        total: usize,
+    },
+    Io {
+        path: PathBuf,
+        cause: std::io::Error,
+    },
+    /// A file doesn't hold the columns or the kind of trace expected
+    CsvSchema {
+        path: PathBuf,
+        message: String,
+    },
+    FitFailed {
+        message: String,
+    },
+    /// A trace file fits more than one device type, or none
+    AmbiguousTrace {
+        path: PathBuf,
+        message: String,
+    },
+    /// A command line value, or one passed as such, that can't be parsed
+    InvalidArgument {
+        message: String,
+    },
+    /// An acquisition parameter out of its range
+    InvalidConfig {
+        message: String,
+    },
+    /// The calibration fixtures measured too far off the nominal values
+    CalibrationFailed {
+        message: String,
+    },
+    NoConfigDir,
+    /// There's no trace to save yet
+    NoTrace,
+    /// Cairo failed to draw or write an image
+    Render {
+        message: String,
+    },
+    /// A part's model lacks what the matching metric compares
+    NotComparable {
+        message: String,
+    },
+    /// None of the inputs of a batch names a trace file
+    NoTraceFiles,
+}
+
+impl TracerError {
+    pub fn io(path: &Path, cause: std::io::Error) -> Self {
+        TracerError::Io {
+            path: path.to_path_buf(),
+            cause,
+        }
+    }
+
+    pub fn csv(path: &Path, error: csv::Error) -> Self {
+        if !error.is_io_error() {
+            return Self::csv_schema(path, &error.to_string());
+        }
+        match error.into_kind() {
+            csv::ErrorKind::Io(cause) => Self::io(path, cause),
+            kind => Self::csv_schema(path, &format!("{:?}", kind)),
+        }
+    }
+
+    pub fn csv_schema(path: &Path, message: &str) -> Self {
+        TracerError::CsvSchema {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn ambiguous_trace(path: &Path, message: &str) -> Self {
+        TracerError::AmbiguousTrace {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_argument(message: &str) -> Self {
+        TracerError::InvalidArgument {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_config(message: &str) -> Self {
+        TracerError::InvalidConfig {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn render(message: &str) -> Self {
+        TracerError::Render {
+            message: message.to_string(),
+        }
+    }
+
+    /// What the user can do about it
+    pub fn hint(&self) -> &'static str {
+        match self {
+            TracerError::NoDevice { .. } => {
+                "Check that the AD2 is plugged in and the WaveForms runtime is installed, \
+                 `curve-tracer-cli list-devices` shows the devices found"
+            }
+            TracerError::DeviceBusy { .. } => {
+                "Close WaveForms or any other application using the device, or pick another one"
+            }
+            TracerError::SamplesLost { .. } => {
+                "Lower the sampling frequency or plug the AD2 into a less busy USB port"
+            }
+            TracerError::Io { .. } => "Check that the path exists and is accessible",
+            TracerError::CsvSchema { .. } => {
+                "Expected tab-separated v and i columns, with a bias column for three-terminal \
+                 traces"
+            }
+            TracerError::FitFailed { .. } => {
+                "Check the connection to the DUT and that the trace reaches its conduction region"
+            }
+            TracerError::AmbiguousTrace { .. } => {
+                "Select the device type by hand, or save the trace again to record it in the file"
+            }
+            TracerError::InvalidArgument { .. } => "Run with --help for the accepted values",
+            TracerError::InvalidConfig { .. } => {
+                "Fix the value in the AD2 configuration file or on the command line"
+            }
+            TracerError::CalibrationFailed { .. } => {
+                "Connect each fixture when prompted and run the calibration again"
+            }
+            TracerError::NoConfigDir => "Set the HOME environment variable",
+            TracerError::NoTrace => "Capture or open a trace first",
+            TracerError::Render { .. } => {
+                "Check that the output path is writable and the disk isn't full"
+            }
+            TracerError::NotComparable { .. } => {
+                "Match by parameters every part's model has, over a range every model covers"
+            }
+            TracerError::NoTraceFiles => {
+                "Pass .csv or .csv.gz files, directories holding them or patterns matching them"
+            }
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TracerError::NoDevice { .. } => 2,
+            TracerError::DeviceBusy { .. } => 3,
+            TracerError::SamplesLost { .. } => 4,
+            TracerError::Io { .. } => 5,
+            TracerError::CsvSchema { .. } => 6,
+            TracerError::FitFailed { .. } => 7,
+            TracerError::AmbiguousTrace { .. } => 8,
+            TracerError::InvalidArgument { .. } => 9,
+            TracerError::InvalidConfig { .. } => 10,
+            TracerError::CalibrationFailed { .. } => 11,
+            TracerError::NoConfigDir => 12,
+            TracerError::NoTrace => 13,
+            TracerError::Render { .. } => 14,
+            TracerError::NotComparable { .. } => 15,
+            TracerError::NoTraceFiles => 16,
+        }
+    }
+}
+
+impl Display for TracerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TracerError::NoDevice { message } => f.write_str(message),
+            TracerError::DeviceBusy { name, serial } => write!(
+                f,
+                "Device {} {} is in use by another application",
+                name, serial
+            ),
+            TracerError::SamplesLost {
+                lost,
+                corrupted,
+                total,
+            } => write!(
+                f,
+                "Lost {} and got {} corrupted sample(-s) out of {}",
+                lost, corrupted, total
+            ),
+            TracerError::Io { path, cause } => write!(f, "{}: {}", path.display(), cause),
+            TracerError::CsvSchema { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            TracerError::FitFailed { message } => f.write_str(message),
+            TracerError::AmbiguousTrace { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            TracerError::InvalidArgument { message }
+            | TracerError::InvalidConfig { message }
+            | TracerError::CalibrationFailed { message }
+            | TracerError::Render { message }
+            | TracerError::NotComparable { message } => f.write_str(message),
+            TracerError::NoConfigDir => f.write_str("Can't find the user configuration directory"),
+            TracerError::NoTrace => f.write_str("No trace to save"),
+            TracerError::NoTraceFiles => f.write_str("No .csv or .csv.gz traces found"),
+        }
+    }
+}
+
+impl Fail for TracerError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match self {
+            TracerError::Io { cause, .. } => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+/// Process exit code for a failure, 1 for the ones not covered by `TracerError`
+pub fn exit_code(error: &crate::Error) -> i32 {
+    if let Some(error) = error.downcast_ref::<TracerError>() {
+        error.exit_code()
+    } else if error.downcast_ref::<TraceCancelled>().is_some() {
+        130
+    } else {
+        1
+    }
+}
+
+/// The error message followed by a hint when there is one
+pub fn describe(error: &crate::Error) -> String {
+    match error.downcast_ref::<TracerError>() {
+        Some(error) => format!("{}\n{}", error, error.hint()),
+        None => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::backend::{Replay, SweepRange};
+    use crate::dut::trace::{NullTrace, Trace};
+    use crate::error::{exit_code, TracerError};
+
+    #[test]
+    pub fn file_errors_are_told_apart() {
+        let wrong_columns = std::env::temp_dir().join("ks-curve-tracer-wrong-columns.csv");
+        std::fs::write(&wrong_columns, "x\ty\n1\t2\n").unwrap();
+
+        let missing = Replay::from_csv("res/missing.csv").err().unwrap();
+        let schema = Replay::from_csv(&wrong_columns).err().unwrap();
+        std::fs::remove_file(&wrong_columns).unwrap();
+
+        match missing.downcast_ref::<TracerError>() {
+            Some(TracerError::Io { path, .. }) => assert_eq!(path, Path::new("res/missing.csv")),
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(exit_code(&missing), 5);
+        assert!(matches!(
+            schema.downcast_ref::<TracerError>(),
+            Some(TracerError::CsvSchema { .. })
+        ));
+        assert_eq!(exit_code(&schema), 6);
+    }
+
+    #[test]
+    pub fn argument_and_missing_trace_errors_are_told_apart() {
+        let range = "sideways".parse::<SweepRange>().err().unwrap();
+        assert!(matches!(
+            range.downcast_ref::<TracerError>(),
+            Some(TracerError::InvalidArgument { .. })
+        ));
+        assert_eq!(exit_code(&range), 9);
+
+        let save = NullTrace {}
+            .save_as_csv(Path::new("unsaved.csv"))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            save.downcast_ref::<TracerError>(),
+            Some(TracerError::NoTrace)
+        ));
+        assert_eq!(exit_code(&save), 13);
+    }
+}
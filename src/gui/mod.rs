@@ -1,3 +1,6 @@
+pub mod plot;
+pub mod settings;
+pub mod viewport;
 pub mod widgets;
 
 use itertools::Itertools;
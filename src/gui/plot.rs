@@ -0,0 +1,357 @@
+//! Renders traces onto any cairo surface, the GUI drawing area as well as image files
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::Path;
+
+use cairo::{
+    Context, FontSlant, FontWeight, Format, ImageSurface, PdfSurface, Status, Surface, SvgSurface,
+};
+use itertools_num::linspace;
+
+use crate::dut::trace::GuiTrace;
+use crate::error::TracerError;
+use crate::gui::viewport::Viewport;
+use crate::Result;
+
+const MARGIN: f64 = 10.0;
+const FONT_SIZE: f64 = 13.0;
+const TITLE_HEIGHT: f64 = 24.0;
+const LEGEND_HEIGHT: f64 = 24.0;
+
+/// How to render a trace
+#[derive(Clone, Debug)]
+pub struct Plot {
+    pub width: f64,
+    pub height: f64,
+    /// Voltage at the edge of the plot, V
+    pub v_zoom: f64,
+    /// Current at the edge of the plot, A
+    pub i_zoom: f64,
+    pub title: Option<String>,
+    /// Whether to draw the legend of three-terminal traces under the plot
+    pub legend: bool,
+}
+
+/// Image formats a plot can be saved in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlotFormat {
+    Png,
+    Svg,
+    Pdf,
+}
+
+impl PlotFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path
+            .extension()
+            .and_then(OsStr::to_str)
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            Some("png") => Ok(PlotFormat::Png),
+            Some("svg") => Ok(PlotFormat::Svg),
+            Some("pdf") => Ok(PlotFormat::Pdf),
+            _ => Err(TracerError::invalid_argument(&format!(
+                "Can't tell the image format of {}, expected a .png, .svg or .pdf file",
+                path.display()
+            ))
+            .into()),
+        }
+    }
+}
+
+fn cairo_error(status: Status) -> failure::Error {
+    TracerError::render(&format!("Can't render the plot: {:?}", status)).into()
+}
+
+/// Fails with the status drawing left `cr` in
+fn check_drawn(cr: &Context) -> Result<()> {
+    match cr.status() {
+        Status::Success => Ok(()),
+        status => Err(cairo_error(status)),
+    }
+}
+
+/// Finishes `surface`, which is when SVG and PDF surfaces write out the rest of their file,
+/// and fails if that didn't go through
+fn finish(surface: &Surface) -> Result<()> {
+    surface.finish();
+    match surface.status() {
+        Status::Success => Ok(()),
+        status => Err(cairo_error(status)),
+    }
+}
+
+/// Strokes `text` haloed in white, so that it stays legible over the grid and the trace
+fn halo_text(cr: &Context, text: &str) {
+    cr.text_path(text);
+    cr.set_source_rgba(1.0, 1.0, 1.0, 1.0);
+    cr.set_line_width(2.0);
+    cr.stroke_preserve();
+    cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
+    cr.fill();
+}
+
+impl Plot {
+    pub fn new(width: f64, height: f64, v_zoom: f64, i_zoom: f64) -> Self {
+        Plot {
+            width,
+            height,
+            v_zoom,
+            i_zoom,
+            title: None,
+            legend: false,
+        }
+    }
+
+    pub fn with_title(self, title: Option<String>) -> Self {
+        Plot { title, ..self }
+    }
+
+    pub fn with_legend(self, legend: bool) -> Self {
+        Plot { legend, ..self }
+    }
+
+    /// Draws the grid, the axes with their labels, the trace and its model, filling the
+    /// `width` × `height` area at the origin of `cr`
+    pub fn draw(&self, cr: &Context, trace: &dyn GuiTrace) {
+        let legend = if self.legend {
+            trace.legend()
+        } else {
+            Vec::new()
+        };
+        let title_height = if self.title.is_some() {
+            TITLE_HEIGHT
+        } else {
+            0.0
+        };
+        let legend_height = if legend.is_empty() {
+            0.0
+        } else {
+            LEGEND_HEIGHT
+        };
+
+        cr.save();
+        cr.identity_matrix();
+        cr.translate(0.5, 0.5);
+
+        cr.set_source_rgb(1.0, 1.0, 1.0);
+        cr.paint();
+
+        cr.set_source_rgb(0.0, 0.0, 0.0);
+        cr.set_line_width(1.0);
+        cr.select_font_face("Monospace", FontSlant::Normal, FontWeight::Normal);
+        cr.set_font_size(FONT_SIZE);
+
+        if let Some(title) = &self.title {
+            let extents = cr.text_extents(title);
+            cr.move_to((self.width - extents.width) / 2.0, MARGIN + extents.height);
+            cr.show_text(title);
+        }
+
+        cr.translate(MARGIN, MARGIN + title_height);
+
+        let viewport = Viewport::new(
+            &trace.area_of_interest(),
+            self.v_zoom,
+            self.i_zoom,
+            self.width - 2.0 * MARGIN,
+            self.height - 2.0 * MARGIN - title_height - legend_height,
+        );
+
+        self.draw_grid(cr, &viewport);
+        trace.draw(cr, &viewport);
+        self.draw_labels(cr, &viewport);
+        trace.draw_model(cr, &viewport);
+
+        if !legend.is_empty() {
+            let mut x = 0.0;
+            let y = viewport.height + LEGEND_HEIGHT / 2.0 + MARGIN / 2.0;
+            for (label, color) in legend {
+                cr.set_source_rgb(color.0, color.1, color.2);
+                cr.rectangle(x, y - FONT_SIZE / 2.0, FONT_SIZE, FONT_SIZE);
+                cr.fill();
+                x += FONT_SIZE + 4.0;
+
+                cr.set_source_rgb(0.0, 0.0, 0.0);
+                let extents = cr.text_extents(&label);
+                cr.move_to(x, y + extents.height / 2.0);
+                cr.show_text(&label);
+                x += extents.x_advance + 12.0;
+            }
+        }
+
+        cr.restore();
+    }
+
+    fn draw_grid(&self, cr: &Context, viewport: &Viewport) {
+        let set_dash = |ix: usize| match ix {
+            0 | 10 => {
+                cr.set_dash(&[], 0.0);
+            }
+            5 => {
+                cr.set_dash(&[1.0, 2.0], 0.0);
+            }
+            _ => {
+                cr.set_dash(&[1.0, 3.0], 0.0);
+            }
+        };
+
+        for (ix, i_gridline) in linspace(viewport.min_i, viewport.max_i, 11).enumerate() {
+            set_dash(ix);
+            cr.move_to(0.0, viewport.y(i_gridline));
+            cr.line_to(viewport.width, viewport.y(i_gridline));
+            cr.stroke();
+        }
+
+        for (ix, v_gridline) in linspace(viewport.min_v, viewport.max_v, 11).enumerate() {
+            set_dash(ix);
+            cr.move_to(viewport.x(v_gridline), 0.0);
+            cr.line_to(viewport.x(v_gridline), viewport.height);
+            cr.stroke();
+        }
+
+        // Axes through the origin, on the border unless the plot spans both polarities
+        cr.set_dash(&[], 0.0);
+        cr.move_to(0.0, viewport.y(0.0));
+        cr.line_to(viewport.width, viewport.y(0.0));
+        cr.move_to(viewport.x(0.0), 0.0);
+        cr.line_to(viewport.x(0.0), viewport.height);
+        cr.stroke();
+    }
+
+    fn draw_labels(&self, cr: &Context, viewport: &Viewport) {
+        let is_origin =
+            |gridline: f64, min: f64, max: f64| gridline.abs() < (max - min).abs() / 20.0;
+
+        for i_gridline in linspace(viewport.min_i, viewport.max_i, 11) {
+            if !is_origin(i_gridline, viewport.min_i, viewport.max_i) {
+                let text = format!("{:04.1}mA", i_gridline * 1000.0);
+                let extents = cr.text_extents(&text);
+                cr.move_to(
+                    2.0,
+                    (viewport.y(i_gridline) + extents.height + 2.0).min(viewport.height),
+                );
+                halo_text(cr, &text);
+            }
+        }
+
+        for (ix, v_gridline) in linspace(viewport.min_v, viewport.max_v, 11).enumerate() {
+            if !is_origin(v_gridline, viewport.min_v, viewport.max_v) {
+                let text = format!("{:.2}V", v_gridline);
+                let extents = cr.text_extents(&text);
+                let x = match ix {
+                    0 => viewport.x(v_gridline) + 2.0,
+                    10 => viewport.x(v_gridline) - extents.width - 2.0,
+                    _ => viewport.x(v_gridline) - extents.width / 2.0,
+                };
+                cr.move_to(x, extents.height + 2.0);
+                halo_text(cr, &text);
+            }
+        }
+    }
+
+    /// Renders the plot to `path`, in the format its extension calls for
+    pub fn save(&self, trace: &dyn GuiTrace, path: &Path) -> Result<()> {
+        match PlotFormat::from_path(path)? {
+            PlotFormat::Png => {
+                let surface =
+                    ImageSurface::create(Format::ARgb32, self.width as i32, self.height as i32)
+                        .map_err(cairo_error)?;
+                let cr = Context::new(&surface);
+                self.draw(&cr, trace);
+                check_drawn(&cr)?;
+                let mut file = File::create(path).map_err(|e| TracerError::io(path, e))?;
+                surface
+                    .write_to_png(&mut file)
+                    .map_err(|e| TracerError::render(&format!("Can't write the PNG: {:?}", e)))?;
+            }
+            PlotFormat::Svg => {
+                let surface =
+                    SvgSurface::new(self.width, self.height, Some(path)).map_err(cairo_error)?;
+                let cr = Context::new(&surface);
+                self.draw(&cr, trace);
+                check_drawn(&cr)?;
+                finish(&surface)?;
+            }
+            PlotFormat::Pdf => {
+                let surface =
+                    PdfSurface::new(self.width, self.height, path).map_err(cairo_error)?;
+                let cr = Context::new(&surface);
+                self.draw(&cr, trace);
+                check_drawn(&cr)?;
+                finish(&surface)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::dut::trace::{DrawableTrace, GuiTrace, ShareableTrace, TwoTerminalGuiTrace};
+    use crate::dut::{
+        CurrentBiasedDevice, CurrentBiasedDeviceType, Device, TwoTerminalDevice,
+        TwoTerminalDeviceType,
+    };
+    use crate::error::TracerError;
+    use crate::gui::plot::{Plot, PlotFormat};
+
+    #[test]
+    pub fn format_follows_the_extension() {
+        assert_eq!(
+            PlotFormat::from_path(Path::new("plot.PNG")).unwrap(),
+            PlotFormat::Png
+        );
+        assert_eq!(
+            PlotFormat::from_path(Path::new("plot.svg")).unwrap(),
+            PlotFormat::Svg
+        );
+        assert!(PlotFormat::from_path(Path::new("plot.csv")).is_err());
+    }
+
+    #[test]
+    pub fn renders_without_a_display() {
+        let device = TwoTerminalDevice::from_type(TwoTerminalDeviceType::Diode);
+        let trace = TwoTerminalGuiTrace::from(device.load_from_csv("res/1N4148.csv").unwrap());
+        let plot = Plot::new(400.0, 300.0, 1.0, 0.05).with_title(Some("1N4148".to_string()));
+
+        for extension in &["png", "svg", "pdf"] {
+            let path = std::env::temp_dir().join(format!("ks-curve-tracer-plot.{}", extension));
+            plot.save(&trace, &path).unwrap();
+            assert!(std::fs::metadata(&path).unwrap().len() > 0);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn unwritable_paths_fail() {
+        let device = TwoTerminalDevice::from_type(TwoTerminalDeviceType::Diode);
+        let trace = TwoTerminalGuiTrace::from(device.load_from_csv("res/1N4148.csv").unwrap());
+        let plot = Plot::new(400.0, 300.0, 1.0, 0.05);
+
+        for extension in &["png", "svg", "pdf"] {
+            let path = std::env::temp_dir()
+                .join("ks-curve-tracer-missing")
+                .join(format!("plot.{}", extension));
+            let error = plot.save(&trace, &path).unwrap_err();
+            assert!(error.downcast_ref::<TracerError>().is_some());
+        }
+    }
+
+    #[test]
+    pub fn three_terminal_legend_lists_bias_levels() {
+        let device = CurrentBiasedDevice::from_type(CurrentBiasedDeviceType::NPN);
+        let trace: Box<dyn GuiTrace> = device
+            .load_from_csv("res/2N3904.csv.gz")
+            .unwrap()
+            .as_gui_trace();
+
+        let legend = trace.legend();
+        assert_eq!(legend.len(), 5);
+        assert_eq!(legend[0].0, "10.000µA");
+    }
+}
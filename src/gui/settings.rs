@@ -0,0 +1,355 @@
+use gtk::ComboBoxExt;
+use gtk::ComboBoxTextExt;
+use gtk::ContainerExt;
+use gtk::DialogExt;
+use gtk::EntryExt;
+use gtk::FileChooserExt;
+use gtk::GridExt;
+use gtk::GtkWindowExt;
+use gtk::LabelExt;
+use gtk::SpinButtonExt;
+use gtk::WidgetExt;
+use gtk::{DialogFlags, FileChooserAction, ResponseType};
+use std::path::{Path, PathBuf};
+
+use crate::backend::{AD2Config, AD2DeviceInfo, DeviceSelection, DeviceSelector};
+use crate::dut::meta::TraceMetadata;
+
+const ANY_FREE_DEVICE_ID: &str = "";
+
+fn spin_button(
+    grid: &gtk::Grid,
+    row: i32,
+    label: &str,
+    value: f64,
+    range: (f64, f64),
+    step: f64,
+    digits: u32,
+) -> gtk::SpinButton {
+    let label = gtk::Label::new(Some(label));
+    label.set_xalign(0.0);
+    grid.attach(&label, 0, row, 1, 1);
+
+    let spinner = gtk::SpinButton::new_with_range(range.0, range.1, step);
+    spinner.set_digits(digits);
+    spinner.set_numeric(true);
+    spinner.set_hexpand(true);
+    spinner.set_value(value);
+    grid.attach(&spinner, 1, row, 1, 1);
+
+    spinner
+}
+
+fn text_entry(grid: &gtk::Grid, row: i32, label: &str, text: Option<&str>) -> gtk::Entry {
+    let label = gtk::Label::new(Some(label));
+    label.set_xalign(0.0);
+    grid.attach(&label, 0, row, 1, 1);
+
+    let entry = gtk::Entry::new();
+    entry.set_hexpand(true);
+    entry.set_text(text.unwrap_or(""));
+    grid.attach(&entry, 1, row, 1, 1);
+
+    entry
+}
+
+fn entry_text(entry: &gtk::Entry) -> Option<String> {
+    entry
+        .get_text()
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// Picks the file to save a trace to, along with the part number and notes to save with it
+pub fn save_trace_dialog(
+    parent: &gtk::Window,
+    metadata: &TraceMetadata,
+) -> Option<(PathBuf, TraceMetadata)> {
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some("Save trace"),
+        Some(parent),
+        FileChooserAction::Save,
+        &[
+            ("_Cancel", ResponseType::Cancel),
+            ("_Save", ResponseType::Accept),
+        ],
+    );
+    dialog.set_do_overwrite_confirmation(true);
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(8);
+    grid.set_column_spacing(8);
+    let part_number = text_entry(&grid, 0, "Part number", metadata.part_number.as_deref());
+    let notes = text_entry(&grid, 1, "Notes", metadata.notes.as_deref());
+    grid.show_all();
+    dialog.set_extra_widget(&grid);
+
+    let result = if dialog.run() == ResponseType::Accept {
+        dialog.get_filename().map(|filename| {
+            (
+                filename,
+                TraceMetadata {
+                    part_number: entry_text(&part_number),
+                    notes: entry_text(&notes),
+                    ..metadata.clone()
+                },
+            )
+        })
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
+
+/// Asks for the part number of a capture session and the folder to save its traces to
+pub fn session_dialog(
+    parent: &gtk::Window,
+    part_number: Option<&str>,
+    folder: &Path,
+) -> Option<(String, PathBuf)> {
+    let dialog = gtk::Dialog::new_with_buttons(
+        Some("Start session"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[
+            ("_Cancel", ResponseType::Cancel),
+            ("_Start", ResponseType::Accept),
+        ],
+    );
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(8);
+    grid.set_column_spacing(8);
+    grid.set_margin_top(8);
+    grid.set_margin_bottom(8);
+    grid.set_margin_start(8);
+    grid.set_margin_end(8);
+
+    let part_number = text_entry(&grid, 0, "Part number", part_number);
+
+    let label = gtk::Label::new(Some("Folder"));
+    label.set_xalign(0.0);
+    grid.attach(&label, 0, 1, 1, 1);
+    let folder_button =
+        gtk::FileChooserButton::new("Session folder", FileChooserAction::SelectFolder);
+    folder_button.set_hexpand(true);
+    folder_button.set_filename(folder);
+    grid.attach(&folder_button, 1, 1, 1, 1);
+
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+
+    let result = if dialog.run() == ResponseType::Accept {
+        entry_text(&part_number)
+            .and_then(|part_number| folder_button.get_filename().map(|dir| (part_number, dir)))
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
+
+pub fn ad2_settings_dialog(parent: &gtk::Window, config: &AD2Config) -> Option<AD2Config> {
+    let dialog = gtk::Dialog::new_with_buttons(
+        Some("Acquisition settings"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[
+            ("_Cancel", ResponseType::Cancel),
+            ("_Apply", ResponseType::Accept),
+        ],
+    );
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(8);
+    grid.set_column_spacing(8);
+    grid.set_margin_top(8);
+    grid.set_margin_bottom(8);
+    grid.set_margin_start(8);
+    grid.set_margin_end(8);
+
+    let current_limit = spin_button(
+        &grid,
+        0,
+        "Current limit, mA",
+        config.diode_current_limit_ma,
+        (1.0, 100.0),
+        1.0,
+        1,
+    );
+    let shunt = spin_button(
+        &grid,
+        1,
+        "Current shunt, Ω",
+        config.current_shunt_ohms,
+        (1.0, 10_000.0),
+        1.0,
+        2,
+    );
+    let bias_limiter = spin_button(
+        &grid,
+        2,
+        "Bias limiter, kΩ",
+        config.bias_limiter_ohms / 1000.0,
+        (1.0, 10_000.0),
+        1.0,
+        1,
+    );
+    let sampling_time = spin_button(
+        &grid,
+        3,
+        "Sweep time, s",
+        config.sampling_time,
+        (0.01, 10.0),
+        0.1,
+        2,
+    );
+    let bias_level_sampling_time = spin_button(
+        &grid,
+        4,
+        "Sweep time per bias level, s",
+        config.bias_level_sampling_time,
+        (0.01, 10.0),
+        0.1,
+        2,
+    );
+    let sampling_frequency = spin_button(
+        &grid,
+        5,
+        "Sampling frequency, kHz",
+        config.sampling_frequency / 1000.0,
+        (1.0, 100_000.0),
+        100.0,
+        0,
+    );
+    let cycles_to_sample = spin_button(
+        &grid,
+        6,
+        "Cycles to sample",
+        f64::from(config.cycles_to_sample),
+        (1.0, 100.0),
+        1.0,
+        0,
+    );
+    let cycles_to_skip = spin_button(
+        &grid,
+        7,
+        "Cycles to skip",
+        f64::from(config.cycles_to_skip),
+        (0.0, 100.0),
+        1.0,
+        0,
+    );
+    let max_v = spin_button(&grid, 8, "Max V", config.max_v, (-5.0, 5.0), 0.1, 2);
+    let min_v = spin_button(&grid, 9, "Min V", config.min_v, (-5.0, 5.0), 0.1, 2);
+
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+
+    let result = if dialog.run() == ResponseType::Accept {
+        Some(AD2Config {
+            diode_current_limit_ma: current_limit.get_value(),
+            current_shunt_ohms: shunt.get_value(),
+            bias_limiter_ohms: bias_limiter.get_value() * 1000.0,
+            sampling_time: sampling_time.get_value(),
+            bias_level_sampling_time: bias_level_sampling_time.get_value(),
+            sampling_frequency: sampling_frequency.get_value() * 1000.0,
+            cycles_to_sample: cycles_to_sample.get_value_as_int() as u32,
+            cycles_to_skip: cycles_to_skip.get_value_as_int() as u32,
+            max_v: max_v.get_value(),
+            min_v: min_v.get_value(),
+            ..config.clone()
+        })
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
+
+pub fn device_picker_dialog(
+    parent: &gtk::Window,
+    devices: &[AD2DeviceInfo],
+    selection: &DeviceSelection,
+) -> Option<DeviceSelection> {
+    let dialog = gtk::Dialog::new_with_buttons(
+        Some("Select device"),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[
+            ("_Cancel", ResponseType::Cancel),
+            ("_Select", ResponseType::Accept),
+        ],
+    );
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(8);
+    grid.set_column_spacing(8);
+    grid.set_margin_top(8);
+    grid.set_margin_bottom(8);
+    grid.set_margin_start(8);
+    grid.set_margin_end(8);
+
+    let label = gtk::Label::new(Some("Device"));
+    label.set_xalign(0.0);
+    grid.attach(&label, 0, 0, 1, 1);
+
+    let device_combo = gtk::ComboBoxText::new();
+    device_combo.set_hexpand(true);
+    device_combo.append(Some(ANY_FREE_DEVICE_ID), "First device not in use");
+    for device in devices {
+        let mut text = format!("{} {}", device.name, device.serial);
+        if device.in_use {
+            text.push_str(" (in use)");
+        }
+        device_combo.append(Some(&device.serial), &text);
+    }
+    let active_id = selection
+        .device
+        .as_ref()
+        .and_then(|selector| {
+            devices
+                .iter()
+                .find(|device| selector.matches(device))
+                .map(|device| device.serial.as_str())
+        })
+        .unwrap_or(ANY_FREE_DEVICE_ID);
+    device_combo.set_active_id(Some(active_id));
+    grid.attach(&device_combo, 1, 0, 1, 1);
+
+    let max_config = devices
+        .iter()
+        .map(|device| device.configs.len())
+        .max()
+        .unwrap_or(1)
+        .max(1)
+        - 1;
+    let config = spin_button(
+        &grid,
+        1,
+        "Config",
+        selection.config as f64,
+        (0.0, max_config as f64),
+        1.0,
+        0,
+    );
+
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+
+    let result = if dialog.run() == ResponseType::Accept {
+        let device = device_combo
+            .get_active_id()
+            .filter(|id| id.as_str() != ANY_FREE_DEVICE_ID)
+            .map(|id| DeviceSelector::Serial(id.to_string()));
+        Some(DeviceSelection {
+            device,
+            config: config.get_value_as_int() as usize,
+        })
+    } else {
+        None
+    };
+    dialog.close();
+    result
+}
@@ -0,0 +1,88 @@
+use crate::dut::aoi::AreaOfInterest;
+
+/// Maps voltages and currents onto the plot area, `(0, 0)` being its top left corner.
+///
+/// An axis spans `[-zoom, zoom]` when the area of interest covers both polarities, otherwise
+/// it starts at 0 and is mirrored for negative-only areas, so the curve always grows
+/// towards the top right.
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    pub min_v: f64,
+    pub max_v: f64,
+    pub min_i: f64,
+    pub max_i: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Viewport {
+    pub fn new(aoi: &AreaOfInterest, v_zoom: f64, i_zoom: f64, width: f64, height: f64) -> Self {
+        let axis = |zoom: f64, bipolar: bool, polarity: f64| {
+            if bipolar {
+                (-zoom, zoom)
+            } else {
+                (0.0, zoom * polarity)
+            }
+        };
+        let (min_v, max_v) = axis(v_zoom, aoi.v_bipolar(), aoi.v_polarity());
+        let (min_i, max_i) = axis(i_zoom, aoi.i_bipolar(), aoi.i_polarity());
+        Viewport {
+            min_v,
+            max_v,
+            min_i,
+            max_i,
+            width,
+            height,
+        }
+    }
+
+    pub fn v_factor(&self) -> f64 {
+        self.width / (self.max_v - self.min_v)
+    }
+
+    pub fn i_factor(&self) -> f64 {
+        self.height / (self.max_i - self.min_i)
+    }
+
+    pub fn x(&self, v: f64) -> f64 {
+        (v - self.min_v) * self.v_factor()
+    }
+
+    pub fn y(&self, i: f64) -> f64 {
+        self.height - (i - self.min_i) * self.i_factor()
+    }
+
+    /// Voltages within the viewport, lowest first
+    pub fn v_range(&self) -> (f64, f64) {
+        (self.min_v.min(self.max_v), self.min_v.max(self.max_v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dut::aoi::AreaOfInterest;
+    use crate::gui::viewport::Viewport;
+
+    #[test]
+    pub fn bipolar_viewport_centers_the_origin() {
+        let aoi = AreaOfInterest::new_bipolar(0.05, 5.0).extended();
+
+        let viewport = Viewport::new(&aoi, 2.0, 0.01, 400.0, 200.0);
+
+        assert_relative_eq!(viewport.x(0.0), 200.0);
+        assert_relative_eq!(viewport.y(0.0), 100.0);
+        assert_relative_eq!(viewport.x(-2.0), 0.0);
+        assert_relative_eq!(viewport.y(0.01), 0.0);
+    }
+
+    #[test]
+    pub fn negative_viewport_is_mirrored() {
+        let aoi = AreaOfInterest::new_neg_i_neg_v(0.05, 5.0).extended();
+
+        let viewport = Viewport::new(&aoi, 2.0, 0.01, 400.0, 200.0);
+
+        assert_relative_eq!(viewport.x(-2.0), 400.0);
+        assert_relative_eq!(viewport.y(-0.01), 0.0);
+        assert_eq!(viewport.v_range(), (-2.0, 0.0));
+    }
+}
@@ -12,6 +12,7 @@ use relm::{Relm, Update, Widget};
 pub enum BJTOptionsMsg {
     MinBias(R64),
     MaxBias(R64),
+    SatCurrent(f64),
     Updated(CurrentBiasedDeviceConfig),
 }
 
@@ -73,6 +74,13 @@ impl Update for BJTOptionsWidget {
                     .stream()
                     .emit(BJTOptionsMsg::Updated(config.clone()));
             }
+            BJTOptionsMsg::SatCurrent(ma) => {
+                self.model.config.sat_current = ma / 1000.0;
+                self.model
+                    .relm
+                    .stream()
+                    .emit(BJTOptionsMsg::Updated(self.model.config.clone()));
+            }
             BJTOptionsMsg::Updated(_) => {}
         }
     }
@@ -108,6 +116,19 @@ impl Widget for BJTOptionsWidget {
 
         hbox.add(&gtk::Label::new(Some("µA")));
 
+        let sat_label = gtk::Label::new(Some(""));
+        sat_label.set_markup("V<sub>CE(sat)</sub> at I<sub>C</sub>");
+        hbox.add(&sat_label);
+
+        let sat_spinner = gtk::SpinButton::new_with_range(0.1, 50.0, 0.1);
+        sat_spinner.set_digits(1);
+        sat_spinner.set_numeric(true);
+        sat_spinner.set_hexpand(true);
+        sat_spinner.set_value(model.config.sat_current * 1000.0);
+        hbox.add(&sat_spinner);
+
+        hbox.add(&gtk::Label::new(Some("mA")));
+
         connect!(
             relm,
             min_spinner,
@@ -120,6 +141,12 @@ impl Widget for BJTOptionsWidget {
             connect_value_changed(btn),
             BJTOptionsMsg::MaxBias(r64(btn.get_value()))
         );
+        connect!(
+            relm,
+            sat_spinner,
+            connect_value_changed(btn),
+            BJTOptionsMsg::SatCurrent(btn.get_value())
+        );
 
         hbox.show_all();
 
@@ -4,13 +4,15 @@ use relm::{Relm, Update, Widget};
 use crate::dut::{CurrentBiasedDeviceConfig, TwoTerminalDeviceConfig, VoltageBiasedDeviceConfig};
 use crate::gui::widgets::bjt::{BJTOptionsMsg, BJTOptionsWidget};
 use crate::gui::widgets::fet::{FETOptionsMsg, FETOptionsWidget};
+use crate::gui::widgets::two::{TwoTerminalOptionsMsg, TwoTerminalOptionsWidget};
 
 pub mod bjt;
 pub mod fet;
+pub mod two;
 
 #[derive(Clone, Debug)]
 pub enum DeviceConfig {
-    None,
+    TwoTerminal(TwoTerminalDeviceConfig),
     BJT(CurrentBiasedDeviceConfig),
     FET(VoltageBiasedDeviceConfig),
 }
@@ -26,8 +28,8 @@ impl DeviceConfig {
 
 impl Into<Option<TwoTerminalDeviceConfig>> for &DeviceConfig {
     fn into(self) -> Option<TwoTerminalDeviceConfig> {
-        if let DeviceConfig::None = self {
-            Some(TwoTerminalDeviceConfig {})
+        if let DeviceConfig::TwoTerminal(config) = self {
+            Some(config.clone())
         } else {
             None
         }
@@ -56,6 +58,10 @@ impl Into<Option<VoltageBiasedDeviceConfig>> for &DeviceConfig {
 
 enum DeviceConfigModel {
     None,
+    TwoTerminal(
+        relm::Component<TwoTerminalOptionsWidget>,
+        TwoTerminalDeviceConfig,
+    ),
     BJT(relm::Component<BJTOptionsWidget>, CurrentBiasedDeviceConfig),
     FET(relm::Component<FETOptionsWidget>, VoltageBiasedDeviceConfig),
 }
@@ -95,6 +101,9 @@ impl Update for DeviceConfigWidget {
             DeviceConfigMsg::SetConfig(config) => {
                 match &self.model.device_config_model {
                     DeviceConfigModel::None => {}
+                    DeviceConfigModel::TwoTerminal(c, _) => {
+                        self.root().remove_widget(c.clone());
+                    }
                     DeviceConfigModel::BJT(c, _) => {
                         self.root().remove_widget(c.clone());
                     }
@@ -104,7 +113,24 @@ impl Update for DeviceConfigWidget {
                 };
 
                 self.model.device_config_model = match config {
-                    DeviceConfig::None => DeviceConfigModel::None,
+                    DeviceConfig::TwoTerminal(c) => {
+                        let comp = self
+                            .root()
+                            .add_widget::<TwoTerminalOptionsWidget>(c.clone());
+                        let stream = self.model.relm.stream().clone();
+                        comp.stream().observe(move |msg| {
+                            #[allow(clippy::single_match)]
+                            match msg {
+                                TwoTerminalOptionsMsg::Updated(config) => {
+                                    stream.emit(DeviceConfigMsg::ConfigUpdated(
+                                        DeviceConfig::TwoTerminal(config.clone()),
+                                    ))
+                                }
+                                _ => {}
+                            };
+                        });
+                        DeviceConfigModel::TwoTerminal(comp, c)
+                    }
                     DeviceConfig::BJT(c) => {
                         let comp = self.root().add_widget::<BJTOptionsWidget>(c.clone());
                         let stream = self.model.relm.stream().clone();
@@ -164,7 +190,10 @@ impl Widget for DeviceConfigWidget {
 impl DeviceConfigWidget {
     pub fn config(&self) -> DeviceConfig {
         match &self.model.device_config_model {
-            DeviceConfigModel::None => DeviceConfig::None,
+            DeviceConfigModel::None => {
+                DeviceConfig::TwoTerminal(TwoTerminalDeviceConfig::default())
+            }
+            DeviceConfigModel::TwoTerminal(_, c) => DeviceConfig::TwoTerminal(c.clone()),
             DeviceConfigModel::BJT(_, c) => DeviceConfig::BJT(c.clone()),
             DeviceConfigModel::FET(_, c) => DeviceConfig::FET(c.clone()),
         }
@@ -0,0 +1,162 @@
+use crate::backend::SweepRange;
+use crate::dut::TwoTerminalDeviceConfig;
+use gtk::ComboBoxExt;
+use gtk::ComboBoxTextExt;
+use gtk::ContainerExt;
+use gtk::LabelExt;
+use gtk::SpinButtonExt;
+use gtk::SpinButtonSignals;
+use gtk::WidgetExt;
+use relm::{Relm, Update, Widget};
+
+#[derive(Msg)]
+pub enum TwoTerminalOptionsMsg {
+    SweepRange(Option<SweepRange>),
+    LeakageVoltage(f64),
+    TestCurrent(f64),
+    Updated(TwoTerminalDeviceConfig),
+}
+
+struct Widgets {
+    root: gtk::Box,
+    leakage_spinner: gtk::SpinButton,
+}
+
+pub struct TwoTerminalOptionsWidget {
+    model: TwoTerminalOptionsModel,
+    widgets: Widgets,
+}
+
+pub struct TwoTerminalOptionsModel {
+    relm: Relm<TwoTerminalOptionsWidget>,
+    config: TwoTerminalDeviceConfig,
+}
+
+impl Update for TwoTerminalOptionsWidget {
+    type Model = TwoTerminalOptionsModel;
+    type ModelParam = TwoTerminalDeviceConfig;
+    type Msg = TwoTerminalOptionsMsg;
+
+    fn model(relm: &Relm<Self>, config: TwoTerminalDeviceConfig) -> TwoTerminalOptionsModel {
+        TwoTerminalOptionsModel {
+            relm: relm.clone(),
+            config,
+        }
+    }
+
+    fn update(&mut self, event: Self::Msg) {
+        match event {
+            TwoTerminalOptionsMsg::SweepRange(Some(range)) => {
+                self.model.config.sweep_range = range;
+                self.widgets
+                    .leakage_spinner
+                    .set_sensitive(range.covers_negative());
+                self.model
+                    .relm
+                    .stream()
+                    .emit(TwoTerminalOptionsMsg::Updated(self.model.config.clone()));
+            }
+            TwoTerminalOptionsMsg::SweepRange(None) => {}
+            TwoTerminalOptionsMsg::LeakageVoltage(v) => {
+                self.model.config.leakage_v = v;
+                self.model
+                    .relm
+                    .stream()
+                    .emit(TwoTerminalOptionsMsg::Updated(self.model.config.clone()));
+            }
+            TwoTerminalOptionsMsg::TestCurrent(ma) => {
+                self.model.config.zener_test_current = Some(ma / 1000.0);
+                self.model
+                    .relm
+                    .stream()
+                    .emit(TwoTerminalOptionsMsg::Updated(self.model.config.clone()));
+            }
+            TwoTerminalOptionsMsg::Updated(_) => {}
+        }
+    }
+}
+
+impl Widget for TwoTerminalOptionsWidget {
+    type Root = gtk::Box;
+
+    fn root(&self) -> gtk::Box {
+        self.widgets.root.clone()
+    }
+
+    fn view(relm: &Relm<Self>, model: Self::Model) -> Self {
+        let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+
+        hbox.add(&gtk::Label::new(Some("Sweep")));
+
+        let range_combo = gtk::ComboBoxText::new();
+        for range in &[
+            SweepRange::Positive,
+            SweepRange::Negative,
+            SweepRange::Bipolar,
+        ] {
+            let id = range.to_string();
+            range_combo.append(Some(&id), &id);
+        }
+        range_combo.set_active_id(Some(&model.config.sweep_range.to_string()));
+        hbox.add(&range_combo);
+
+        let leakage_label = gtk::Label::new(Some(""));
+        leakage_label.set_markup("I<sub>R</sub> at −");
+        hbox.add(&leakage_label);
+
+        let leakage_spinner = gtk::SpinButton::new_with_range(0.1, 5.0, 0.1);
+        leakage_spinner.set_digits(1);
+        leakage_spinner.set_numeric(true);
+        leakage_spinner.set_hexpand(true);
+        leakage_spinner.set_value(model.config.leakage_v);
+        leakage_spinner.set_sensitive(model.config.sweep_range.covers_negative());
+        hbox.add(&leakage_spinner);
+
+        hbox.add(&gtk::Label::new(Some("V")));
+
+        if let Some(test_current) = model.config.zener_test_current {
+            let test_current_label = gtk::Label::new(Some(""));
+            test_current_label.set_markup("I<sub>ZT</sub>");
+            hbox.add(&test_current_label);
+
+            let test_current_spinner = gtk::SpinButton::new_with_range(0.1, 40.0, 0.1);
+            test_current_spinner.set_digits(1);
+            test_current_spinner.set_numeric(true);
+            test_current_spinner.set_hexpand(true);
+            test_current_spinner.set_value(test_current * 1000.0);
+            hbox.add(&test_current_spinner);
+
+            hbox.add(&gtk::Label::new(Some("mA")));
+
+            connect!(
+                relm,
+                test_current_spinner,
+                connect_value_changed(btn),
+                TwoTerminalOptionsMsg::TestCurrent(btn.get_value())
+            );
+        }
+
+        connect!(
+            relm,
+            range_combo,
+            connect_changed(combo),
+            TwoTerminalOptionsMsg::SweepRange(combo.get_active_id().and_then(|id| id.parse().ok()))
+        );
+        connect!(
+            relm,
+            leakage_spinner,
+            connect_value_changed(btn),
+            TwoTerminalOptionsMsg::LeakageVoltage(btn.get_value())
+        );
+
+        hbox.show_all();
+
+        TwoTerminalOptionsWidget {
+            model,
+            widgets: Widgets {
+                root: hbox,
+                leakage_spinner,
+            },
+        }
+    }
+}
@@ -14,6 +14,7 @@ extern crate approx;
 
 pub mod backend;
 pub mod dut;
+pub mod error;
 pub mod gui;
 pub mod model;
 pub mod options;
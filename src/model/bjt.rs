@@ -0,0 +1,505 @@
+use std::fmt::Display;
+
+use itertools::Itertools;
+use nalgebra::*;
+
+use crate::backend::{AD2Config, RawTrace};
+use crate::model::curvefit::{linear_regression, FitQuality};
+use crate::model::pwc::PieceWiseConstantFunction;
+use crate::model::spice::{thermal_voltage, ModelCard, DEFAULT_TEMPERATURE};
+use crate::model::{BiasedModel, IVModel, Param};
+use crate::util::Engineering;
+
+/// Lowest collector-emitter voltage considered to be past saturation
+const ACTIVE_MIN_V: f64 = 0.5;
+const MIN_ACTIVE_SAMPLES: usize = 100;
+/// Datasheets usually quote hFE at this collector-emitter voltage
+const HFE_VCE: f64 = 1.0;
+/// Base-emitter voltages from germanium to silicon transistors, anything else means the base
+/// current doesn't fall short of the bias level the way `BjtModel::forward_active` needs
+const MIN_VBE: f64 = 0.15;
+const MAX_VBE: f64 = 1.2;
+
+/// Straight line fitted to the active region of one output characteristic. Voltages and
+/// currents are magnitudes, the signs of the trace are kept to draw it.
+#[derive(Clone, Debug)]
+pub struct OutputCurveModel {
+    pub ib: f64,
+    /// Base current reaching the transistor: `ib` less V_BE over the bias limiter when
+    /// `BjtModel::forward_active` tells V_BE, `ib` itself otherwise
+    pub base_current: f64,
+    /// Collector current the active region extrapolates to at zero collector-emitter voltage
+    pub ic0: f64,
+    /// Output conductance
+    pub go: f64,
+    /// Of the straight line, the slope `go` being parameter 0 and the intercept `ic0` 1
+    pub quality: FitQuality,
+    active_v: (f64, f64),
+    v_sign: f64,
+    i_sign: f64,
+}
+
+impl OutputCurveModel {
+    pub fn ic(&self, vce: f64) -> f64 {
+        self.ic0 + self.go * vce
+    }
+
+    /// Collector current at `HFE_VCE`
+    pub fn hfe_ic(&self) -> f64 {
+        self.ic(HFE_VCE)
+    }
+
+    pub fn hfe(&self) -> f64 {
+        self.hfe_ic() / self.base_current
+    }
+
+    pub fn early_v(&self) -> f64 {
+        self.ic0 / self.go
+    }
+}
+
+impl IVModel for OutputCurveModel {
+    fn min_v(&self) -> f64 {
+        (self.v_sign * self.active_v.0).min(self.v_sign * self.active_v.1)
+    }
+
+    fn max_v(&self) -> f64 {
+        (self.v_sign * self.active_v.0).max(self.v_sign * self.active_v.1)
+    }
+
+    fn evaluate(&self, v: f64) -> f64 {
+        self.i_sign * self.ic(v * self.v_sign)
+    }
+
+    fn params(&self) -> Vec<Param> {
+        let quality = Some(&self.quality);
+        vec![
+            Param::new("hfe", self.hfe(), "")
+                .at("ib", self.ib)
+                .at("ic", self.hfe_ic()),
+            Param::new("ic0", self.ic0, "A")
+                .fitted(quality, 1)
+                .at("ib", self.ib),
+            Param::new("go", self.go, "S")
+                .fitted(quality, 0)
+                .at("ib", self.ib),
+        ]
+    }
+
+    fn quality(&self) -> Option<&FitQuality> {
+        Some(&self.quality)
+    }
+}
+
+impl Display for OutputCurveModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "h<sub>FE</sub>@{:.3}A\t{:.0}",
+            Engineering(self.hfe_ic()),
+            self.hfe()
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SaturationModel {
+    pub ic: f64,
+    pub ib: f64,
+    pub vce: f64,
+}
+
+/// Forward-active parameters of the Gummel-Poon model
+#[derive(Clone, Copy, Debug)]
+pub struct ForwardActiveModel {
+    pub bf: f64,
+    pub is: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct BjtModel {
+    /// One per bias level the active region could be fitted for, by increasing base current
+    pub curves: Vec<OutputCurveModel>,
+    pub saturation: Option<SaturationModel>,
+    /// Of all the active regions together, whose spread over the bias levels is what the
+    /// model explains: a single active region is too flat for its R² to mean anything
+    pub quality: FitQuality,
+    /// The base current is driven through it, so falls short of the bias level by V_BE over it
+    pub bias_limiter_ohms: f64,
+}
+
+impl BjtModel {
+    /// Mean over the bias levels, whose individual estimates are rather noisy
+    pub fn early_v(&self) -> f64 {
+        self.curves
+            .iter()
+            .map(OutputCurveModel::early_v)
+            .sum::<f64>()
+            / self.curves.len() as f64
+    }
+
+    /// Forward gain and saturation current at `temperature`, °C, from the collector currents
+    /// the active regions extrapolate to at zero collector-emitter voltage, clear of the
+    /// Early effect. As the base current falls short of the bias level by V_BE over the bias
+    /// limiter, with V_BE = V_T⋅ln(I_C0/I_S), fitting I_C0 = BF⋅(I_B - V_BE/R) over the bias
+    /// levels tells I_S. `None` unless that makes for plausible base-emitter voltages, as
+    /// with a bias that is a current source rather than a limiter.
+    pub fn forward_active(&self, temperature: f64) -> Option<ForwardActiveModel> {
+        if self.curves.len() < 2 {
+            return None;
+        }
+        let vt = thermal_voltage(temperature);
+        let vt_over_r = vt / self.bias_limiter_ohms;
+
+        let n = self.curves.len();
+        let xs = MatrixMN::<f64, U2, Dynamic>::from_rows(&[
+            RowDVector::from_iterator(n, self.curves.iter().map(|c| c.ib - vt_over_r * c.ic0.ln())),
+            RowDVector::from_element(n, 1.0),
+        ]);
+        let ys = DVector::from_iterator(n, self.curves.iter().map(|c| c.ic0));
+        let betas = linear_regression(xs, ys)?;
+        let (bf, intercept) = (betas[(0, 0)], betas[(0, 1)]);
+        let is = (intercept / (bf * vt_over_r)).exp();
+
+        let plausible = bf.is_finite()
+            && bf > 0.0
+            && is.is_finite()
+            && is > 0.0
+            && self.curves.iter().all(|c| {
+                let vbe = vt * (c.ic0 / is).ln();
+                vbe > MIN_VBE && vbe < MAX_VBE
+            });
+        if plausible {
+            Some(ForwardActiveModel { bf, is })
+        } else {
+            None
+        }
+    }
+
+    /// Collector-emitter voltages are negative for PNP transistors
+    pub fn is_pnp(&self) -> bool {
+        self.curves[0].v_sign < 0.0
+    }
+}
+
+impl BiasedModel for BjtModel {
+    fn curves(&self) -> Vec<&dyn IVModel> {
+        self.curves.iter().map(|c| c as &dyn IVModel).collect()
+    }
+
+    fn params(&self) -> Vec<Param> {
+        let mut params = self
+            .curves
+            .iter()
+            .flat_map(|curve| curve.params())
+            .collect::<Vec<_>>();
+        params.push(Param::new("early_v", self.early_v(), "V"));
+        if let Some(saturation) = &self.saturation {
+            params.push(
+                Param::new("vce_sat", saturation.vce, "V")
+                    .at("ic", saturation.ic)
+                    .at("ib", saturation.ib),
+            );
+        }
+        params
+    }
+
+    fn quality(&self) -> Option<&FitQuality> {
+        Some(&self.quality)
+    }
+
+    /// BF and IS of `forward_active`, failing which BF is the highest hFE traced and IS
+    /// stays at the SPICE default
+    fn spice_card(&self, name: &str, temperature: f64) -> Option<ModelCard> {
+        let card = ModelCard::new(name, if self.is_pnp() { "PNP" } else { "NPN" });
+        let card = match self.forward_active(temperature) {
+            Some(forward) => card.param("BF", forward.bf).param("IS", forward.is),
+            None => {
+                let bf = self
+                    .curves
+                    .iter()
+                    .map(OutputCurveModel::hfe)
+                    .fold(0.0, f64::max);
+                card.param("BF", bf).comment(
+                    "IS not measured, the base current doesn't fall short of the bias level \
+                     by a plausible base-emitter voltage",
+                )
+            }
+        };
+        Some(card.param("VAF", self.early_v()).param("TNOM", temperature))
+    }
+}
+
+impl Display for BjtModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for curve in &self.curves {
+            write!(f, "{}", curve)?;
+        }
+        writeln!(f, "V<sub>A</sub>\t{:.3}V", Engineering(self.early_v()))?;
+        if let Some(saturation) = &self.saturation {
+            writeln!(
+                f,
+                "V<sub>CE(sat)</sub>@{:.3}A/{:.3}A\t{:.3}V",
+                Engineering(saturation.ic),
+                Engineering(saturation.ib),
+                Engineering(saturation.vce)
+            )?;
+        }
+        write!(f, "{}", self.quality)
+    }
+}
+
+/// Signs of the voltages and currents of a curve, so it can be handled in magnitudes
+fn signs(trace: &RawTrace) -> (f64, f64) {
+    let (v_sum, i_sum) = trace
+        .iter()
+        .fold((0.0, 0.0), |(v_sum, i_sum), (v, i)| (v_sum + v, i_sum + i));
+    (v_sum.signum(), i_sum.signum())
+}
+
+fn output_curve_model(ib: f64, trace: &RawTrace) -> Option<OutputCurveModel> {
+    let (v_sign, i_sign) = signs(trace);
+    let points = trace
+        .iter()
+        .filter(|(v, i)| v.is_finite() && i.is_finite())
+        .map(|(v, i)| (v * v_sign, i * i_sign))
+        .collect_vec();
+    let max_v = points.iter().map(|(v, _)| *v).fold(0.0, f64::max);
+    // The upper half of the sweep, to stay clear of quasi-saturation
+    let min_v = ACTIVE_MIN_V.max(max_v / 2.0);
+    let active = points
+        .into_iter()
+        .filter(|(v, _)| *v >= min_v)
+        .collect_vec();
+    if active.len() < MIN_ACTIVE_SAMPLES {
+        return None;
+    }
+
+    let xs = MatrixMN::<f64, U2, Dynamic>::from_rows(&[
+        RowDVector::from_iterator(active.len(), active.iter().map(|(v, _)| *v)),
+        RowDVector::from_element(active.len(), 1.0),
+    ]);
+    let ys = DVector::from_iterator(active.len(), active.iter().map(|(_, i)| *i));
+    let betas = linear_regression(xs.clone(), ys.clone())?;
+    let (go, ic0) = (betas[(0, 0)], betas[(0, 1)]);
+    if !go.is_finite() || !ic0.is_finite() || go <= 0.0 || ic0 <= 0.0 {
+        return None;
+    }
+
+    Some(OutputCurveModel {
+        ib: ib.abs(),
+        base_current: ib.abs(),
+        ic0,
+        go,
+        quality: FitQuality::linear(&xs, &ys, &betas),
+        active_v: (min_v, max_v),
+        v_sign,
+        i_sign,
+    })
+}
+
+fn active_quality(fitted: &[(OutputCurveModel, &RawTrace)]) -> FitQuality {
+    let (ys, values): (Vec<f64>, Vec<f64>) = fitted
+        .iter()
+        .flat_map(|(curve, trace)| {
+            trace
+                .iter()
+                .filter(|(v, i)| v.is_finite() && i.is_finite())
+                .map(move |(v, i)| (v * curve.v_sign, i * curve.i_sign))
+                .filter(move |(v, _)| *v >= curve.active_v.0)
+                .map(move |(v, i)| (i, curve.ic(v)))
+        })
+        .unzip();
+    FitQuality::from_values(&DVector::from_vec(ys), &DVector::from_vec(values), None)
+}
+
+/// Collector-emitter voltage at which the curve reaches `ic`, coming out of saturation, over
+/// the sweep of `config`
+fn saturation_vce(trace: &RawTrace, ic: f64, config: &AD2Config) -> Option<f64> {
+    let (v_sign, i_sign) = signs(trace);
+    let points = trace
+        .iter()
+        .map(|(v, i)| (v * v_sign, i * i_sign))
+        .collect_vec();
+    let max_v = config.sweep_max_v(v_sign).abs();
+    let curve = PieceWiseConstantFunction::from_points(0.0, max_v, 1000, 1, &points)
+        .iter()
+        .collect_vec();
+    let ix = curve.iter().position(|(_, i)| *i >= ic)?;
+    if ix == 0 {
+        return Some(curve[0].0);
+    }
+    let (v0, i0) = curve[ix - 1];
+    let (v1, i1) = curve[ix];
+    Some(v0 + (v1 - v0) * (ic - i0) / (i1 - i0))
+}
+
+/// Fits the output characteristics of a BJT, one trace per base current driven through the
+/// bias limiter of `config`. VCE(sat) is measured at `sat_current` on the most overdriven
+/// curve that reaches it. hFE is over the base current `forward_active` tells, taking the
+/// traces to be at `DEFAULT_TEMPERATURE`.
+pub fn bjt_model<'a, I>(traces: I, sat_current: f64, config: &AD2Config) -> Option<BjtModel>
+where
+    I: IntoIterator<Item = (f64, &'a RawTrace)>,
+{
+    let traces = traces
+        .into_iter()
+        .sorted_by(|(a, _), (b, _)| a.abs().partial_cmp(&b.abs()).unwrap())
+        .collect_vec();
+
+    let fitted = traces
+        .iter()
+        .filter_map(|(ib, trace)| Some((output_curve_model(*ib, trace)?, *trace)))
+        .collect_vec();
+    if fitted.is_empty() {
+        return None;
+    }
+    let quality = active_quality(&fitted);
+    let curves = fitted.into_iter().map(|(curve, _)| curve).collect_vec();
+
+    let saturation = traces.iter().rev().find_map(|(ib, trace)| {
+        saturation_vce(trace, sat_current, config).map(|vce| SaturationModel {
+            ic: sat_current,
+            ib: ib.abs(),
+            vce,
+        })
+    });
+
+    let mut model = BjtModel {
+        curves,
+        saturation,
+        quality,
+        bias_limiter_ohms: config.bias_limiter_ohms,
+    };
+    if let Some(forward) = model.forward_active(DEFAULT_TEMPERATURE) {
+        let vt = thermal_voltage(DEFAULT_TEMPERATURE);
+        let base_currents = model
+            .curves
+            .iter()
+            .map(|c| c.ib - vt * (c.hfe_ic() / forward.is).ln() / model.bias_limiter_ohms)
+            .collect_vec();
+        if base_currents.iter().all(|ib| *ib > 0.0) {
+            for (curve, base_current) in model.curves.iter_mut().zip(base_currents) {
+                curve.base_current = base_current;
+            }
+        }
+    }
+    Some(model)
+}
+
+#[cfg(test)]
+mod test {
+    use noisy_float::prelude::r64;
+
+    use crate::backend::{AD2Config, Backend, Sim, SimConfig, TraceControl};
+    use crate::dut::{BiasDrive, CurrentBiasedDevice, CurrentBiasedDeviceType, Device};
+    use crate::model::bjt::bjt_model;
+    use crate::model::BiasedModel;
+
+    fn model(device_type: CurrentBiasedDeviceType, path: &str) -> crate::model::bjt::BjtModel {
+        let device = CurrentBiasedDevice::from_type(device_type);
+        let trace = device.load_from_csv(path).unwrap();
+        bjt_model(
+            trace
+                .traces
+                .iter()
+                .map(|(bias, trace)| (bias.raw(), &trace.trace)),
+            0.001,
+            &AD2Config::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    pub fn bjt_model_2n3904() {
+        let model = model(CurrentBiasedDeviceType::NPN, "res/2N3904.csv.gz");
+
+        assert_eq!(model.curves.len(), 5);
+        let ics = model.curves.iter().map(|c| c.hfe_ic()).collect::<Vec<_>>();
+        assert!(ics.windows(2).all(|w| w[0] < w[1]));
+        // Over the base current reaching the transistor, 3µA rather than 10µA at the lowest level
+        assert_relative_eq!(model.curves[0].base_current, 3.04e-6, max_relative = 0.05);
+        for curve in &model.curves {
+            assert_relative_eq!(curve.hfe(), 200.0, max_relative = 0.02);
+        }
+        assert!(model.early_v() > 10.0 && model.early_v() < 100.0);
+
+        // A single active region is about flat, the bias levels are what the model explains
+        assert!(model.quality.r_squared > 0.999);
+        assert!(model.curves.iter().all(|c| c.quality.r_squared < 0.5));
+        for curve in &model.curves {
+            let std_errors = curve.quality.std_errors.as_ref().unwrap();
+            assert!(std_errors[0] < 0.05 * curve.go);
+            assert!(std_errors[1] < 0.01 * curve.ic0);
+        }
+
+        let saturation = model.saturation.unwrap();
+        assert_relative_eq!(saturation.ib, 0.000_05, max_relative = 1e-9);
+        assert!(saturation.vce > 0.03 && saturation.vce < 0.15);
+    }
+
+    #[test]
+    pub fn bjt_model_2n3906() {
+        let model = model(CurrentBiasedDeviceType::PNP, "res/2N3906.csv.gz");
+
+        assert_eq!(model.curves.len(), 5);
+        let ics = model.curves.iter().map(|c| c.hfe_ic()).collect::<Vec<_>>();
+        assert!(ics.windows(2).all(|w| w[0] < w[1]));
+        // Every curve rises by some 1.5mS whatever the bias, a leak of the fixture rather than
+        // the Early effect, which hFE at 1V takes for collector current: I_C0 tells the gain
+        assert!(model.curves.iter().all(|c| {
+            let gain = c.ic0 / c.base_current;
+            gain > 100.0 && gain < 300.0
+        }));
+
+        let saturation = model.saturation.unwrap();
+        assert_relative_eq!(saturation.ib, 0.000_05, max_relative = 1e-9);
+        assert!(saturation.vce > 0.03 && saturation.vce < 0.15);
+    }
+
+    #[test]
+    pub fn forward_active_2n3904() {
+        let model = model(CurrentBiasedDeviceType::NPN, "res/2N3904.csv.gz");
+
+        // The bias levels fall short by some 7µA, V_BE over the 100kΩ bias limiter
+        let forward = model.forward_active(27.0).unwrap();
+        assert_relative_eq!(forward.bf, 197.8, max_relative = 0.01);
+        assert_relative_eq!(forward.is, 1.238e-15, max_relative = 0.05);
+
+        let card = model.spice_card("2N3904", 27.0).unwrap();
+        assert_relative_eq!(card.get("IS").unwrap(), forward.is);
+        assert_relative_eq!(card.get("BF").unwrap(), forward.bf);
+    }
+
+    #[test]
+    pub fn forward_active_needs_the_bias_limiter() {
+        // The simulated bias is an ideal current source
+        let sim = Sim::new(
+            SimConfig {
+                samples: 2000,
+                ..SimConfig::default()
+            },
+            AD2Config::default(),
+        );
+        let traces = sim
+            .trace_3(
+                r64(1.0),
+                BiasDrive::Current,
+                (1..=5).map(|ix| r64(0.000_01 * f64::from(ix))).collect(),
+                &TraceControl::new(),
+            )
+            .unwrap();
+        let model = bjt_model(
+            traces.iter().map(|t| (t.bias.raw(), &t.trace)),
+            0.001,
+            &AD2Config::default(),
+        )
+        .unwrap();
+
+        assert!(model.forward_active(27.0).is_none());
+        let card = model.spice_card("SIM", 27.0).unwrap();
+        assert_eq!(card.get("IS"), None);
+        assert_relative_eq!(card.get("BF").unwrap(), 200.0, max_relative = 0.05);
+        assert!(card.to_string().starts_with("* IS not measured"));
+    }
+}
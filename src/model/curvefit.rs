@@ -4,6 +4,8 @@ use nalgebra::allocator::Allocator;
 use nalgebra::allocator::Reallocator;
 use nalgebra::*;
 
+use crate::util::Engineering;
+
 pub struct GaussNewtonParams<N: RealField> {
     pub min_iterations: usize,
     pub max_iterations: usize,
@@ -39,7 +41,16 @@ pub trait DiffFn<N: RealField, D: Dim + DimName> {
     where
         DefaultAllocator: Allocator<N, U1, D>;
 
-    fn sanitize_params(&mut self);
+    /// Lowest and highest values each parameter may take, unbounded by default
+    fn bounds(&self) -> (RowVectorN<N, D>, RowVectorN<N, D>)
+    where
+        DefaultAllocator: Allocator<N, U1, D>,
+    {
+        (
+            RowVectorN::from_element(-N::max_value()),
+            RowVectorN::from_element(N::max_value()),
+        )
+    }
 
     fn value(&self, x: N) -> N
     where
@@ -62,6 +73,17 @@ pub trait DiffFn<N: RealField, D: Dim + DimName> {
         DefaultAllocator: Allocator<N, U1, D> + Allocator<N, Dynamic, D>;
 }
 
+fn clamp_to_bounds<N: RealField, D: Dim + DimName, F: DiffFn<N, D>>(
+    model: &F,
+    params: &RowVectorN<N, D>,
+) -> RowVectorN<N, D>
+where
+    DefaultAllocator: Allocator<N, U1, D>,
+{
+    let (lower, upper) = model.bounds();
+    params.zip_zip_map(&lower, &upper, |p, lower, upper| p.max(lower).min(upper))
+}
+
 pub fn gauss_newton<N: RealField, D: Dim + DimName, F>(
     xs: &DVector<N>,
     ys: &DVector<N>,
@@ -146,8 +168,9 @@ pub fn gauss_newton<N: RealField, D: Dim + DimName, F>(
         {
             let params = model.params() + correction;
             model.mut_params().copy_from(&params);
-            trace!("new model before sanitizing params: {}", model);
-            model.sanitize_params();
+            trace!("new model before clamping params: {}", model);
+            let params = clamp_to_bounds(model, &params);
+            model.mut_params().copy_from(&params);
         }
 
         shift_cut = (shift_cut * params.shift_cut_speed_up).min(N::one());
@@ -169,6 +192,534 @@ pub fn gauss_newton<N: RealField, D: Dim + DimName, F>(
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct LevenbergMarquardtParams<N: RealField> {
+    pub max_iterations: usize,
+    pub initial_lambda: N,
+    pub lambda_up: N,
+    pub lambda_down: N,
+    /// Gives up on a step once the damping grows past this
+    pub max_lambda: N,
+    pub max_total_error: N,
+    pub max_error_improvement: N,
+    /// Stops once no parameter moves by more than this share of its magnitude
+    pub max_relative_step: N,
+}
+
+impl<N: RealField> Default for LevenbergMarquardtParams<N> {
+    fn default() -> Self {
+        Self {
+            max_iterations: 500,
+            initial_lambda: N::from_f64(0.001).unwrap(),
+            lambda_up: N::from_f64(10.0).unwrap(),
+            lambda_down: N::from_f64(0.1).unwrap(),
+            max_lambda: N::from_f64(1e16).unwrap(),
+            max_total_error: N::from_f64(0.000_000_000_000_1).unwrap(),
+            max_error_improvement: N::from_f64(0.000_000_001).unwrap(),
+            max_relative_step: N::from_f64(0.000_000_001).unwrap(),
+        }
+    }
+}
+
+/// Why a fit stopped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Convergence {
+    /// The residuals all but vanished
+    SmallResidual,
+    /// A step barely reduced the residuals
+    SmallImprovement,
+    /// A step barely moved the parameters
+    SmallStep,
+    /// No step reduces the residuals however much it's damped, including when the normal
+    /// equations can't be solved
+    Stalled,
+    MaxIterations,
+}
+
+#[derive(Clone, Debug)]
+pub struct FitResult<N: RealField> {
+    pub iterations: usize,
+    pub convergence: Convergence,
+    /// Euclidean norm of the residuals at the fitted parameters
+    pub residual_norm: N,
+    /// Residual variance times `(JᵀJ)⁻¹`, `None` if `JᵀJ` is singular
+    pub covariance: Option<DMatrix<N>>,
+}
+
+impl<N: RealField> FitResult<N> {
+    pub fn converged(&self) -> bool {
+        self.convergence != Convergence::MaxIterations && self.convergence != Convergence::Stalled
+    }
+}
+
+/// Two-sided 95% quantile of the normal distribution. The fits have far more samples than
+/// parameters, so it stands in for Student's t.
+const Z_95: f64 = 1.96;
+
+/// How closely a fitted model follows the data, and how well its parameters are determined
+#[derive(Serialize, Clone, Debug)]
+pub struct FitQuality {
+    pub r_squared: f64,
+    /// RMS of the residuals
+    pub rms: f64,
+    /// RMS of the residuals of the natural logarithms, over the samples where both the data
+    /// and the model are positive. NaN if there are none.
+    pub log_rms: f64,
+    /// Standard error of each parameter, `None` if the covariance is unknown
+    pub std_errors: Option<Vec<f64>>,
+}
+
+impl FitQuality {
+    pub fn new<D: Dim + DimName, F: DiffFn<f64, D>>(
+        xs: &DVector<f64>,
+        ys: &DVector<f64>,
+        model: &F,
+        result: &FitResult<f64>,
+    ) -> Self
+    where
+        DefaultAllocator: Allocator<f64, U1, D>,
+    {
+        let covariance = result.covariance.as_ref();
+        Self::from_values(ys, &model.values(xs), covariance)
+    }
+
+    /// Quality of a `linear_regression` fit of `y` to the rows of `x`
+    pub fn linear<D: DimName>(
+        x: &MatrixMN<f64, D, Dynamic>,
+        y: &DVector<f64>,
+        betas: &RowVectorN<f64, D>,
+    ) -> Self
+    where
+        DefaultAllocator: Allocator<f64, D, Dynamic> + Allocator<f64, U1, D>,
+    {
+        let design = DMatrix::from_fn(x.ncols(), x.nrows(), |r, c| x[(c, r)]);
+        let values = &design * DVector::from_iterator(betas.len(), betas.iter().cloned());
+        let error = (y - &values).norm_squared();
+        let degrees_of_freedom = y.nrows().saturating_sub(design.ncols()).max(1);
+        let covariance = (design.transpose() * &design)
+            .try_inverse()
+            .map(|inverse| inverse * (error / degrees_of_freedom as f64));
+        Self::from_values(y, &values, covariance.as_ref())
+    }
+
+    /// Quality of `values` modelling `ys`, for fits without a parameter covariance
+    pub fn from_values(
+        ys: &DVector<f64>,
+        values: &DVector<f64>,
+        covariance: Option<&DMatrix<f64>>,
+    ) -> Self {
+        let residuals = ys - values;
+        let n = ys.nrows().max(1) as f64;
+        let mean = ys.mean();
+        let total = ys.iter().map(|y| (y - mean).powi(2)).sum::<f64>();
+
+        let log_residuals = ys
+            .iter()
+            .zip(values.iter())
+            .filter(|(y, value)| **y > 0.0 && **value > 0.0)
+            .map(|(y, value)| (y.ln() - value.ln()).powi(2))
+            .collect::<Vec<_>>();
+
+        FitQuality {
+            r_squared: 1.0 - residuals.norm_squared() / total,
+            rms: (residuals.norm_squared() / n).sqrt(),
+            log_rms: (log_residuals.iter().sum::<f64>() / log_residuals.len() as f64).sqrt(),
+            std_errors: covariance.map(|covariance| {
+                covariance
+                    .diagonal()
+                    .iter()
+                    .map(|variance| variance.max(0.0).sqrt())
+                    .collect()
+            }),
+        }
+    }
+
+    /// Half-width of the 95% confidence interval of parameter `ix`
+    pub fn confidence_95(&self, ix: usize) -> Option<f64> {
+        self.std_errors
+            .as_ref()
+            .and_then(|std_errors| std_errors.get(ix))
+            .map(|std_error| std_error * Z_95)
+    }
+}
+
+impl Display for FitQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "R²\t{:.5}", self.r_squared)?;
+        writeln!(f, "RMS\t{:.3}A", Engineering(self.rms))?;
+        if self.log_rms.is_finite() {
+            writeln!(f, "RMS<sub>ln I</sub>\t{:.3}", self.log_rms)?;
+        }
+        Ok(())
+    }
+}
+
+fn residuals<N: RealField, D: Dim + DimName, F: DiffFn<N, D>>(
+    xs: &DVector<N>,
+    ys: &DVector<N>,
+    model: &F,
+) -> DVector<N>
+where
+    DefaultAllocator: Allocator<N, U1, D>,
+{
+    ys - model.values(xs)
+}
+
+fn dynamic_jacobian<N: RealField, D: Dim + DimName, F: DiffFn<N, D>>(
+    xs: &DVector<N>,
+    model: &F,
+) -> DMatrix<N>
+where
+    DefaultAllocator: Allocator<N, U1, D> + Allocator<N, Dynamic, D>,
+{
+    let jacobian = model.jacobian(xs);
+    DMatrix::from_iterator(jacobian.nrows(), jacobian.ncols(), jacobian.iter().cloned())
+}
+
+/// Minimizes the squared residuals of `model` over `xs` and `ys` starting from its current
+/// parameters, keeping them within `DiffFn::bounds`
+pub fn levenberg_marquardt<N: RealField, D: Dim + DimName, F>(
+    xs: &DVector<N>,
+    ys: &DVector<N>,
+    model: &mut F,
+    params: LevenbergMarquardtParams<N>,
+) -> FitResult<N>
+where
+    F: DiffFn<N, D> + Display,
+    DefaultAllocator: Allocator<N, U1, D> + Allocator<N, Dynamic, D>,
+{
+    let weights = xs.map(|_| N::one());
+    weighted_levenberg_marquardt(xs, ys, &weights, model, &params)
+}
+
+/// `levenberg_marquardt` minimizing the squared residuals times `weights`
+pub fn weighted_levenberg_marquardt<N: RealField, D: Dim + DimName, F>(
+    xs: &DVector<N>,
+    ys: &DVector<N>,
+    weights: &DVector<N>,
+    model: &mut F,
+    params: &LevenbergMarquardtParams<N>,
+) -> FitResult<N>
+where
+    F: DiffFn<N, D> + Display,
+    DefaultAllocator: Allocator<N, U1, D> + Allocator<N, Dynamic, D>,
+{
+    debug_time!("levenberg_marquardt");
+    let sqrt_weights = weights.map(|w| w.sqrt());
+    let residuals = |model: &F| residuals(xs, ys, model).component_mul(&sqrt_weights);
+    let jacobian = |model: &F| {
+        let mut jacobian = dynamic_jacobian(xs, model);
+        for row in 0..jacobian.nrows() {
+            for col in 0..jacobian.ncols() {
+                jacobian[(row, col)] *= sqrt_weights[row];
+            }
+        }
+        jacobian
+    };
+    let mut lambda = params.initial_lambda;
+    let mut error = residuals(model).norm_squared();
+    let mut convergence = Convergence::MaxIterations;
+    let mut iterations = 0;
+
+    while iterations < params.max_iterations {
+        iterations += 1;
+        trace!(
+            "model: {}, total error: {}, λ: {}",
+            model,
+            error.sqrt(),
+            lambda
+        );
+        if error.sqrt() < params.max_total_error {
+            convergence = Convergence::SmallResidual;
+            break;
+        }
+
+        let jacobian = jacobian(model);
+        let jt = jacobian.transpose();
+        let jtj = &jt * &jacobian;
+        let gradient = &jt * residuals(model);
+        let current = model.params().clone_owned();
+
+        let step_taken = loop {
+            if lambda > params.max_lambda {
+                break None;
+            }
+            let mut damped = jtj.clone();
+            for k in 0..damped.nrows() {
+                damped[(k, k)] += lambda * jtj[(k, k)].max(N::default_epsilon());
+            }
+            let step = match damped.cholesky() {
+                Some(cholesky) => cholesky.solve(&gradient),
+                None => {
+                    lambda *= params.lambda_up;
+                    continue;
+                }
+            };
+
+            let candidate = clamp_to_bounds(
+                model,
+                &(&current + RowVectorN::<N, D>::from_iterator(step.iter().cloned())),
+            );
+            model.mut_params().copy_from(&candidate);
+            let candidate_error = residuals(model).norm_squared();
+            if candidate_error.is_finite() && candidate_error < error {
+                lambda *= params.lambda_down;
+                break Some((candidate - &current, candidate_error));
+            }
+            model.mut_params().copy_from(&current);
+            lambda *= params.lambda_up;
+        };
+
+        let (step, new_error) = match step_taken {
+            Some(step) => step,
+            None => {
+                convergence = Convergence::Stalled;
+                break;
+            }
+        };
+        let improvement = (error - new_error) / error;
+        error = new_error;
+        if improvement < params.max_error_improvement {
+            convergence = Convergence::SmallImprovement;
+            break;
+        }
+        // Parameter by parameter, as their magnitudes are worlds apart: Is next to n⋅Vt
+        // would otherwise never count as moving
+        let tolerance = params.max_relative_step;
+        if step
+            .iter()
+            .zip(current.iter())
+            .all(|(dp, p)| dp.abs() <= tolerance * (p.abs() + tolerance))
+        {
+            convergence = Convergence::SmallStep;
+            break;
+        }
+    }
+
+    let jacobian = jacobian(model);
+    let weighted_points = weights.iter().filter(|w| **w > N::zero()).count();
+    let degrees_of_freedom = weighted_points.saturating_sub(jacobian.ncols()).max(1);
+    let variance = error / N::from_usize(degrees_of_freedom).unwrap();
+    let covariance = (jacobian.transpose() * &jacobian)
+        .try_inverse()
+        .map(|inverse| inverse * variance);
+
+    debug!(
+        "levenberg_marquardt: {:?} after {} iterations, total error {}",
+        convergence,
+        iterations,
+        error.sqrt()
+    );
+    FitResult {
+        iterations,
+        convergence,
+        residual_norm: error.sqrt(),
+        covariance,
+    }
+}
+
+/// How much a residual counts towards the fit, relative to the robust scale of the residuals
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Loss<N: RealField> {
+    /// Plain least squares, every point counts fully however far off it is
+    Squared,
+    /// Quadratic up to the given multiple of the scale, linear beyond it
+    Huber(N),
+    /// Points beyond the given multiple of the scale are ignored altogether
+    Tukey(N),
+}
+
+impl<N: RealField> Loss<N> {
+    /// Huber loss tuned to 95% efficiency on normally distributed residuals
+    pub fn huber() -> Self {
+        Loss::Huber(N::from_f64(1.345).unwrap())
+    }
+
+    /// Tukey's biweight tuned to 95% efficiency on normally distributed residuals
+    pub fn tukey() -> Self {
+        Loss::Tukey(N::from_f64(4.685).unwrap())
+    }
+
+    /// IRLS weight of a residual `u` times the scale
+    fn weight(&self, u: N) -> N {
+        let u = u.abs();
+        match *self {
+            Loss::Squared => N::one(),
+            Loss::Huber(k) => {
+                if u <= k {
+                    N::one()
+                } else {
+                    k / u
+                }
+            }
+            Loss::Tukey(c) => {
+                if u < c {
+                    let t = N::one() - (u / c) * (u / c);
+                    t * t
+                } else {
+                    N::zero()
+                }
+            }
+        }
+    }
+}
+
+/// Weights of the points before any reweighting
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Weighting<N: RealField> {
+    Uniform,
+    /// Minimizes the relative rather than the absolute errors, which keeps the steep end of
+    /// an exponential curve from drowning out the rest. Values below `floor` are weighted as
+    /// if they were `floor`.
+    Relative {
+        floor: N,
+    },
+}
+
+impl<N: RealField> Weighting<N> {
+    fn weights(&self, ys: &DVector<N>) -> DVector<N> {
+        match *self {
+            Weighting::Uniform => DVector::from_element(ys.nrows(), N::one()),
+            Weighting::Relative { floor } => ys.map(|y| {
+                let y = y.abs().max(floor);
+                N::one() / (y * y)
+            }),
+        }
+    }
+}
+
+/// How a model is fitted, least squares by default
+#[derive(Clone, Debug)]
+pub struct FitMethod<N: RealField> {
+    pub loss: Loss<N>,
+    pub weighting: Weighting<N>,
+    /// Most times the points are reweighted after the first fit
+    pub max_reweightings: usize,
+    /// Stops reweighting once the parameters move by less than this relative step
+    pub reweighting_tolerance: N,
+    pub levenberg_marquardt: LevenbergMarquardtParams<N>,
+}
+
+impl<N: RealField> Default for FitMethod<N> {
+    fn default() -> Self {
+        FitMethod {
+            loss: Loss::Squared,
+            weighting: Weighting::Uniform,
+            max_reweightings: 20,
+            reweighting_tolerance: N::from_f64(0.000_001).unwrap(),
+            levenberg_marquardt: LevenbergMarquardtParams::default(),
+        }
+    }
+}
+
+impl<N: RealField> FitMethod<N> {
+    /// Huber loss, which shrugs off glitches without giving up on the bulk of the points
+    pub fn robust() -> Self {
+        FitMethod {
+            loss: Loss::huber(),
+            ..FitMethod::default()
+        }
+    }
+
+    /// Whether points get reweighted by their residuals
+    pub fn is_robust(&self) -> bool {
+        self.loss != Loss::Squared
+    }
+
+    /// Typical size of `residuals` of a model with `params` parameters, as far as this method
+    /// is concerned: their RMS for least squares, their robust scale otherwise
+    pub fn residual_scale(&self, residuals: impl Iterator<Item = N>, params: usize) -> N {
+        match self.loss {
+            Loss::Squared => {
+                let (sum, n) =
+                    residuals.fold((N::zero(), 0usize), |(sum, n), r| (sum + r * r, n + 1));
+                (sum / N::from_usize(n.saturating_sub(params).max(1)).unwrap()).sqrt()
+            }
+            _ => robust_scale(residuals),
+        }
+    }
+}
+
+/// Median of the absolute deviations from the median, scaled to estimate the standard
+/// deviation of normally distributed values. Non-finite values, such as the residuals of a
+/// model blowing up, are left out.
+fn robust_scale<N: RealField>(values: impl Iterator<Item = N>) -> N {
+    let median = |values: &mut Vec<N>| {
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = values.len();
+        if n % 2 == 1 {
+            values[n / 2]
+        } else {
+            (values[n / 2 - 1] + values[n / 2]) / N::from_f64(2.0).unwrap()
+        }
+    };
+    let mut values = values.filter(|v| v.is_finite()).collect::<Vec<_>>();
+    if values.is_empty() {
+        return N::zero();
+    }
+    let center = median(&mut values);
+    let mut deviations = values.iter().map(|v| (*v - center).abs()).collect();
+    median(&mut deviations) * N::from_f64(1.4826).unwrap()
+}
+
+/// Fits `model` as `method` says, by iteratively reweighted least squares unless the loss
+/// is squared. Points with a non-finite coordinate, such as lost samples, are left out.
+pub fn fit<N: RealField, D: Dim + DimName, F>(
+    xs: &DVector<N>,
+    ys: &DVector<N>,
+    model: &mut F,
+    method: &FitMethod<N>,
+) -> FitResult<N>
+where
+    F: DiffFn<N, D> + Display,
+    DefaultAllocator: Allocator<N, U1, D> + Allocator<N, Dynamic, D>,
+{
+    let finite = (0..xs.nrows())
+        .filter(|ix| xs[*ix].is_finite() && ys[*ix].is_finite())
+        .collect::<Vec<_>>();
+    if finite.len() < xs.nrows() {
+        debug!(
+            "fit: leaving out {} non-finite points",
+            xs.nrows() - finite.len()
+        );
+    }
+    let (xs, ys) = (xs.select_rows(&finite), ys.select_rows(&finite));
+
+    let prior = method.weighting.weights(&ys);
+    let mut result =
+        weighted_levenberg_marquardt(&xs, &ys, &prior, model, &method.levenberg_marquardt);
+    if !method.is_robust() {
+        return result;
+    }
+
+    let sqrt_prior = prior.map(|w| w.sqrt());
+    for reweighting in 0..method.max_reweightings {
+        let scaled_residuals = residuals(&xs, &ys, model).component_mul(&sqrt_prior);
+        let scale = robust_scale(scaled_residuals.iter().cloned());
+        if !(scale.is_finite() && scale > N::zero()) {
+            break;
+        }
+        let weights = prior.zip_map(&scaled_residuals, |w, r| w * method.loss.weight(r / scale));
+
+        let before = model.params().clone_owned();
+        let iterations = result.iterations;
+        result =
+            weighted_levenberg_marquardt(&xs, &ys, &weights, model, &method.levenberg_marquardt);
+        result.iterations += iterations;
+        let step = model.params() - &before;
+        trace!("fit: reweighting {}, step {}", reweighting, step.norm());
+        let tolerance = method.reweighting_tolerance;
+        if step
+            .iter()
+            .zip(before.iter())
+            .all(|(dp, p)| dp.abs() <= tolerance * (p.abs() + tolerance))
+        {
+            break;
+        }
+    }
+    result
+}
+
 pub fn linear_regression<N: RealField, D: DimName>(
     x: MatrixMN<N, D, Dynamic>,
     y: MatrixMN<N, Dynamic, U1>,
@@ -1,11 +1,103 @@
-use std::fmt::Display;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 
+use crate::model::curvefit::FitQuality;
+use crate::model::spice::ModelCard;
+use crate::util::Engineering;
+
+pub mod bjt;
 pub mod curvefit;
 pub mod diode;
+pub mod mosfet;
 pub mod pwc;
+pub mod spice;
+pub mod zener;
+
+/// A model parameter in a form fit for machine consumption, unlike the `Display` markup
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+    /// Half-width of the 95% confidence interval, if known
+    pub uncertainty: Option<f64>,
+    /// Quantities the parameter is measured at, such as the collector current of hFE
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub at: BTreeMap<String, f64>,
+}
+
+impl Param {
+    pub fn new(name: &str, value: f64, unit: &str) -> Self {
+        Param {
+            name: name.to_string(),
+            value,
+            unit: unit.to_string(),
+            uncertainty: None,
+            at: BTreeMap::new(),
+        }
+    }
+
+    /// Takes the uncertainty of fitted parameter `ix` from `quality`
+    pub fn fitted(self, quality: Option<&FitQuality>, ix: usize) -> Self {
+        Param {
+            uncertainty: quality.and_then(|quality| quality.confidence_95(ix)),
+            ..self
+        }
+    }
+
+    pub fn at(mut self, name: &str, value: f64) -> Self {
+        self.at.insert(name.to_string(), value);
+        self
+    }
+}
 
 pub trait IVModel: Display + Send + Sync {
     fn min_v(&self) -> f64;
     fn max_v(&self) -> f64;
     fn evaluate(&self, v: f64) -> f64;
+
+    /// The parameters reported by `Display`
+    fn params(&self) -> Vec<Param>;
+
+    /// Set for models fitted with `curvefit::levenberg_marquardt`
+    fn quality(&self) -> Option<&FitQuality> {
+        None
+    }
+
+    /// SPICE model of a device measured at `temperature`, °C, if there's one to export
+    fn spice_card(&self, _name: &str, _temperature: f64) -> Option<ModelCard> {
+        None
+    }
+}
+
+/// Model of a three-terminal device, drawn as one curve per bias level
+pub trait BiasedModel: Display + Send + Sync {
+    fn curves(&self) -> Vec<&dyn IVModel>;
+
+    /// The parameters reported by `Display`
+    fn params(&self) -> Vec<Param>;
+
+    fn quality(&self) -> Option<&FitQuality> {
+        None
+    }
+
+    fn spice_card(&self, _name: &str, _temperature: f64) -> Option<ModelCard> {
+        None
+    }
+}
+
+/// Writes a report row for a fitted parameter, with its 95% confidence interval if known
+fn write_param(
+    f: &mut Formatter<'_>,
+    name: &str,
+    value: f64,
+    unit: &str,
+    quality: Option<&FitQuality>,
+    ix: usize,
+) -> std::fmt::Result {
+    write!(f, "{}\t{:.3}{}", name, Engineering(value), unit)?;
+    if let Some(half_width) = quality.and_then(|quality| quality.confidence_95(ix)) {
+        write!(f, " ± {:.3}{}", Engineering(half_width), unit)?;
+    }
+    writeln!(f)
 }
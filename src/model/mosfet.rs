@@ -0,0 +1,457 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use nalgebra::*;
+
+use crate::backend::RawTrace;
+use crate::model::curvefit::{self, linear_regression, DiffFn, FitMethod, FitQuality};
+use crate::model::pwc::PieceWiseConstantFunction;
+use crate::model::spice::ModelCard;
+use crate::model::{write_param, BiasedModel, IVModel, Param};
+use crate::util::Engineering;
+
+const BUCKETS_PER_CURVE: usize = 200;
+/// Curves carrying less than this share of the largest current are taken as cut off when
+/// guessing the initial parameters
+const CONDUCTION_THRESHOLD: f64 = 0.01;
+
+/// Square-law MOSFET with channel-length modulation, in n-channel terms: a p-channel device
+/// has its voltages and currents negated, a depletion-mode one a negative threshold
+#[derive(Clone, Debug)]
+pub struct SquareLawModel {
+    p: RowVectorN<f64, U3>,
+}
+
+impl SquareLawModel {
+    pub fn new(vth: f64, kp: f64, lambda: f64) -> Self {
+        SquareLawModel {
+            p: RowVectorN::<f64, U3>::new(vth, kp, lambda),
+        }
+    }
+
+    pub fn vth(&self) -> f64 {
+        self.p[0]
+    }
+
+    pub fn kp(&self) -> f64 {
+        self.p[1]
+    }
+
+    pub fn lambda(&self) -> f64 {
+        self.p[2]
+    }
+
+    pub fn drain_current(&self, vgs: f64, vds: f64) -> f64 {
+        let (vth, kp, lambda) = (self.p[0], self.p[1], self.p[2]);
+        let v_ov = vgs - vth;
+        if v_ov <= 0.0 {
+            0.0
+        } else if vds < v_ov {
+            kp * (v_ov * vds - vds * vds / 2.0) * (1.0 + lambda * vds)
+        } else {
+            kp / 2.0 * v_ov * v_ov * (1.0 + lambda * vds)
+        }
+    }
+
+    fn drain_current_grad(&self, vgs: f64, vds: f64) -> RowVectorN<f64, U3> {
+        let (vth, kp, lambda) = (self.p[0], self.p[1], self.p[2]);
+        let v_ov = vgs - vth;
+        if v_ov <= 0.0 {
+            RowVectorN::<f64, U3>::zeros()
+        } else if vds < v_ov {
+            let triode = v_ov * vds - vds * vds / 2.0;
+            RowVectorN::<f64, U3>::new(
+                -kp * vds * (1.0 + lambda * vds),
+                triode * (1.0 + lambda * vds),
+                kp * triode * vds,
+            )
+        } else {
+            RowVectorN::<f64, U3>::new(
+                -kp * v_ov * (1.0 + lambda * vds),
+                v_ov * v_ov / 2.0 * (1.0 + lambda * vds),
+                kp / 2.0 * v_ov * v_ov * vds,
+            )
+        }
+    }
+
+    /// Drain-source resistance deep in the triode region
+    pub fn rds_on(&self, vgs: f64) -> Option<f64> {
+        let v_ov = vgs - self.vth();
+        if v_ov > 0.0 {
+            Some(1.0 / (self.kp() * v_ov))
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for SquareLawModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Vth = {}, Kp = {}, λ = {}",
+            self.vth(),
+            self.kp(),
+            self.lambda()
+        )
+    }
+}
+
+/// The model over the operating points of all the bias curves, `x` being the index of
+/// the point, so that `levenberg_marquardt` can fit it to all of them at once
+#[derive(Clone, Debug)]
+struct JointFit {
+    model: SquareLawModel,
+    points: Arc<Vec<(f64, f64)>>,
+}
+
+impl Display for JointFit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.model.fmt(f)
+    }
+}
+
+impl DiffFn<f64, U3> for JointFit {
+    fn params(&self) -> &RowVectorN<f64, U3> {
+        &self.model.p
+    }
+
+    fn mut_params(&mut self) -> &mut RowVectorN<f64, U3> {
+        &mut self.model.p
+    }
+
+    fn bounds(&self) -> (RowVectorN<f64, U3>, RowVectorN<f64, U3>) {
+        (
+            RowVectorN::<f64, U3>::new(f64::MIN, 0.000_000_001, 0.0),
+            RowVectorN::<f64, U3>::from_element(f64::MAX),
+        )
+    }
+
+    #[inline]
+    fn value(&self, x: f64) -> f64 {
+        let (vgs, vds) = self.points[x as usize];
+        self.model.drain_current(vgs, vds)
+    }
+
+    #[inline]
+    fn values(&self, xs: &DVector<f64>) -> DVector<f64> {
+        DVector::from_iterator(xs.nrows(), xs.iter().map(|x| self.value(*x)))
+    }
+
+    #[inline]
+    fn grad(&self, x: f64) -> RowVectorN<f64, U3> {
+        let (vgs, vds) = self.points[x as usize];
+        self.model.drain_current_grad(vgs, vds)
+    }
+
+    /// Derivative by the drain-source voltage
+    #[inline]
+    fn deriv(&self, x: f64) -> f64 {
+        let (vgs, vds) = self.points[x as usize];
+        let delta = 0.000_001;
+        (self.model.drain_current(vgs, vds + delta) - self.model.drain_current(vgs, vds)) / delta
+    }
+
+    #[inline]
+    fn jacobian(&self, xs: &DVector<f64>) -> MatrixMN<f64, Dynamic, U3> {
+        MatrixMN::from_rows(xs.iter().map(|x| self.grad(*x)).collect_vec().as_slice())
+    }
+}
+
+/// One bias curve of the fitted model, with the signs of the trace it was fitted to
+#[derive(Clone, Debug)]
+pub struct MosfetCurve {
+    model: SquareLawModel,
+    vgs: f64,
+    max_v: f64,
+    v_sign: f64,
+    i_sign: f64,
+}
+
+impl IVModel for MosfetCurve {
+    fn min_v(&self) -> f64 {
+        self.max_v.min(0.0)
+    }
+
+    fn max_v(&self) -> f64 {
+        self.max_v.max(0.0)
+    }
+
+    fn evaluate(&self, v: f64) -> f64 {
+        self.i_sign * self.model.drain_current(self.vgs, v * self.v_sign)
+    }
+
+    /// The parameters are shared by all the curves, `MosfetModel` reports them
+    fn params(&self) -> Vec<Param> {
+        Vec::new()
+    }
+}
+
+impl Display for MosfetCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.model.fmt(f)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MosfetModel {
+    pub square_law: SquareLawModel,
+    pub curves: Vec<MosfetCurve>,
+    /// Sign of the drain-source voltage, negative for p-channel devices
+    pub polarity: f64,
+    pub quality: Option<FitQuality>,
+}
+
+impl MosfetModel {
+    /// Threshold voltage with the sign the device is biased with
+    pub fn vth(&self) -> f64 {
+        self.square_law.vth() * self.polarity
+    }
+
+    /// The highest gate-source voltage traced and the resistance at it
+    pub fn rds_on(&self) -> Option<(f64, f64)> {
+        let vgs = self
+            .curves
+            .iter()
+            .map(|c| c.vgs)
+            .fold(f64::NEG_INFINITY, f64::max);
+        self.square_law
+            .rds_on(vgs)
+            .map(|rds_on| (vgs * self.polarity, rds_on))
+    }
+}
+
+impl BiasedModel for MosfetModel {
+    fn curves(&self) -> Vec<&dyn IVModel> {
+        self.curves.iter().map(|c| c as &dyn IVModel).collect()
+    }
+
+    fn params(&self) -> Vec<Param> {
+        let quality = self.quality.as_ref();
+        let mut params = vec![
+            Param::new("vth", self.vth(), "V").fitted(quality, 0),
+            Param::new("kp", self.square_law.kp(), "A/V²").fitted(quality, 1),
+            Param::new("lambda", self.square_law.lambda(), "V⁻¹").fitted(quality, 2),
+        ];
+        if let Some((vgs, rds_on)) = self.rds_on() {
+            params.push(Param::new("rds_on", rds_on, "Ω").at("vgs", vgs));
+        }
+        params
+    }
+
+    fn quality(&self) -> Option<&FitQuality> {
+        self.quality.as_ref()
+    }
+
+    /// Level 1, whose KP matches ours for W = L
+    fn spice_card(&self, name: &str, temperature: f64) -> Option<ModelCard> {
+        Some(
+            ModelCard::new(name, if self.polarity < 0.0 { "PMOS" } else { "NMOS" })
+                .param("LEVEL", 1.0)
+                .param("VTO", self.vth())
+                .param("KP", self.square_law.kp())
+                .param("LAMBDA", self.square_law.lambda())
+                .param("TNOM", temperature)
+                .comment("Instantiate with W = L"),
+        )
+    }
+}
+
+impl Display for MosfetModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let quality = self.quality.as_ref();
+        write_param(f, "V<sub>th</sub>", self.vth(), "V", quality, 0)?;
+        write_param(f, "K<sub>p</sub>", self.square_law.kp(), "A/V²", quality, 1)?;
+        write_param(f, "λ", self.square_law.lambda(), "V⁻¹", quality, 2)?;
+        if let Some((vgs, rds_on)) = self.rds_on() {
+            writeln!(
+                f,
+                "R<sub>DS(on)</sub>@{:.3}V\t{:.3}Ω",
+                Engineering(vgs),
+                Engineering(rds_on)
+            )?;
+        }
+        if let Some(quality) = quality {
+            write!(f, "{}", quality)?;
+        }
+        Ok(())
+    }
+}
+
+/// Threshold and Kp from the square root of the highest current of each curve growing
+/// linearly with the gate-source voltage, as it would in saturation
+fn initial_guess(curves: &[(f64, Vec<(f64, f64)>)]) -> Option<SquareLawModel> {
+    let max_currents = curves
+        .iter()
+        .map(|(vgs, curve)| {
+            let max_i = curve.iter().map(|(_, i)| *i).fold(0.0, f64::max);
+            (*vgs, max_i)
+        })
+        .collect_vec();
+    let largest = max_currents.iter().map(|(_, i)| *i).fold(0.0, f64::max);
+    let conducting = max_currents
+        .into_iter()
+        .filter(|(_, i)| *i > largest * CONDUCTION_THRESHOLD)
+        .collect_vec();
+    if conducting.len() < 2 {
+        return None;
+    }
+
+    let xs = MatrixMN::<f64, U2, Dynamic>::from_rows(&[
+        RowDVector::from_iterator(conducting.len(), conducting.iter().map(|(vgs, _)| *vgs)),
+        RowDVector::from_element(conducting.len(), 1.0),
+    ]);
+    let ys = DVector::from_iterator(conducting.len(), conducting.iter().map(|(_, i)| i.sqrt()));
+    let betas = linear_regression(xs, ys)?;
+    let (slope, intercept) = (betas[(0, 0)], betas[(0, 1)]);
+    if !(slope.is_finite() && intercept.is_finite()) || slope <= 0.0 {
+        return None;
+    }
+
+    Some(SquareLawModel::new(
+        -intercept / slope,
+        2.0 * slope * slope,
+        0.0,
+    ))
+}
+
+/// Fits a single square-law model to all the curves of a voltage-biased device, one trace
+/// per gate-source voltage, robustly
+pub fn mosfet_model<'a, I>(traces: I) -> Option<MosfetModel>
+where
+    I: IntoIterator<Item = (f64, &'a RawTrace)>,
+{
+    mosfet_model_with(traces, &FitMethod::robust())
+}
+
+pub fn mosfet_model_with<'a, I>(traces: I, method: &FitMethod<f64>) -> Option<MosfetModel>
+where
+    I: IntoIterator<Item = (f64, &'a RawTrace)>,
+{
+    let traces = traces.into_iter().collect_vec();
+    let (v_sum, i_sum) = traces
+        .iter()
+        .flat_map(|(_, trace)| trace.iter())
+        .fold((0.0, 0.0), |(v_sum, i_sum), (v, i)| (v_sum + v, i_sum + i));
+    let (v_sign, i_sign) = (v_sum.signum(), i_sum.signum());
+
+    let curves = traces
+        .iter()
+        .map(|(bias, trace)| {
+            let points = trace
+                .iter()
+                .filter(|(v, i)| v.is_finite() && i.is_finite())
+                .map(|(v, i)| (v * v_sign, i * i_sign))
+                .collect_vec();
+            let max_v = points.iter().map(|(v, _)| *v).fold(0.0, f64::max);
+            // Averaging the voltages too, as the bucket centres would skew the slopes
+            let buckets = |points: &[(f64, f64)]| {
+                PieceWiseConstantFunction::from_points(0.0, max_v, BUCKETS_PER_CURVE, 1, points)
+            };
+            let vs = points.iter().map(|(v, _)| (*v, *v)).collect_vec();
+            let curve = buckets(&vs)
+                .iter()
+                .zip(buckets(&points).iter())
+                .map(|((_, v), (_, i))| (v, i))
+                .collect_vec();
+            (bias * v_sign, curve)
+        })
+        .filter(|(_, curve)| !curve.is_empty())
+        .collect_vec();
+
+    let mut fit = JointFit {
+        model: initial_guess(&curves)?,
+        points: Arc::new(
+            curves
+                .iter()
+                .flat_map(|(vgs, curve)| curve.iter().map(move |(vds, _)| (*vgs, *vds)))
+                .collect(),
+        ),
+    };
+    let xs = DVector::from_iterator(fit.points.len(), (0..fit.points.len()).map(|ix| ix as f64));
+    let ys = DVector::from_iterator(
+        fit.points.len(),
+        curves
+            .iter()
+            .flat_map(|(_, curve)| curve.iter().map(|(_, i)| *i)),
+    );
+    let result = curvefit::fit(&xs, &ys, &mut fit, method);
+    if !result.converged() {
+        debug!("MOSFET fit stopped on {:?}", result.convergence);
+        return None;
+    }
+
+    let quality = FitQuality::new(&xs, &ys, &fit, &result);
+    let square_law = fit.model;
+    Some(MosfetModel {
+        curves: curves
+            .iter()
+            .map(|(vgs, curve)| MosfetCurve {
+                model: square_law.clone(),
+                vgs: *vgs,
+                max_v: curve.last().map_or(0.0, |(v, _)| *v) * v_sign,
+                v_sign,
+                i_sign,
+            })
+            .collect(),
+        square_law,
+        polarity: v_sign,
+        quality: Some(quality),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use noisy_float::prelude::r64;
+
+    use crate::backend::{AD2Config, Backend, FetMode, Sim, SimConfig, TraceControl};
+    use crate::dut::BiasDrive;
+    use crate::model::mosfet::mosfet_model;
+
+    fn fit(
+        fet_mode: FetMode,
+        polarity: f64,
+        bias_levels: &[f64],
+    ) -> crate::model::mosfet::MosfetModel {
+        let sim = Sim::new(
+            SimConfig {
+                fet_mode,
+                samples: 2000,
+                ..SimConfig::default()
+            },
+            AD2Config::default(),
+        );
+        let traces = sim
+            .trace_3(
+                r64(polarity),
+                BiasDrive::Voltage,
+                bias_levels.iter().map(|b| r64(*b)).collect(),
+                &TraceControl::new(),
+            )
+            .unwrap();
+
+        mosfet_model(traces.iter().map(|t| (t.bias.raw(), &t.trace))).unwrap()
+    }
+
+    #[test]
+    pub fn mosfet_model_matches_simulated_enhancement_nfet() {
+        let model = fit(FetMode::Enhancement, 1.0, &[0.0, 1.25, 2.5, 3.75, 5.0]);
+
+        assert_relative_eq!(model.vth(), 1.5, max_relative = 0.01);
+        assert_relative_eq!(model.square_law.kp(), 0.02, max_relative = 0.01);
+        assert_relative_eq!(model.square_law.lambda(), 0.02, max_relative = 0.05);
+        let (vgs, rds_on) = model.rds_on().unwrap();
+        assert_relative_eq!(vgs, 5.0);
+        assert_relative_eq!(rds_on, 1.0 / (0.02 * 3.5), max_relative = 0.01);
+    }
+
+    #[test]
+    pub fn mosfet_model_matches_simulated_depletion_pfet() {
+        let model = fit(FetMode::Depletion, -1.0, &[0.0, 1.25, 2.5, 3.75, 5.0]);
+
+        assert_relative_eq!(model.polarity, -1.0);
+        assert_relative_eq!(model.vth(), 1.5, max_relative = 0.01);
+        assert_relative_eq!(model.square_law.kp(), 0.02, max_relative = 0.01);
+    }
+}
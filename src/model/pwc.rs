@@ -16,18 +16,29 @@ impl PieceWiseConstantFunction {
         points: &[(f64, f64)],
     ) -> PieceWiseConstantFunction {
         PieceWiseConstantFunction::new(min, max, buckets, min_bucket_population, |s, e| {
-            points.iter().cloned().filter_map(
-                move |(x, y)| {
-                    if x >= s && x < e {
-                        Some(y)
-                    } else {
-                        None
-                    }
-                },
-            )
+            bucket_points(points, s, e)
         })
     }
 
+    /// Like `from_points`, but takes the median of each bucket rather than the mean, so
+    /// that a glitch stays an outlier instead of dragging its bucket along
+    pub fn medians_from_points(
+        min: f64,
+        max: f64,
+        buckets: usize,
+        min_bucket_population: usize,
+        points: &[(f64, f64)],
+    ) -> PieceWiseConstantFunction {
+        PieceWiseConstantFunction::aggregated(
+            min,
+            max,
+            buckets,
+            min_bucket_population,
+            |s, e| bucket_points(points, s, e),
+            median,
+        )
+    }
+
     pub fn new<F, I>(
         min: f64,
         max: f64,
@@ -35,6 +46,21 @@ impl PieceWiseConstantFunction {
         min_bucket_population: usize,
         f: F,
     ) -> PieceWiseConstantFunction
+    where
+        F: Fn(f64, f64) -> I,
+        I: Iterator<Item = f64>,
+    {
+        PieceWiseConstantFunction::aggregated(min, max, buckets, min_bucket_population, f, mean)
+    }
+
+    fn aggregated<F, I>(
+        min: f64,
+        max: f64,
+        buckets: usize,
+        min_bucket_population: usize,
+        f: F,
+        aggregate: fn(&mut [f64]) -> f64,
+    ) -> PieceWiseConstantFunction
     where
         F: Fn(f64, f64) -> I,
         I: Iterator<Item = f64>,
@@ -49,9 +75,9 @@ impl PieceWiseConstantFunction {
             let start = min + span * (b as f64 / buckets as f64);
             let end = min + span * ((b + 1) as f64 / buckets as f64);
 
-            let vs: Vec<f64> = f(start, end).collect_vec();
-            if vs.len() >= min_bucket_population {
-                pwc[b] = vs.iter().sum::<f64>() / vs.len() as f64;
+            let mut vs: Vec<f64> = f(start, end).collect_vec();
+            if !vs.is_empty() && vs.len() >= min_bucket_population {
+                pwc[b] = aggregate(&mut vs);
             }
         }
 
@@ -78,6 +104,30 @@ impl PieceWiseConstantFunction {
     }
 }
 
+fn bucket_points(points: &[(f64, f64)], s: f64, e: f64) -> impl Iterator<Item = f64> + '_ {
+    points.iter().cloned().filter_map(move |(x, y)| {
+        if x >= s && x < e && y.is_finite() {
+            Some(y)
+        } else {
+            None
+        }
+    })
+}
+
+fn mean(vs: &mut [f64]) -> f64 {
+    vs.iter().sum::<f64>() / vs.len() as f64
+}
+
+fn median(vs: &mut [f64]) -> f64 {
+    vs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = vs.len();
+    if n % 2 == 1 {
+        vs[n / 2]
+    } else {
+        (vs[n / 2 - 1] + vs[n / 2]) / 2.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::model::pwc::PieceWiseConstantFunction;
@@ -102,9 +152,33 @@ mod tests {
         assert_eq!(h.iter().collect_vec(), vec![]);
     }
 
+    #[test]
+    fn non_finite_values_are_skipped() {
+        let h = PieceWiseConstantFunction::from_points(
+            0.0,
+            1.0,
+            1,
+            1,
+            &[(0.2, 12.0), (0.4, f64::NAN), (0.6, f64::INFINITY)],
+        );
+        assert_eq!(h.iter().collect_vec(), vec![(0.5, 12.0)]);
+    }
+
     #[test]
     fn bucket_value_is_average() {
         let h = PieceWiseConstantFunction::from_points(0.0, 1.0, 1, 1, &[(0.2, 12.0), (0.0, 23.0)]);
         assert_eq!(h.iter().collect_vec(), vec![(0.5, 17.5)]);
     }
+
+    #[test]
+    fn bucket_value_is_median() {
+        let h = PieceWiseConstantFunction::medians_from_points(
+            0.0,
+            1.0,
+            1,
+            1,
+            &[(0.2, 12.0), (0.0, 23.0), (0.4, 1000.0)],
+        );
+        assert_eq!(h.iter().collect_vec(), vec![(0.5, 23.0)]);
+    }
 }
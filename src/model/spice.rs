@@ -0,0 +1,197 @@
+//! SPICE `.model` cards of the fitted models, in the syntax ngspice and LTspice share
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use itertools::Itertools;
+
+use crate::error::TracerError;
+
+/// The nominal temperature of SPICE, °C
+pub const DEFAULT_TEMPERATURE: f64 = 27.0;
+
+const BOLTZMANN: f64 = 1.380_649e-23;
+const ELEMENTARY_CHARGE: f64 = 1.602_176_634e-19;
+const ZERO_CELSIUS: f64 = 273.15;
+
+/// kT/q at `temperature`, °C
+pub fn thermal_voltage(temperature: f64) -> f64 {
+    BOLTZMANN * (temperature + ZERO_CELSIUS) / ELEMENTARY_CHARGE
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelCard {
+    name: String,
+    kind: &'static str,
+    params: Vec<(&'static str, f64)>,
+    comments: Vec<String>,
+}
+
+impl ModelCard {
+    /// Characters SPICE would choke on in `name` are replaced with underscores
+    pub fn new(name: &str, kind: &'static str) -> Self {
+        let name = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        ModelCard {
+            name: if name.is_empty() {
+                "DUT".to_string()
+            } else {
+                name
+            },
+            kind,
+            params: Vec::new(),
+            comments: Vec::new(),
+        }
+    }
+
+    pub fn param(mut self, name: &'static str, value: f64) -> Self {
+        self.params.push((name, value));
+        self
+    }
+
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comments.push(comment.to_string());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// Fails with `TracerError::FitFailed` on a parameter that isn't finite, which SPICE
+    /// couldn't parse
+    pub fn check(&self) -> crate::Result<()> {
+        match self.params.iter().find(|(_, value)| !value.is_finite()) {
+            Some((name, value)) => Err(TracerError::FitFailed {
+                message: format!(
+                    "{} of the model is {}, there's no card to export",
+                    name, value
+                ),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> crate::Result<()> {
+        self.check()?;
+        std::fs::write(path, self.to_string()).map_err(|e| TracerError::io(path, e))?;
+        Ok(())
+    }
+}
+
+impl Display for ModelCard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for comment in &self.comments {
+            writeln!(f, "* {}", comment)?;
+        }
+        writeln!(
+            f,
+            ".model {} {}({})",
+            self.name,
+            self.kind,
+            self.params
+                .iter()
+                .map(|(name, value)| format!("{}={:.5e}", name, value))
+                .join(" ")
+        )
+    }
+}
+
+/// Diode card, the ideality factor being `n_vt` over the thermal voltage at `temperature`
+pub fn diode_card(name: &str, is: f64, n_vt: f64, rs: Option<f64>, temperature: f64) -> ModelCard {
+    let card = ModelCard::new(name, "D")
+        .param("IS", is)
+        .param("N", n_vt / thermal_voltage(temperature));
+    match rs {
+        Some(rs) => card.param("RS", rs),
+        None => card,
+    }
+    .param("TNOM", temperature)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backend::AD2Config;
+    use crate::dut::{CurrentBiasedDevice, CurrentBiasedDeviceType, Device};
+    use crate::dut::{TwoTerminalDevice, TwoTerminalDeviceType};
+    use crate::error::TracerError;
+    use crate::model::bjt::bjt_model;
+    use crate::model::diode::diode_model;
+    use crate::model::spice::{thermal_voltage, ModelCard};
+    use crate::model::{BiasedModel, IVModel};
+
+    #[test]
+    pub fn model_card_is_spice_syntax() {
+        let card = ModelCard::new("1N4148 (lot 2)", "D")
+            .param("IS", 2.52e-9)
+            .param("N", 1.83)
+            .comment("fitted");
+
+        assert_relative_eq!(thermal_voltage(27.0), 0.025_865, max_relative = 1e-4);
+        assert_eq!(
+            card.to_string(),
+            "* fitted\n.model 1N4148__lot_2_ D(IS=2.52000e-9 N=1.83000e0)\n"
+        );
+        assert!(card.check().is_ok());
+
+        let degenerate = card.param("RS", f64::NAN);
+        assert!(matches!(
+            degenerate
+                .check()
+                .err()
+                .unwrap()
+                .downcast_ref::<TracerError>(),
+            Some(TracerError::FitFailed { .. })
+        ));
+    }
+
+    #[test]
+    pub fn diode_card_1n4148() {
+        let device = TwoTerminalDevice::from_type(TwoTerminalDeviceType::Diode);
+        let trace = device.load_from_csv("res/1N4148.csv").unwrap();
+        let model = diode_model(&trace.trace, &AD2Config::default()).unwrap();
+
+        let card = model.spice_card("1N4148", 27.0).unwrap();
+        let n = card.get("N").unwrap();
+        assert_relative_eq!(n, model.n_vt() / thermal_voltage(27.0));
+        assert!(n > 1.5 && n < 2.0);
+        assert_relative_eq!(card.get("RS").unwrap(), model.rs().unwrap());
+        assert!(card.to_string().contains(".model 1N4148 D(IS="));
+
+        // The same curve measured hotter means a lower ideality factor
+        let hot = model.spice_card("1N4148", 50.0).unwrap();
+        assert!(hot.get("N").unwrap() < n);
+    }
+
+    #[test]
+    pub fn bjt_card_2n3906() {
+        let device = CurrentBiasedDevice::from_type(CurrentBiasedDeviceType::PNP);
+        let trace = device.load_from_csv("res/2N3906.csv.gz").unwrap();
+        let model = bjt_model(
+            trace
+                .traces
+                .iter()
+                .map(|(bias, trace)| (bias.raw(), &trace.trace)),
+            0.001,
+            &AD2Config::default(),
+        )
+        .unwrap();
+
+        let card = model.spice_card("2N3906", 27.0).unwrap();
+        assert!(card.to_string().contains(".model 2N3906 PNP(BF="));
+        assert!(card.get("BF").unwrap() > 100.0);
+        assert_relative_eq!(card.get("VAF").unwrap(), model.early_v());
+    }
+}
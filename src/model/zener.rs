@@ -0,0 +1,339 @@
+use std::fmt::Display;
+
+use itertools::Itertools;
+use nalgebra::*;
+
+use crate::backend::{AD2Config, RawTrace};
+use crate::model::curvefit::{linear_regression, FitQuality};
+use crate::model::diode::{diode_model, DiodeModel};
+use crate::model::pwc::PieceWiseConstantFunction;
+use crate::model::spice::ModelCard;
+use crate::model::{IVModel, Param};
+use crate::util::Engineering;
+
+const MIN_ZZ_SAMPLES: usize = 3;
+/// Voltage buckets the reverse part of a trace is averaged into
+const REVERSE_BUCKETS: usize = 500;
+
+/// Reverse breakdown parameters, all voltages and currents as positive magnitudes
+#[derive(Clone, Copy, Debug)]
+pub struct BreakdownModel {
+    /// Current `vz` is measured at
+    pub test_current: f64,
+    pub vz: f64,
+    /// Dynamic impedance at the test current
+    pub zz: f64,
+    /// Current at the voltage where the tangent at the test current crosses zero current
+    pub knee_current: f64,
+}
+
+impl BreakdownModel {
+    /// Voltage where the tangent at the test current crosses zero current
+    pub fn knee_v(&self) -> f64 {
+        self.vz - self.zz * self.test_current
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        vec![
+            Param::new("vz", self.vz, "V").at("iz", self.test_current),
+            Param::new("zz", self.zz, "Ω").at("iz", self.test_current),
+            Param::new("knee_current", self.knee_current, "A"),
+        ]
+    }
+}
+
+impl Display for BreakdownModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "V<sub>Z</sub>@{:.3}A\t{:.3}V",
+            Engineering(self.test_current),
+            Engineering(self.vz)
+        )?;
+        writeln!(f, "Z<sub>Z</sub>\t{:.3}Ω", Engineering(self.zz))?;
+        writeln!(f, "I<sub>ZK</sub>\t{:.3}A", Engineering(self.knee_current))?;
+        Ok(())
+    }
+}
+
+/// Why the breakdown couldn't be extracted from a trace
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoBreakdown {
+    /// The trace wasn't swept in reverse
+    NoReverseSweep,
+    /// The reverse current stays below the test current, reaching `max_current` at `max_v`
+    NotReached {
+        test_current: f64,
+        max_v: f64,
+        max_current: f64,
+    },
+    /// Too few points around the test current to get the dynamic impedance from
+    TooFewPoints,
+}
+
+impl Display for NoBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoBreakdown::NoReverseSweep => write!(f, "No reverse sweep"),
+            NoBreakdown::NotReached {
+                test_current,
+                max_v,
+                max_current,
+            } => write!(
+                f,
+                "Doesn't reach breakdown: {:.3}A at {:.3}V, below {:.3}A",
+                Engineering(*max_current),
+                Engineering(-max_v),
+                Engineering(*test_current)
+            ),
+            NoBreakdown::TooFewPoints => write!(f, "Too few points around the test current"),
+        }
+    }
+}
+
+/// Forward Shockley model and reverse breakdown, the forward model missing if the trace
+/// doesn't reach far enough forward and the breakdown with the reason it's missing
+#[derive(Clone, Debug)]
+pub struct ZenerModel {
+    pub forward: Option<DiodeModel>,
+    pub breakdown: Result<BreakdownModel, NoBreakdown>,
+}
+
+impl IVModel for ZenerModel {
+    fn min_v(&self) -> f64 {
+        match &self.breakdown {
+            Ok(breakdown) => -(breakdown.vz + breakdown.zz * breakdown.test_current),
+            Err(_) => 0.0,
+        }
+    }
+
+    fn max_v(&self) -> f64 {
+        match &self.forward {
+            Some(forward) => forward.max_v(),
+            None => 0.0,
+        }
+    }
+
+    /// The breakdown is drawn as its tangent at the test current
+    fn evaluate(&self, v: f64) -> f64 {
+        if v >= 0.0 {
+            self.forward
+                .as_ref()
+                .map_or(0.0, |forward| forward.evaluate(v))
+        } else {
+            self.breakdown.as_ref().map_or(0.0, |breakdown| {
+                (v + breakdown.knee_v()).min(0.0) / breakdown.zz
+            })
+        }
+    }
+
+    /// That of the forward fit, the breakdown being extracted rather than fitted
+    fn quality(&self) -> Option<&FitQuality> {
+        self.forward.as_ref().and_then(|forward| forward.quality())
+    }
+
+    fn params(&self) -> Vec<Param> {
+        let mut params = self
+            .forward
+            .as_ref()
+            .map_or_else(Vec::new, |forward| forward.params());
+        if let Ok(breakdown) = &self.breakdown {
+            params.extend(breakdown.params());
+        }
+        params
+    }
+
+    fn spice_card(&self, name: &str, temperature: f64) -> Option<ModelCard> {
+        let card = self.forward.as_ref()?.spice_card(name, temperature)?;
+        Some(match &self.breakdown {
+            Ok(breakdown) => card
+                .param("BV", breakdown.vz)
+                .param("IBV", breakdown.test_current),
+            Err(_) => card,
+        })
+    }
+}
+
+impl Display for ZenerModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(forward) = &self.forward {
+            write!(f, "{}", forward)?;
+        }
+        match &self.breakdown {
+            Ok(breakdown) => write!(f, "{}", breakdown)?,
+            Err(reason) => writeln!(f, "V<sub>Z</sub>\t{}", reason)?,
+        }
+        Ok(())
+    }
+}
+
+/// Linearly interpolates the voltage where a curve sorted by voltage, with current growing
+/// along it, reaches `current`
+fn voltage_at(curve: &[(f64, f64)], current: f64) -> Option<f64> {
+    let ix = curve.iter().position(|(_, i)| *i >= current)?;
+    if ix == 0 {
+        return Some(curve[0].0);
+    }
+    let (v0, i0) = curve[ix - 1];
+    let (v1, i1) = curve[ix];
+    Some(v0 + (v1 - v0) * (current - i0) / (i1 - i0))
+}
+
+fn current_at(curve: &[(f64, f64)], v: f64) -> Option<f64> {
+    let ix = curve.iter().position(|(cv, _)| *cv >= v)?;
+    if ix == 0 {
+        return Some(curve[0].1);
+    }
+    let (v0, i0) = curve[ix - 1];
+    let (v1, i1) = curve[ix];
+    Some(i0 + (i1 - i0) * (v - v0) / (v1 - v0))
+}
+
+/// Extracts the breakdown at `test_current` (a positive magnitude) from the reverse part
+/// of `trace`, over however far it was swept
+pub fn breakdown_model(trace: &RawTrace, test_current: f64) -> Result<BreakdownModel, NoBreakdown> {
+    let reverse = trace
+        .iter()
+        .filter(|(v, i)| *v < 0.0 && i.is_finite())
+        .map(|(v, i)| (-v, -i))
+        .collect_vec();
+    let (max_v, max_current) = reverse.iter().fold(
+        (0.0, f64::NEG_INFINITY),
+        |(max_v, max_current): (f64, f64), (v, i)| (max_v.max(*v), max_current.max(*i)),
+    );
+    if reverse.is_empty() || max_v <= 0.0 {
+        return Err(NoBreakdown::NoReverseSweep);
+    }
+
+    let curve = PieceWiseConstantFunction::from_points(0.0, max_v, REVERSE_BUCKETS, 1, &reverse)
+        .iter()
+        .collect_vec();
+
+    let vz = voltage_at(&curve, test_current).ok_or(NoBreakdown::NotReached {
+        test_current,
+        max_v,
+        max_current,
+    })?;
+
+    let near_test_current = curve
+        .iter()
+        .filter(|(_, i)| (*i - test_current).abs() <= test_current / 2.0)
+        .collect_vec();
+    if near_test_current.len() < MIN_ZZ_SAMPLES {
+        return Err(NoBreakdown::TooFewPoints);
+    }
+    let xs = MatrixMN::<f64, U2, Dynamic>::from_rows(&[
+        RowDVector::from_iterator(
+            near_test_current.len(),
+            near_test_current.iter().map(|(_, i)| *i),
+        ),
+        RowDVector::from_element(near_test_current.len(), 1.0),
+    ]);
+    let ys = DVector::from_iterator(
+        near_test_current.len(),
+        near_test_current.iter().map(|(v, _)| *v),
+    );
+    let zz = linear_regression(xs, ys).ok_or(NoBreakdown::TooFewPoints)?[(0, 0)];
+    if zz.is_nan() || zz <= 0.0 {
+        return Err(NoBreakdown::TooFewPoints);
+    }
+
+    let mut breakdown = BreakdownModel {
+        test_current,
+        vz,
+        zz,
+        knee_current: 0.0,
+    };
+    breakdown.knee_current = current_at(&curve, breakdown.knee_v())
+        .ok_or(NoBreakdown::TooFewPoints)?
+        .max(0.0);
+    Ok(breakdown)
+}
+
+pub fn zener_model(trace: &RawTrace, test_current: f64, config: &AD2Config) -> Option<ZenerModel> {
+    let model = ZenerModel {
+        forward: diode_model(trace, config),
+        breakdown: breakdown_model(trace, test_current),
+    };
+    if model.forward.is_none() && model.breakdown.is_err() {
+        None
+    } else {
+        Some(model)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backend::{AD2Config, Backend, Sim, SimConfig, SweepRange, TraceControl};
+    use crate::dut::{Device, TwoTerminalDevice, TwoTerminalDeviceType};
+    use crate::model::zener::{zener_model, NoBreakdown};
+
+    #[test]
+    pub fn zener_model_1n4728a_forward_only() {
+        let device = TwoTerminalDevice::from_type(TwoTerminalDeviceType::Zener);
+
+        for path in &["res/1N4728A-1.csv", "res/1N4728A-2.csv"] {
+            let trace = device.load_from_csv(path).unwrap();
+            let model = zener_model(&trace.trace, 0.005, &AD2Config::default()).unwrap();
+
+            assert!(model.forward.is_some());
+            assert_eq!(model.breakdown.unwrap_err(), NoBreakdown::NoReverseSweep);
+            assert!(model.to_string().contains("No reverse sweep"));
+        }
+    }
+
+    #[test]
+    pub fn zener_model_matches_simulated_breakdown() {
+        let sim = Sim::new(
+            SimConfig {
+                samples: 20000,
+                zener_vz: 0.8,
+                ..SimConfig::default()
+            },
+            AD2Config::default(),
+        );
+        let trace = sim
+            .trace_2(SweepRange::Bipolar, &TraceControl::new())
+            .unwrap();
+
+        let breakdown = zener_model(&trace, 0.004, &AD2Config::default())
+            .unwrap()
+            .breakdown
+            .unwrap();
+
+        // Exponential breakdown with n⋅Vt = 50mV behind 2Ω, Is = 10nA
+        let expected_vz = 0.8 + 0.05 * (0.004f64 / 1e-8).ln() + 2.0 * 0.004;
+        assert_relative_eq!(breakdown.vz, expected_vz, max_relative = 0.01);
+        assert_relative_eq!(breakdown.zz, 0.05 / 0.004 + 2.0, max_relative = 0.1);
+        assert!(breakdown.knee_current > 0.0 && breakdown.knee_current < 0.004);
+    }
+
+    #[test]
+    pub fn zener_model_reports_an_unreached_breakdown() {
+        let sim = Sim::new(
+            SimConfig {
+                samples: 20000,
+                zener_vz: 0.8,
+                ..SimConfig::default()
+            },
+            AD2Config::default(),
+        );
+        let trace = sim
+            .trace_2(SweepRange::Bipolar, &TraceControl::new())
+            .unwrap();
+
+        // The sweep bottoms out at -2.2V, driving 7mA through the shunt and the zener
+        match zener_model(&trace, 0.01, &AD2Config::default())
+            .unwrap()
+            .breakdown
+        {
+            Err(NoBreakdown::NotReached {
+                max_v, max_current, ..
+            }) => {
+                assert_relative_eq!(max_v, 1.487, max_relative = 0.01);
+                assert_relative_eq!(max_current, 0.007_06, max_relative = 0.01);
+            }
+            other => panic!("Expected the breakdown not to be reached, got {:?}", other),
+        }
+    }
+}
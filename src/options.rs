@@ -1,20 +1,388 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use log::LevelFilter;
+use noisy_float::prelude::r64;
 use simplelog::{Config, TerminalMode};
 use structopt::StructOpt;
 
-use crate::backend::Backend;
-use crate::backend::AD2;
-use crate::dut::trace::{GuiTrace, TwoTerminalGuiTrace};
-use crate::dut::Device;
-use crate::dut::TwoTerminalDevice;
+use crate::backend::{AD2Config, DeviceSelection, DeviceSelector, AD2};
+use crate::backend::{Backend, TraceControl};
+use crate::backend::{Replay, Sim, SimConfig, SweepRange};
+use crate::dut::batch::SummaryFormat;
+use crate::dut::matching::{Grouping, Metric};
+use crate::dut::meta::TraceMetadata;
+use crate::dut::trace::{GuiTrace, ShareableTrace, Trace};
+use crate::dut::{detect_device_type, Device, SomeDevice, SomeDeviceType};
+use crate::dut::{CurrentBiasedDevice, CurrentBiasedDeviceConfig};
+use crate::dut::{TwoTerminalDevice, TwoTerminalDeviceConfig, TwoTerminalDeviceType};
+use crate::dut::{VoltageBiasedDevice, VoltageBiasedDeviceConfig};
+use crate::error::TracerError;
+use crate::gui::plot::Plot;
+use crate::model::spice::DEFAULT_TEMPERATURE;
 use crate::Result;
 
 pub trait Opt {
     fn initialize_logging(&self) -> Result<()>;
 }
 
+#[derive(StructOpt, Debug)]
+pub struct AD2ConfigOpt {
+    #[structopt(
+        long = "ad2-config",
+        parse(from_os_str),
+        help = "TOML file with the AD2 acquisition parameters"
+    )]
+    config_file: Option<PathBuf>,
+    #[structopt(long, help = "DUT current limit, mA")]
+    diode_current_limit_ma: Option<f64>,
+    #[structopt(long, help = "current shunt resistance, Ω")]
+    current_shunt_ohms: Option<f64>,
+    #[structopt(long, help = "bias current limiting resistance, Ω")]
+    bias_limiter_ohms: Option<f64>,
+    #[structopt(long, help = "two-terminal sweep duration, s")]
+    sampling_time: Option<f64>,
+    #[structopt(
+        long,
+        help = "time to let the input offsets settle before capturing, s"
+    )]
+    capture_offset_stabilization_time: Option<f64>,
+    #[structopt(long, help = "per-bias-level sweep duration, s")]
+    bias_level_sampling_time: Option<f64>,
+    #[structopt(long, help = "sampling frequency, Hz")]
+    sampling_frequency: Option<f64>,
+    #[structopt(long, help = "number of sweep cycles to record")]
+    cycles_to_sample: Option<u32>,
+    #[structopt(long, help = "number of sweep cycles to skip before recording")]
+    cycles_to_skip: Option<u32>,
+    #[structopt(long, allow_hyphen_values = true, help = "highest sweep voltage, V")]
+    max_v: Option<f64>,
+    #[structopt(long, allow_hyphen_values = true, help = "lowest sweep voltage, V")]
+    min_v: Option<f64>,
+}
+
+impl AD2ConfigOpt {
+    pub fn config(&self) -> Result<AD2Config> {
+        let mut config = match &self.config_file {
+            Some(path) => AD2Config::load(path)?,
+            None => AD2Config::default(),
+        };
+
+        macro_rules! override_field {
+            ($($field:ident),*) => {
+                $(
+                    if let Some(value) = self.$field {
+                        config.$field = value;
+                    }
+                )*
+            };
+        }
+        override_field!(
+            diode_current_limit_ma,
+            current_shunt_ohms,
+            bias_limiter_ohms,
+            sampling_time,
+            capture_offset_stabilization_time,
+            bias_level_sampling_time,
+            sampling_frequency,
+            cycles_to_sample,
+            cycles_to_skip,
+            max_v,
+            min_v
+        );
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct DeviceSelectionOpt {
+    #[structopt(
+        long,
+        help = "serial number or #index of the device to use, defaults to the last one picked in the GUI"
+    )]
+    device: Option<DeviceSelector>,
+    #[structopt(long, help = "index of the device config to use")]
+    device_config: Option<usize>,
+}
+
+impl DeviceSelectionOpt {
+    pub fn selection(&self) -> DeviceSelection {
+        let remembered = DeviceSelection::remembered();
+        match &self.device {
+            Some(device) => DeviceSelection {
+                device: Some(device.clone()),
+                config: self.device_config.unwrap_or(0),
+            },
+            None => DeviceSelection {
+                config: self.device_config.unwrap_or(remembered.config),
+                ..remembered
+            },
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TemperatureOpt {
+    #[structopt(
+        long,
+        allow_hyphen_values = true,
+        help = "temperature the device is traced at, °C, for the SPICE export [default: 27]"
+    )]
+    temperature: Option<f64>,
+}
+
+impl TemperatureOpt {
+    pub fn temperature(&self) -> f64 {
+        self.temperature.unwrap_or(DEFAULT_TEMPERATURE)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PlotOpt {
+    #[structopt(
+        short = "f",
+        long = "file",
+        parse(from_os_str),
+        help = "CSV file to plot"
+    )]
+    file: PathBuf,
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        help = "image file to write, the format follows the .png, .svg or .pdf extension"
+    )]
+    output: PathBuf,
+    #[structopt(long, default_value = "1200", help = "image width, px or pt")]
+    width: f64,
+    #[structopt(long, default_value = "800", help = "image height, px or pt")]
+    height: f64,
+    #[structopt(long, default_value = "2", help = "voltage at the edge of the plot, V")]
+    v_zoom: f64,
+    #[structopt(
+        long,
+        default_value = "0.05",
+        help = "current at the edge of the plot, A"
+    )]
+    i_zoom: f64,
+    #[structopt(
+        long,
+        help = "title above the plot, the part number or file name and the device type by default"
+    )]
+    title: Option<String>,
+}
+
+impl PlotOpt {
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    pub fn plot(&self, trace: &dyn GuiTrace) -> Plot {
+        let title = self.title.clone().unwrap_or_else(|| {
+            let metadata = trace.metadata();
+            let name = metadata.part_number.unwrap_or_else(|| {
+                self.file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+            match metadata.device_type {
+                Some(device_type) => format!("{} ({})", name, device_type),
+                None => name,
+            }
+        });
+        Plot::new(self.width, self.height, self.v_zoom, self.i_zoom)
+            .with_title(Some(title))
+            .with_legend(true)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct AnalyzeOpt {
+    #[structopt(
+        required = true,
+        help = "directories, files or glob patterns of the .csv and .csv.gz traces to analyze"
+    )]
+    inputs: Vec<String>,
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        help = "file to write the summary to, stdout by default"
+    )]
+    output: Option<PathBuf>,
+    #[structopt(long, default_value = "csv", help = "summary format: csv or json")]
+    format: SummaryFormat,
+}
+
+impl AnalyzeOpt {
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    pub fn format(&self) -> SummaryFormat {
+        self.format
+    }
+}
+
+/// A range of values given as `min:max`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Span {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl FromStr for Span {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad_span =
+            || TracerError::invalid_argument(&format!("Bad range {}, expected min:max", s));
+        let (min, max) = s
+            .split(':')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .ok()
+            .and_then(|values| match values.as_slice() {
+                [min, max] => Some((*min, *max)),
+                _ => None,
+            })
+            .ok_or_else(bad_span)?;
+        Ok(Span { min, max })
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct MatchOpt {
+    #[structopt(
+        required = true,
+        help = "directories, files or glob patterns of the .csv and .csv.gz traces of the lot"
+    )]
+    inputs: Vec<String>,
+    #[structopt(
+        long,
+        default_value = "pairs",
+        help = "pairs, quads or bins, formed greedily: a lot may allow more groups, or tighter \
+                ones, than proposed"
+    )]
+    group: Grouping,
+    #[structopt(
+        long,
+        help = "largest mismatch within a group: a relative difference for parameters and \
+                currents, V for voltages"
+    )]
+    tolerance: f64,
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "match by these fitted parameters, such as is,n_vt or hfe"
+    )]
+    params: Vec<String>,
+    #[structopt(
+        long,
+        allow_hyphen_values = true,
+        help = "match by the model currents over this voltage range, V, as min:max"
+    )]
+    v_range: Option<Span>,
+    #[structopt(
+        long,
+        allow_hyphen_values = true,
+        help = "match by the model voltages over this current range, A, as min:max"
+    )]
+    i_range: Option<Span>,
+    #[structopt(
+        long,
+        default_value = "8",
+        help = "points the curves are compared at across the range"
+    )]
+    points: usize,
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        help = "file to write the grouping to, stdout by default"
+    )]
+    output: Option<PathBuf>,
+    #[structopt(long, default_value = "csv", help = "report format: csv or json")]
+    format: SummaryFormat,
+}
+
+impl MatchOpt {
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    pub fn grouping(&self) -> Grouping {
+        self.group
+    }
+
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    /// The metric of the one of `--params`, `--v-range` and `--i-range` given
+    pub fn metric(&self) -> Result<Metric> {
+        match (self.params.is_empty(), self.v_range, self.i_range) {
+            (false, None, None) => Ok(Metric::Params(self.params.clone())),
+            (true, Some(span), None) => Ok(Metric::Current {
+                min_v: span.min,
+                max_v: span.max,
+                points: self.points,
+            }),
+            (true, None, Some(span)) => Ok(Metric::Voltage {
+                min_i: span.min,
+                max_i: span.max,
+                points: self.points,
+            }),
+            _ => Err(TracerError::invalid_argument(
+                "Match by exactly one of --params, --v-range and --i-range",
+            )
+            .into()),
+        }
+    }
+
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    pub fn format(&self) -> SummaryFormat {
+        self.format
+    }
+}
+
+/// How the CLI prints the model report
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(TracerError::invalid_argument(&format!(
+                "Unknown report format {}, expected text or json",
+                s
+            ))
+            .into()),
+        }
+    }
+}
+
 #[derive(StructOpt, Debug)]
 enum CliBackendOption {
     #[structopt(
@@ -22,7 +390,10 @@ enum CliBackendOption {
         about = "pick the first device through the Digilent™ WaveForms™ API"
     )]
     DWF,
-    #[structopt(name = "csv", about = "analyze a CSV file")]
+    #[structopt(
+        name = "csv",
+        about = "analyze a CSV file, the device type is detected from its contents"
+    )]
     Csv {
         #[structopt(
             short = "f",
@@ -32,6 +403,49 @@ enum CliBackendOption {
         )]
         file: PathBuf,
     },
+    #[structopt(name = "sim", about = "trace a simulated device, no hardware required")]
+    Sim(SimConfig),
+    #[structopt(name = "replay", about = "replay a previously saved trace")]
+    Replay {
+        #[structopt(
+            short = "f",
+            long = "file",
+            parse(from_os_str),
+            help = "CSV file to replay"
+        )]
+        file: PathBuf,
+    },
+    #[structopt(name = "list-devices", about = "list the available Digilent™ devices")]
+    ListDevices,
+    #[structopt(
+        name = "plot",
+        about = "render a saved trace to a PNG, SVG or PDF file"
+    )]
+    Plot(PlotOpt),
+    #[structopt(
+        name = "analyze",
+        about = "fit the models of many saved traces and summarize their parameters in a table"
+    )]
+    Analyze(AnalyzeOpt),
+    #[structopt(
+        name = "match",
+        about = "propose matched pairs, quads or bins of parts from the traces of a lot"
+    )]
+    Match(MatchOpt),
+    #[structopt(
+        name = "calibrate",
+        about = "calibrate the device with an open and a shorted or known resistor fixture, \
+                 for positive two-terminal and for three-terminal sweeps"
+    )]
+    Calibrate {
+        #[structopt(
+            long,
+            default_value = "0",
+            help = "resistance of the closed fixture, Ω, 0 for a short, which calibrates \
+                    the offsets and the voltage gain but not the shunt resistance"
+        )]
+        fixture_ohms: f64,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -39,6 +453,89 @@ enum CliBackendOption {
 pub struct CliOpt {
     #[structopt(subcommand)]
     device: Option<CliBackendOption>,
+    #[structopt(flatten)]
+    ad2: AD2ConfigOpt,
+    #[structopt(flatten)]
+    device_selection: DeviceSelectionOpt,
+    #[structopt(
+        short = "t",
+        long = "type",
+        help = "device type: PN, Zener, NPN, PNP, NEFET, PEFET, NDFET or PDFET, \
+                detected for CSV files and PN otherwise by default"
+    )]
+    device_type: Option<SomeDeviceType>,
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        help = "save the trace to this CSV file, gzipped if it ends in .gz"
+    )]
+    output: Option<PathBuf>,
+    #[structopt(long, help = "lowest BJT base current, A [default: 10e-6]")]
+    min_bias_current: Option<f64>,
+    #[structopt(long, help = "highest BJT base current, A [default: 50e-6]")]
+    max_bias_current: Option<f64>,
+    #[structopt(
+        long,
+        help = "collector current to measure VCE(sat) at, A [default: 0.001]"
+    )]
+    sat_current: Option<f64>,
+    #[structopt(long, help = "lowest FET gate voltage magnitude, V [default: 0]")]
+    min_bias_voltage: Option<f64>,
+    #[structopt(long, help = "highest FET gate voltage magnitude, V [default: 5]")]
+    max_bias_voltage: Option<f64>,
+    #[structopt(
+        long,
+        help = "two-terminal sweep range: positive, negative or bipolar, \
+                bipolar for zeners and positive otherwise by default"
+    )]
+    sweep: Option<SweepRange>,
+    #[structopt(
+        long,
+        default_value = "2",
+        help = "reverse voltage to report the leakage current at, V"
+    )]
+    leakage_v: f64,
+    #[structopt(
+        long,
+        help = "trace a zener and measure its breakdown voltage at this reverse current, A"
+    )]
+    zener_test_current: Option<f64>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "write the fitted model to this file as a SPICE .model card"
+    )]
+    spice: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "trace parts of this part number one after another, saving each to the session \
+                folder under an incrementing serial"
+    )]
+    session: Option<String>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        default_value = ".",
+        help = "folder the traces of a session are saved to"
+    )]
+    session_dir: PathBuf,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "file to export the session table to, JSON if it ends in .json and CSV otherwise \
+                [default: <part number>-session.csv in the session folder]"
+    )]
+    session_summary: Option<PathBuf>,
+    #[structopt(flatten)]
+    temperature: TemperatureOpt,
+    #[structopt(
+        long,
+        default_value = "text",
+        help = "report format: text, or json with the device type, bias levels, \
+                fitted parameters and trace statistics"
+    )]
+    format: ReportFormat,
     #[structopt(
         short,
         long,
@@ -56,17 +553,167 @@ impl Opt for CliOpt {
 }
 
 impl CliOpt {
+    pub fn list_devices(&self) -> bool {
+        matches!(self.device, Some(CliBackendOption::ListDevices))
+    }
+
+    pub fn plot_opt(&self) -> Option<&PlotOpt> {
+        match &self.device {
+            Some(CliBackendOption::Plot(plot)) => Some(plot),
+            _ => None,
+        }
+    }
+
+    pub fn analyze_opt(&self) -> Option<&AnalyzeOpt> {
+        match &self.device {
+            Some(CliBackendOption::Analyze(analyze)) => Some(analyze),
+            _ => None,
+        }
+    }
+
+    pub fn match_opt(&self) -> Option<&MatchOpt> {
+        match &self.device {
+            Some(CliBackendOption::Match(match_opt)) => Some(match_opt),
+            _ => None,
+        }
+    }
+
+    pub fn calibration_fixture_ohms(&self) -> Option<f64> {
+        match self.device {
+            Some(CliBackendOption::Calibrate { fixture_ohms }) => Some(fixture_ohms),
+            _ => None,
+        }
+    }
+
+    pub fn ad2(&self) -> Result<AD2> {
+        AD2::new(&self.device_selection.selection(), self.ad2.config()?)
+    }
+
+    pub fn spice_path(&self) -> Option<&Path> {
+        self.spice.as_deref()
+    }
+
+    pub fn output_path(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature.temperature()
+    }
+
+    pub fn format(&self) -> ReportFormat {
+        self.format
+    }
+
+    /// Part number of the capture session, if one was asked for
+    pub fn session_part_number(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    pub fn session_dir(&self) -> &Path {
+        &self.session_dir
+    }
+
+    pub fn session_summary_path(&self) -> Option<&Path> {
+        self.session_summary.as_deref()
+    }
+
+    /// A zener if a test current is given, `device_type` otherwise
+    fn two_terminal_device(&self, device_type: TwoTerminalDeviceType) -> TwoTerminalDevice {
+        let device_type = match self.zener_test_current {
+            Some(_) => TwoTerminalDeviceType::Zener,
+            None => device_type,
+        };
+        let mut device = TwoTerminalDevice::from_type(device_type);
+        let config = device.config();
+        device.set_config(&TwoTerminalDeviceConfig {
+            sweep_range: self.sweep.unwrap_or(config.sweep_range),
+            leakage_v: self.leakage_v,
+            zener_test_current: self.zener_test_current.or(config.zener_test_current),
+        });
+        device
+    }
+
+    /// A device of `device_type` configured by the command line options
+    fn configured_device(&self, device_type: SomeDeviceType) -> SomeDevice {
+        match device_type {
+            SomeDeviceType::TwoTerminal(device_type) => {
+                SomeDevice::TwoTerminal(self.two_terminal_device(device_type))
+            }
+            SomeDeviceType::CurrentBiased(device_type) => {
+                let mut device = CurrentBiasedDevice::from_type(device_type);
+                let config = device.config();
+                device.set_config(&CurrentBiasedDeviceConfig {
+                    min_bias_current: self.min_bias_current.map_or(config.min_bias_current, r64),
+                    max_bias_current: self.max_bias_current.map_or(config.max_bias_current, r64),
+                    sat_current: self.sat_current.unwrap_or(config.sat_current),
+                });
+                SomeDevice::CurrentBiased(device)
+            }
+            SomeDeviceType::VoltageBiased(device_type) => {
+                let mut device = VoltageBiasedDevice::from_type(device_type);
+                let config = device.config();
+                device.set_config(&VoltageBiasedDeviceConfig {
+                    min_bias_voltage: self.min_bias_voltage.map_or(config.min_bias_voltage, r64),
+                    max_bias_voltage: self.max_bias_voltage.map_or(config.max_bias_voltage, r64),
+                    ..config
+                });
+                SomeDevice::VoltageBiased(device)
+            }
+        }
+    }
+
+    /// Loads a trace of the device type given, or else detected in the file
+    pub fn load_from_csv(&self, path: &Path) -> Result<Box<dyn GuiTrace>> {
+        let device_type = match self.device_type {
+            Some(device_type) => device_type,
+            None => {
+                let detection = detect_device_type(path)?;
+                info!("Detected a {} trace", detection.device_type);
+                detection.device_type
+            }
+        };
+        let mut trace = self
+            .configured_device(device_type)
+            .load_from_csv(path)?
+            .as_gui_trace();
+        let metadata = trace.metadata();
+        if metadata.device_type.is_none() {
+            trace.set_metadata(TraceMetadata {
+                device_type: Some(device_type),
+                ..metadata
+            });
+        }
+        Ok(trace)
+    }
+
     pub fn trace(&self) -> Result<Box<dyn GuiTrace>> {
-        Ok(
-            match &self.device.as_ref().unwrap_or(&CliBackendOption::DWF) {
-                CliBackendOption::DWF => Box::new(TwoTerminalGuiTrace::from(
-                    TwoTerminalDevice::Diode.trace(&AD2::new()?)?,
-                )),
-                CliBackendOption::Csv { file } => Box::new(TwoTerminalGuiTrace::from(
-                    TwoTerminalDevice::Diode.load_from_csv(file.as_path())?,
-                )),
-            },
-        )
+        let device = self.configured_device(
+            self.device_type
+                .unwrap_or(SomeDeviceType::TwoTerminal(TwoTerminalDeviceType::Diode)),
+        );
+        let control = TraceControl::with_progress(|progress| debug!("Tracing: {}", progress));
+        let trace = match &self.device.as_ref().unwrap_or(&CliBackendOption::DWF) {
+            CliBackendOption::DWF => device.trace(&self.ad2()?, &control)?,
+            CliBackendOption::Csv { file } => return self.load_from_csv(file),
+            CliBackendOption::Sim(config) => {
+                device.trace(&Sim::new(config.clone(), self.ad2.config()?), &control)?
+            }
+            CliBackendOption::Replay { file } => {
+                device.trace(&Replay::from_csv(file)?, &control)?
+            }
+            CliBackendOption::ListDevices
+            | CliBackendOption::Plot(_)
+            | CliBackendOption::Analyze(_)
+            | CliBackendOption::Match(_)
+            | CliBackendOption::Calibrate { .. } => {
+                return Err(TracerError::invalid_argument(
+                    "this subcommand doesn't produce a trace",
+                )
+                .into())
+            }
+        };
+        Ok(trace.as_gui_trace())
     }
 }
 
@@ -77,6 +724,18 @@ enum GuiBackendOption {
         about = "pick the first device through the Digilent™ WaveForms™ API"
     )]
     DWF,
+    #[structopt(name = "sim", about = "trace a simulated device, no hardware required")]
+    Sim(SimConfig),
+    #[structopt(name = "replay", about = "replay a previously saved trace")]
+    Replay {
+        #[structopt(
+            short = "f",
+            long = "file",
+            parse(from_os_str),
+            help = "CSV file to replay"
+        )]
+        file: PathBuf,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -84,6 +743,10 @@ enum GuiBackendOption {
 pub struct GuiOpt {
     #[structopt(subcommand)]
     device: Option<GuiBackendOption>,
+    #[structopt(flatten)]
+    ad2: AD2ConfigOpt,
+    #[structopt(flatten)]
+    device_selection: DeviceSelectionOpt,
     #[structopt(
         short,
         long,
@@ -91,6 +754,14 @@ pub struct GuiOpt {
         help = "off, error, warn, info, debug or trace"
     )]
     log_level: LevelFilter,
+    #[structopt(
+        long,
+        default_value = "0.99",
+        help = "R² below which a model fit is flagged as poor"
+    )]
+    min_r_squared: f64,
+    #[structopt(flatten)]
+    temperature: TemperatureOpt,
 }
 
 impl Opt for GuiOpt {
@@ -101,9 +772,33 @@ impl Opt for GuiOpt {
 }
 
 impl GuiOpt {
-    pub fn device(&self) -> Result<Box<dyn Backend>> {
+    pub fn ad2_config(&self) -> Result<AD2Config> {
+        self.ad2.config()
+    }
+
+    pub fn device_selection(&self) -> DeviceSelection {
+        self.device_selection.selection()
+    }
+
+    pub fn min_r_squared(&self) -> f64 {
+        self.min_r_squared
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature.temperature()
+    }
+
+    pub fn device(
+        &self,
+        device_selection: &DeviceSelection,
+        ad2_config: &AD2Config,
+    ) -> Result<Box<dyn Backend>> {
         match &self.device.as_ref().unwrap_or(&GuiBackendOption::DWF) {
-            GuiBackendOption::DWF => Ok(Box::new(AD2::new()?)),
+            GuiBackendOption::DWF => Ok(Box::new(AD2::new(device_selection, ad2_config.clone())?)),
+            GuiBackendOption::Sim(config) => {
+                Ok(Box::new(Sim::new(config.clone(), ad2_config.clone())))
+            }
+            GuiBackendOption::Replay { file } => Ok(Box::new(Replay::from_csv(file)?)),
         }
     }
 }
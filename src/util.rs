@@ -1,12 +1,42 @@
+use crate::error::TracerError;
 use nalgebra::RealField;
 use num_traits::ToPrimitive;
 use std::fmt::Display;
 use std::fmt::Error;
 use std::fmt::Formatter;
 use std::fmt::Write;
+use std::path::PathBuf;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Path to a file in the per-user configuration directory, creating the directory if needed
+pub fn config_path(file_name: &str) -> crate::Result<PathBuf> {
+    let dir = dirs::config_dir()
+        .ok_or(TracerError::NoConfigDir)?
+        .join("ks-curve-tracer");
+    std::fs::create_dir_all(&dir).map_err(|e| TracerError::io(&dir, e))?;
+    Ok(dir.join(file_name))
+}
+
+/// Pango markup as plain text, with the tags dropped and the entities unescaped
+pub fn strip_markup(markup: &str) -> String {
+    let mut text = String::with_capacity(markup.len());
+    let mut in_tag = false;
+    for c in markup.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
 pub struct Engineering<N: RealField>(pub N);
 
 impl<N: RealField + ToPrimitive> Display for Engineering<N> {